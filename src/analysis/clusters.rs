@@ -0,0 +1,354 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::random::SplitMix64;
+use crate::embeddings::encoder::VECTOR_SIZE;
+use crate::embeddings::flat::{self, normalize};
+use crate::vector_store::sqlite::StoredBlock;
+
+/// Options of a clustering job.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ClusterOptions {
+    /// The number of clusters. Defaults to `sqrt(blocks / 2)`.
+    pub k: Option<usize>,
+
+    /// The maximum number of k-means iterations.
+    pub iterations: usize,
+
+    /// The number of function names each cluster is labelled with.
+    pub labels: usize,
+
+    /// The seed of the k-means++ initialisation, so runs are reproducible.
+    pub seed: u64,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> Self {
+        ClusterOptions {
+            k: None,
+            iterations: 50,
+            labels: 3,
+            seed: 0,
+        }
+    }
+}
+
+/// A topic of a project: a cluster of semantically similar blocks.
+#[derive(Serialize, Debug, Clone)]
+pub struct Cluster {
+    /// The id of the cluster. Clusters are numbered from largest to smallest.
+    pub id: usize,
+
+    /// The number of blocks in the cluster.
+    pub size: usize,
+
+    /// The names of the functions (or classes) closest to the cluster's centroid.
+    pub labels: Vec<String>,
+}
+
+/// The result of clustering a project's blocks.
+pub struct Clustering {
+    pub clusters: Vec<Cluster>,
+
+    /// The cluster of each block, by row id.
+    pub assignments: Vec<(i64, usize)>,
+}
+
+/// Groups blocks into topics with spherical k-means (k-means on normalised vectors, by cosine
+/// similarity), initialised with k-means++.
+///
+/// # Arguments
+///
+/// * `blocks` - The blocks of a project, each with its whole-block vector.
+/// * `options` - The number of clusters and iterations.
+///
+/// # Returns
+///
+/// The non-empty clusters, largest first, and the cluster of each block.
+pub fn cluster_blocks(
+    blocks: Vec<(StoredBlock, [f32; VECTOR_SIZE])>,
+    options: &ClusterOptions,
+) -> Clustering {
+    if blocks.is_empty() {
+        return Clustering {
+            clusters: Vec::new(),
+            assignments: Vec::new(),
+        };
+    }
+
+    let points: Vec<Vec<f32>> = blocks
+        .par_iter()
+        .map(|(_, vector)| normalize(vector))
+        .collect();
+
+    let k = options
+        .k
+        .unwrap_or_else(|| ((points.len() as f32 / 2.0).sqrt().round() as usize).max(1))
+        .clamp(1, points.len());
+
+    let mut centroids = initial_centroids(&points, k, options.seed);
+    let mut assignments = assign(&points, &centroids);
+
+    for _ in 0..options.iterations {
+        centroids = update_centroids(&points, &assignments, &centroids);
+
+        let next = assign(&points, &centroids);
+        let converged = next.iter().zip(&assignments).all(|(a, b)| a.0 == b.0);
+
+        assignments = next;
+        if converged {
+            break;
+        }
+    }
+
+    // Number the non-empty clusters from largest to smallest.
+    let mut sizes = vec![0usize; k];
+    for (cluster, _) in &assignments {
+        sizes[*cluster] += 1;
+    }
+
+    let mut order: Vec<usize> = (0..k).filter(|cluster| sizes[*cluster] > 0).collect();
+    order.sort_by(|a, b| sizes[*b].cmp(&sizes[*a]).then(a.cmp(b)));
+
+    let mut ids = vec![usize::MAX; k];
+    for (id, cluster) in order.iter().enumerate() {
+        ids[*cluster] = id;
+    }
+
+    let clusters = order
+        .iter()
+        .enumerate()
+        .map(|(id, cluster)| {
+            let mut members: Vec<(usize, f32)> = assignments
+                .iter()
+                .enumerate()
+                .filter(|(_, (assigned, _))| assigned == cluster)
+                .map(|(row, (_, similarity))| (row, *similarity))
+                .collect();
+            members.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+            let mut labels: Vec<String> = Vec::new();
+            for (row, _) in members {
+                let block = &blocks[row].0.block;
+                let Some(name) = block.function_name.as_ref().or(block.class_name.as_ref()) else {
+                    continue;
+                };
+
+                if !labels.contains(name) {
+                    labels.push(name.to_owned());
+                }
+                if labels.len() >= options.labels {
+                    break;
+                }
+            }
+
+            Cluster {
+                id,
+                size: sizes[*cluster],
+                labels,
+            }
+        })
+        .collect();
+
+    Clustering {
+        clusters,
+        assignments: blocks
+            .iter()
+            .zip(assignments)
+            .map(|((block, _), (cluster, _))| (block.id, ids[cluster]))
+            .collect(),
+    }
+}
+
+/// Picks `k` initial centroids with k-means++: each next centroid is sampled with a
+/// probability proportional to its squared distance from the closest centroid so far.
+fn initial_centroids(points: &[Vec<f32>], k: usize, seed: u64) -> Vec<Vec<f32>> {
+    let mut random = SplitMix64::new(seed);
+    let mut centroids = vec![points[random.below(points.len())].clone()];
+
+    // The squared distance of every point to its closest centroid. For unit vectors, that is
+    // `2 - 2 * cosine similarity`.
+    let mut distances: Vec<f32> = points
+        .par_iter()
+        .map(|point| (2.0 - 2.0 * flat::dot(point, &centroids[0])).max(0.0))
+        .collect();
+
+    while centroids.len() < k {
+        let total: f32 = distances.iter().sum();
+
+        let next = if total <= 0.0 {
+            random.below(points.len())
+        } else {
+            let mut target = random.unit() * total;
+            distances
+                .iter()
+                .position(|distance| {
+                    target -= distance;
+                    target <= 0.0
+                })
+                .unwrap_or(points.len() - 1)
+        };
+
+        let centroid = points[next].clone();
+        distances
+            .par_iter_mut()
+            .zip(points.par_iter())
+            .for_each(|(distance, point)| {
+                *distance = distance.min((2.0 - 2.0 * flat::dot(point, &centroid)).max(0.0));
+            });
+        centroids.push(centroid);
+    }
+
+    centroids
+}
+
+/// Assigns every point to its most similar centroid.
+///
+/// # Returns
+///
+/// The cluster of each point and its similarity to the cluster's centroid.
+fn assign(points: &[Vec<f32>], centroids: &[Vec<f32>]) -> Vec<(usize, f32)> {
+    points
+        .par_iter()
+        .map(|point| {
+            centroids
+                .iter()
+                .enumerate()
+                .map(|(cluster, centroid)| (cluster, flat::dot(point, centroid)))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap()
+        })
+        .collect()
+}
+
+/// Moves every centroid to the normalised mean of its points. Centroids without points stay put.
+fn update_centroids(
+    points: &[Vec<f32>],
+    assignments: &[(usize, f32)],
+    centroids: &[Vec<f32>],
+) -> Vec<Vec<f32>> {
+    let mut sums = vec![vec![0.0f32; VECTOR_SIZE]; centroids.len()];
+    let mut counts = vec![0usize; centroids.len()];
+
+    for (point, (cluster, _)) in points.iter().zip(assignments) {
+        counts[*cluster] += 1;
+        for (sum, value) in sums[*cluster].iter_mut().zip(point) {
+            *sum += value;
+        }
+    }
+
+    sums.into_iter()
+        .zip(counts)
+        .zip(centroids)
+        .map(|((sum, count), centroid)| {
+            if count == 0 {
+                centroid.clone()
+            } else {
+                normalize(&sum)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A vector along axis `axis`, nudged towards axis `nudge` by `amount`.
+    fn vector(axis: usize, nudge: usize, amount: f32) -> [f32; VECTOR_SIZE] {
+        let mut vector = [0.0; VECTOR_SIZE];
+        vector[axis] = 1.0;
+        vector[nudge] += amount;
+        vector
+    }
+
+    /// Four blocks around axis 0 and two around axis 1. `a0` and `b0` lie on their axis.
+    fn two_groups() -> Vec<(StoredBlock, [f32; VECTOR_SIZE])> {
+        let mut blocks = Vec::new();
+        for i in 0..4 {
+            let name = format!("a{}", i);
+            let amount = if i == 0 { 0.0 } else { 0.01 };
+            blocks.push((
+                StoredBlock::for_test(i, None, Some(&name), ""),
+                vector(0, 10 + i as usize, amount),
+            ));
+        }
+        for i in 0..2 {
+            let name = format!("b{}", i);
+            let amount = if i == 0 { 0.0 } else { 0.01 };
+            blocks.push((
+                StoredBlock::for_test(4 + i, None, Some(&name), ""),
+                vector(1, 20 + i as usize, amount),
+            ));
+        }
+        blocks
+    }
+
+    #[test]
+    fn separates_groups_largest_first() {
+        let options = ClusterOptions {
+            k: Some(2),
+            labels: 1,
+            ..ClusterOptions::default()
+        };
+
+        let clustering = cluster_blocks(two_groups(), &options);
+
+        assert_eq!(clustering.clusters.len(), 2);
+        assert_eq!(clustering.clusters[0].size, 4);
+        assert_eq!(clustering.clusters[1].size, 2);
+        assert_eq!(clustering.clusters[0].labels, vec!["a0"]);
+        assert_eq!(clustering.clusters[1].labels, vec!["b0"]);
+
+        for (id, cluster) in clustering.assignments {
+            assert_eq!(cluster, if id < 4 { 0 } else { 1 });
+        }
+    }
+
+    #[test]
+    fn same_seed_same_clustering() {
+        let options = ClusterOptions {
+            k: Some(3),
+            seed: 7,
+            ..ClusterOptions::default()
+        };
+
+        let first = cluster_blocks(two_groups(), &options);
+        let second = cluster_blocks(two_groups(), &options);
+
+        assert_eq!(first.assignments, second.assignments);
+    }
+
+    #[test]
+    fn clamps_k_to_the_number_of_blocks() {
+        let options = ClusterOptions {
+            k: Some(100),
+            ..ClusterOptions::default()
+        };
+
+        let clustering = cluster_blocks(two_groups(), &options);
+
+        assert!(clustering.clusters.len() <= 6);
+        assert_eq!(clustering.assignments.len(), 6);
+        assert_eq!(
+            clustering
+                .clusters
+                .iter()
+                .map(|cluster| cluster.size)
+                .sum::<usize>(),
+            6
+        );
+        for (_, cluster) in clustering.assignments {
+            assert!(cluster < clustering.clusters.len());
+        }
+    }
+
+    #[test]
+    fn no_blocks() {
+        let clustering = cluster_blocks(Vec::new(), &ClusterOptions::default());
+
+        assert!(clustering.clusters.is_empty());
+        assert!(clustering.assignments.is_empty());
+    }
+}
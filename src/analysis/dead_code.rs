@@ -0,0 +1,478 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::graph::calls::CallEdge;
+use crate::vector_store::sqlite::StoredBlock;
+
+/// Options of a dead code report.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DeadCodeOptions {
+    /// Comma-separated names of the functions called from outside the project, e.g.
+    /// `main,handler`. A name can be qualified with its class (`Server::run`), and a trailing `*`
+    /// matches every name with the prefix (`handle_*`).
+    pub entry_points: String,
+
+    /// Whether to report test functions, which are called by the test runner.
+    pub include_tests: bool,
+
+    /// Whether to report public functions (`pub` in Rust, `export`ed in JavaScript, capitalised
+    /// in Go, `public` in Java, ...), which may be called from outside the project.
+    pub include_public: bool,
+
+    /// Only report the functions of source files whose path starts with this prefix.
+    pub path_prefix: Option<String>,
+}
+
+impl Default for DeadCodeOptions {
+    fn default() -> Self {
+        DeadCodeOptions {
+            entry_points: "main".to_owned(),
+            include_tests: false,
+            include_public: false,
+            path_prefix: None,
+        }
+    }
+}
+
+/// A function without incoming calls.
+#[derive(Serialize, Debug)]
+pub struct DeadFunction {
+    /// The row id of the function's block.
+    pub id: i64,
+    pub node_key: String,
+    pub function_name: String,
+    pub class_name: Option<String>,
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+
+    /// The number of tokens of the function, i.e. how much pruning it saves.
+    pub tokens: usize,
+
+    /// Whether the function calls itself. Recursive calls don't keep a function alive.
+    pub recursive: bool,
+}
+
+/// The functions without incoming calls of a source file.
+#[derive(Serialize, Debug)]
+pub struct DeadFile {
+    /// The path of the file, or `None` for blocks that weren't located.
+    pub file_path: Option<String>,
+
+    /// The functions, in the order they appear in the file.
+    pub functions: Vec<DeadFunction>,
+}
+
+/// The functions of a project that nothing calls.
+#[derive(Serialize, Debug)]
+pub struct DeadCodeReport {
+    /// The number of functions checked.
+    pub functions: usize,
+
+    /// The number of functions left out as entry points, tests, public or implicitly called.
+    pub excluded: usize,
+
+    /// The number of functions without incoming calls.
+    pub unused: usize,
+
+    /// The functions without incoming calls, grouped by file, files sorted by path.
+    pub files: Vec<DeadFile>,
+}
+
+/// Why a function isn't reported even though nothing in the project calls it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exclusion {
+    EntryPoint,
+    Test,
+    Public,
+    Implicit,
+}
+
+/// Finds the functions of a project that no other block calls.
+///
+/// A function is called if a call edge from another block resolves to it (see
+/// `calls::resolve_calls`). Calls that can't be told apart are resolved to every candidate, so
+/// an ambiguous call keeps all the functions it may reach alive.
+///
+/// # Arguments
+///
+/// * `blocks` - Every block of the project.
+/// * `edges` - The resolved call edges of the project.
+/// * `options` - The entry points and the kinds of functions to exclude.
+pub fn find_dead_code(
+    blocks: &[StoredBlock],
+    edges: &[CallEdge],
+    options: &DeadCodeOptions,
+) -> DeadCodeReport {
+    let mut called: HashSet<i64> = HashSet::new();
+    let mut recursive: HashSet<i64> = HashSet::new();
+    for edge in edges {
+        match edge.callee {
+            Some(callee) if callee == edge.caller => {
+                recursive.insert(callee);
+            }
+            Some(callee) => {
+                called.insert(callee);
+            }
+            None => {}
+        }
+    }
+
+    let entry_points: Vec<&str> = options
+        .entry_points
+        .split(',')
+        .map(str::trim)
+        .filter(|entry_point| !entry_point.is_empty())
+        .collect();
+
+    let mut report = DeadCodeReport {
+        functions: 0,
+        excluded: 0,
+        unused: 0,
+        files: Vec::new(),
+    };
+
+    for block in blocks {
+        let Some(function_name) = &block.block.function_name else {
+            continue;
+        };
+
+        let file_path = block
+            .location
+            .as_ref()
+            .map(|location| location.file_path.as_str());
+        if let Some(path_prefix) = &options.path_prefix {
+            if !file_path.is_some_and(|file_path| file_path.starts_with(path_prefix.as_str())) {
+                continue;
+            }
+        }
+
+        report.functions += 1;
+        if called.contains(&block.id) {
+            continue;
+        }
+
+        let exclusion = exclusion(block, function_name, &entry_points);
+        let excluded = match exclusion {
+            Some(Exclusion::Test) => !options.include_tests,
+            Some(Exclusion::Public) => !options.include_public,
+            Some(Exclusion::EntryPoint) | Some(Exclusion::Implicit) => true,
+            None => false,
+        };
+        if excluded {
+            report.excluded += 1;
+            continue;
+        }
+
+        let function = DeadFunction {
+            id: block.id,
+            node_key: block.block.node_key.to_owned(),
+            function_name: function_name.to_owned(),
+            class_name: block.block.class_name.to_owned(),
+            start_line: block.location.as_ref().map(|location| location.start_line),
+            end_line: block.location.as_ref().map(|location| location.end_line),
+            tokens: block.token_count(),
+            recursive: recursive.contains(&block.id),
+        };
+
+        report.unused += 1;
+        match report
+            .files
+            .iter_mut()
+            .find(|file| file.file_path.as_deref() == file_path)
+        {
+            Some(file) => file.functions.push(function),
+            None => report.files.push(DeadFile {
+                file_path: file_path.map(str::to_owned),
+                functions: vec![function],
+            }),
+        }
+    }
+
+    report.files.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    for file in &mut report.files {
+        file.functions
+            .sort_by_key(|function| (function.start_line.unwrap_or(usize::MAX), function.id));
+    }
+
+    report
+}
+
+/// Checks whether a function is called from outside the project, by the test runner or by the
+/// language itself, in that order.
+fn exclusion(block: &StoredBlock, function_name: &str, entry_points: &[&str]) -> Option<Exclusion> {
+    let qualified_name = match &block.block.class_name {
+        Some(class_name) => format!("{}::{}", class_name, function_name),
+        None => function_name.to_owned(),
+    };
+
+    let is_entry_point = entry_points.iter().any(|entry_point| {
+        [function_name, qualified_name.as_str()].iter().any(|name| {
+            match entry_point.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == entry_point,
+            }
+        })
+    });
+    if is_entry_point {
+        return Some(Exclusion::EntryPoint);
+    }
+
+    let language = block
+        .location
+        .as_ref()
+        .map_or("", |location| location.language.as_str());
+
+    // The attributes, decorators and signature of the function, up to the line naming it.
+    let lines: Vec<&str> = block.block.content.lines().collect();
+    let signature = lines
+        .iter()
+        .position(|line| line.contains(function_name))
+        .unwrap_or(0);
+    let preamble = &lines[..signature];
+    let signature = lines.get(signature).map_or("", |line| line.trim_start());
+
+    if is_test(block, function_name, preamble) {
+        return Some(Exclusion::Test);
+    }
+
+    if is_public(language, function_name, signature) {
+        return Some(Exclusion::Public);
+    }
+
+    if is_implicit(function_name) {
+        return Some(Exclusion::Implicit);
+    }
+
+    None
+}
+
+/// Whether a function is a test, by its name, its attributes or the file it is in.
+fn is_test(block: &StoredBlock, function_name: &str, preamble: &[&str]) -> bool {
+    let test_name = ["test_", "Test", "Benchmark", "Fuzz"]
+        .iter()
+        .any(|prefix| function_name.starts_with(prefix));
+
+    let test_attribute = preamble.iter().any(|line| {
+        let line = line.trim();
+        (line.starts_with("#[") && line.contains("test"))
+            || line.starts_with("@Test")
+            || line.starts_with("@pytest")
+            || line.starts_with("@ParameterizedTest")
+    });
+
+    let test_file = block.location.as_ref().is_some_and(|location| {
+        let path = location.file_path.as_str();
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+
+        path.starts_with("tests/")
+            || path.starts_with("test/")
+            || path.contains("/tests/")
+            || path.contains("/test/")
+            || file_name.starts_with("test_")
+            || file_name.contains("_test.")
+            || file_name.contains(".test.")
+            || file_name.contains(".spec.")
+    });
+
+    test_name || test_attribute || test_file
+}
+
+/// Whether a function is exported from its module, by the conventions of its language.
+fn is_public(language: &str, function_name: &str, signature: &str) -> bool {
+    match language {
+        "rust" => signature.starts_with("pub "),
+        "javascript" | "typescript" | "tsx" => signature.starts_with("export "),
+        "go" => function_name.starts_with(|c: char| c.is_uppercase()),
+        "java" | "kotlin" | "scala" | "c_sharp" | "php" | "swift" => {
+            signature.split_whitespace().any(|word| word == "public")
+        }
+        _ => false,
+    }
+}
+
+/// Whether a function is called by the language itself rather than by name: Python's dunder
+/// methods and JavaScript constructors.
+fn is_implicit(function_name: &str) -> bool {
+    (function_name.starts_with("__") && function_name.ends_with("__"))
+        || function_name == "constructor"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::BlockLocation;
+    use crate::embeddings::tokens::TokenCount;
+
+    fn function(
+        id: i64,
+        class_name: Option<&str>,
+        name: &str,
+        content: &str,
+        file_path: &str,
+    ) -> StoredBlock {
+        let mut block = StoredBlock::for_test(id, class_name, Some(name), content);
+        block.tokens = TokenCount {
+            embedding: Some(10),
+            llm: None,
+        };
+        block.location = Some(BlockLocation {
+            file_path: file_path.to_owned(),
+            start_line: id as usize * 10,
+            end_line: id as usize * 10 + 2,
+            start_byte: 0,
+            end_byte: 0,
+            language: "rust".to_owned(),
+        });
+        block
+    }
+
+    fn edge(caller: i64, callee: i64) -> CallEdge {
+        CallEdge {
+            caller,
+            caller_key: format!("block_{}", caller),
+            callee: Some(callee),
+            callee_key: Some(format!("block_{}", callee)),
+            callee_name: "f".to_owned(),
+        }
+    }
+
+    /// The names of the reported functions, in report order.
+    fn names(report: &DeadCodeReport) -> Vec<&str> {
+        report
+            .files
+            .iter()
+            .flat_map(|file| &file.functions)
+            .map(|function| function.function_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn reports_functions_nothing_calls() {
+        let blocks = vec![
+            function(
+                1,
+                None,
+                "main",
+                "fn main() {\n    helper();\n}",
+                "src/main.rs",
+            ),
+            function(2, None, "helper", "fn helper() {}", "src/main.rs"),
+            function(3, None, "unused", "fn unused() {}", "src/main.rs"),
+            StoredBlock::for_test(4, Some("Store"), None, "struct Store;"),
+        ];
+
+        let report = find_dead_code(&blocks, &[edge(1, 2)], &DeadCodeOptions::default());
+
+        assert_eq!(report.functions, 3);
+        assert_eq!(report.excluded, 1);
+        assert_eq!(report.unused, 1);
+        assert_eq!(names(&report), vec!["unused"]);
+        assert_eq!(report.files[0].file_path.as_deref(), Some("src/main.rs"));
+    }
+
+    #[test]
+    fn matches_qualified_and_prefixed_entry_points() {
+        let blocks = vec![
+            function(
+                1,
+                Some("Server"),
+                "run",
+                "fn run(&self) {}",
+                "src/server.rs",
+            ),
+            function(2, None, "run", "fn run() {}", "src/lib.rs"),
+            function(3, None, "handle_get", "fn handle_get() {}", "src/lib.rs"),
+        ];
+        let options = DeadCodeOptions {
+            entry_points: "Server::run, handle_*".to_owned(),
+            ..DeadCodeOptions::default()
+        };
+
+        let report = find_dead_code(&blocks, &[], &options);
+
+        assert_eq!(names(&report), vec!["run"]);
+        assert_eq!(report.files[0].file_path.as_deref(), Some("src/lib.rs"));
+    }
+
+    #[test]
+    fn excludes_tests_public_and_implicit_functions() {
+        let blocks = vec![
+            function(1, None, "checks", "#[test]\nfn checks() {}", "src/lib.rs"),
+            function(2, None, "test_parse", "fn test_parse() {}", "src/lib.rs"),
+            function(3, None, "helper", "fn helper() {}", "tests/common.rs"),
+            function(4, None, "api", "pub fn api() {}", "src/lib.rs"),
+            function(
+                5,
+                Some("Store"),
+                "__init__",
+                "def __init__(self):",
+                "src/lib.rs",
+            ),
+        ];
+
+        let report = find_dead_code(&blocks, &[], &DeadCodeOptions::default());
+        assert_eq!(report.unused, 0);
+        assert_eq!(report.excluded, 5);
+
+        let options = DeadCodeOptions {
+            include_tests: true,
+            include_public: true,
+            ..DeadCodeOptions::default()
+        };
+        let report = find_dead_code(&blocks, &[], &options);
+        assert_eq!(
+            names(&report),
+            vec!["checks", "test_parse", "api", "helper"]
+        );
+    }
+
+    #[test]
+    fn recursive_calls_dont_keep_functions_alive() {
+        let blocks = vec![function(
+            1,
+            None,
+            "walk",
+            "fn walk() {\n    walk();\n}",
+            "src/lib.rs",
+        )];
+
+        let report = find_dead_code(&blocks, &[edge(1, 1)], &DeadCodeOptions::default());
+
+        assert_eq!(names(&report), vec!["walk"]);
+        assert!(report.files[0].functions[0].recursive);
+    }
+
+    #[test]
+    fn filters_by_path_prefix() {
+        let blocks = vec![
+            function(1, None, "a", "fn a() {}", "src/a.rs"),
+            function(2, None, "b", "fn b() {}", "lib/b.rs"),
+        ];
+        let options = DeadCodeOptions {
+            path_prefix: Some("lib/".to_owned()),
+            ..DeadCodeOptions::default()
+        };
+
+        let report = find_dead_code(&blocks, &[], &options);
+
+        assert_eq!(report.functions, 1);
+        assert_eq!(names(&report), vec!["b"]);
+    }
+
+    #[test]
+    fn detects_public_functions_by_language() {
+        assert!(is_public("rust", "parse", "pub fn parse() {"));
+        assert!(!is_public("rust", "parse", "fn parse() {"));
+        assert!(is_public(
+            "typescript",
+            "parse",
+            "export function parse() {"
+        ));
+        assert!(is_public("go", "Parse", "func Parse() {"));
+        assert!(!is_public("go", "parse", "func parse() {"));
+        assert!(is_public("java", "parse", "public static void parse() {"));
+        assert!(!is_public("python", "parse", "def parse():"));
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::blocks::BlockLocation;
+use crate::embeddings::encoder::{Vector, VECTOR_SIZE};
+use crate::embeddings::flat::{self, FlatIndex};
+use crate::vector_store::sqlite::StoredBlock;
+
+/// Options of a near-duplicate search.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DuplicateOptions {
+    /// The minimum cosine similarity of two blocks' vectors to report them as near-duplicates.
+    pub threshold: f32,
+
+    /// Blocks with fewer tokens than this are ignored, since trivial blocks (getters, empty
+    /// bodies, ...) are near-duplicates of each other without being copy-pasted logic.
+    pub min_tokens: usize,
+
+    /// The number of consecutive tokens in a shingle.
+    pub shingle_size: usize,
+
+    /// The minimum Jaccard similarity of two blocks' shingles to consider them exact clones.
+    pub clone_threshold: f32,
+}
+
+impl Default for DuplicateOptions {
+    fn default() -> Self {
+        DuplicateOptions {
+            threshold: 0.95,
+            min_tokens: 20,
+            shingle_size: 5,
+            clone_threshold: 0.9,
+        }
+    }
+}
+
+/// A block that belongs to a group of near-duplicates.
+#[derive(Serialize, Debug)]
+pub struct DuplicateBlock {
+    pub id: i64,
+    pub node_key: String,
+    pub class_name: Option<String>,
+    pub function_name: Option<String>,
+    pub location: Option<BlockLocation>,
+}
+
+/// Two near-duplicate blocks.
+#[derive(Serialize, Debug)]
+pub struct DuplicatePair {
+    /// The row ids of the blocks.
+    pub blocks: (i64, i64),
+
+    /// The cosine similarity of the blocks' vectors.
+    pub similarity: f32,
+
+    /// The Jaccard similarity of the blocks' token shingles.
+    pub token_similarity: f32,
+}
+
+/// A group of blocks connected by near-duplicate pairs.
+#[derive(Serialize, Debug)]
+pub struct DuplicateGroup {
+    pub blocks: Vec<DuplicateBlock>,
+    pub pairs: Vec<DuplicatePair>,
+
+    /// The average cosine similarity of the group's pairs.
+    pub similarity: f32,
+
+    /// Whether every pair of the group is also a token-level clone.
+    pub exact_clone: bool,
+}
+
+/// Finds groups of near-duplicate blocks.
+///
+/// The vectors of every pair of blocks are compared, so this is quadratic in the number of
+/// blocks, split across threads. Pairs with a cosine similarity of at least `options.threshold`
+/// are joined into groups, and each of them is checked for being an exact (token-level) clone
+/// by the Jaccard similarity of its blocks' token shingles.
+///
+/// # Arguments
+///
+/// * `blocks` - The blocks of a project, each with its whole-block vector.
+/// * `options` - The thresholds of the search.
+///
+/// # Returns
+///
+/// The groups of near-duplicates, largest and most similar first.
+pub fn find_duplicates(
+    blocks: Vec<(StoredBlock, [f32; VECTOR_SIZE])>,
+    options: &DuplicateOptions,
+) -> Vec<DuplicateGroup> {
+    let blocks: Vec<(StoredBlock, [f32; VECTOR_SIZE], Vec<String>)> = blocks
+        .into_iter()
+        .map(|(block, vector)| {
+            let tokens = tokens(&block.block.content);
+            (block, vector, tokens)
+        })
+        .filter(|(_, _, tokens)| tokens.len() >= options.min_tokens)
+        .collect();
+
+    let index = FlatIndex::build(
+        blocks
+            .iter()
+            .enumerate()
+            .map(|(row, (_, vector, _))| Vector {
+                id: row as i64,
+                point: *vector,
+                code: String::new(),
+            })
+            .collect(),
+    );
+
+    let pairs: Vec<(usize, usize, f32)> = (0..blocks.len())
+        .into_par_iter()
+        .flat_map_iter(|a| {
+            let index = &index;
+            (a + 1..blocks.len()).filter_map(move |b| {
+                let similarity = flat::dot(index.row(a), index.row(b));
+                (similarity >= options.threshold).then_some((a, b, similarity))
+            })
+        })
+        .collect();
+
+    let shingles: HashMap<usize, HashSet<u64>> = pairs
+        .iter()
+        .flat_map(|(a, b, _)| [*a, *b])
+        .collect::<HashSet<usize>>()
+        .into_par_iter()
+        .map(|row| (row, shingles(&blocks[row].2, options.shingle_size)))
+        .collect();
+
+    let mut groups = UnionFind::new(blocks.len());
+    for (a, b, _) in &pairs {
+        groups.union(*a, *b);
+    }
+
+    let mut grouped: HashMap<usize, Vec<(usize, usize, f32)>> = HashMap::new();
+    for pair in pairs {
+        grouped.entry(groups.find(pair.0)).or_default().push(pair);
+    }
+
+    let mut duplicate_groups: Vec<DuplicateGroup> = grouped
+        .into_values()
+        .map(|pairs| {
+            let mut rows: Vec<usize> = pairs.iter().flat_map(|(a, b, _)| [*a, *b]).collect();
+            rows.sort_unstable();
+            rows.dedup();
+
+            let pairs: Vec<DuplicatePair> = pairs
+                .into_iter()
+                .map(|(a, b, similarity)| DuplicatePair {
+                    blocks: (blocks[a].0.id, blocks[b].0.id),
+                    similarity,
+                    token_similarity: jaccard(&shingles[&a], &shingles[&b]),
+                })
+                .collect();
+
+            DuplicateGroup {
+                blocks: rows
+                    .into_iter()
+                    .map(|row| {
+                        let block = &blocks[row].0;
+                        DuplicateBlock {
+                            id: block.id,
+                            node_key: block.block.node_key.to_owned(),
+                            class_name: block.block.class_name.to_owned(),
+                            function_name: block.block.function_name.to_owned(),
+                            location: block.location.to_owned(),
+                        }
+                    })
+                    .collect(),
+                similarity: pairs.iter().map(|pair| pair.similarity).sum::<f32>()
+                    / pairs.len() as f32,
+                exact_clone: pairs
+                    .iter()
+                    .all(|pair| pair.token_similarity >= options.clone_threshold),
+                pairs,
+            }
+        })
+        .collect();
+
+    duplicate_groups.sort_by(|a, b| {
+        b.blocks
+            .len()
+            .cmp(&a.blocks.len())
+            .then(b.similarity.total_cmp(&a.similarity))
+    });
+
+    duplicate_groups
+}
+
+/// Splits code into tokens: identifiers and numbers, and single punctuation characters.
+fn tokens(code: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+
+    for c in code.chars() {
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+
+        if !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+        if !c.is_whitespace() {
+            tokens.push(c.to_string());
+        }
+    }
+
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+
+    tokens
+}
+
+/// Hashes every run of `size` consecutive tokens.
+fn shingles(tokens: &[String], size: usize) -> HashSet<u64> {
+    tokens
+        .windows(size.max(1).min(tokens.len().max(1)))
+        .map(|window| {
+            let mut hasher = DefaultHasher::new();
+            window.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect()
+}
+
+/// The Jaccard similarity of two sets.
+fn jaccard(a: &HashSet<u64>, b: &HashSet<u64>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+
+    a.intersection(b).count() as f32 / union as f32
+}
+
+/// A disjoint-set forest over row numbers.
+struct UnionFind {
+    parents: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        UnionFind {
+            parents: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, row: usize) -> usize {
+        let mut root = row;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+
+        let mut row = row;
+        while self.parents[row] != root {
+            let parent = self.parents[row];
+            self.parents[row] = root;
+            row = parent;
+        }
+
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        if a != b {
+            self.parents[b] = a;
+        }
+    }
+}
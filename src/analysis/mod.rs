@@ -0,0 +1,5 @@
+pub mod clusters;
+pub mod dead_code;
+pub mod duplicates;
+pub mod projection;
+pub mod random;
@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::random::SplitMix64;
+use crate::embeddings::encoder::{Vector, VECTOR_SIZE};
+use crate::embeddings::flat::{self, FlatIndex};
+use crate::vector_store::sqlite::StoredBlock;
+
+/// How a project's vectors are projected down to a plane or space.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectionMethod {
+    /// Principal component analysis. Fast and deterministic, preserves the global structure.
+    #[default]
+    Pca,
+
+    /// A UMAP-style layout: the PCA projection refined so that each block stays close to its
+    /// nearest neighbours in the embedding space. Slower, but keeps local clusters apart.
+    Umap,
+}
+
+/// Options of a projection.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProjectionOptions {
+    pub method: ProjectionMethod,
+
+    /// The number of dimensions to project to, 2 or 3.
+    pub dimensions: usize,
+
+    /// The number of nearest neighbours each block is kept close to by the UMAP-style layout, at
+    /// most `MAX_NEIGHBORS`.
+    pub neighbors: usize,
+
+    /// The number of optimisation epochs of the UMAP-style layout, at most `MAX_EPOCHS`.
+    pub epochs: usize,
+
+    /// The seed of the UMAP-style layout's negative sampling.
+    pub seed: u64,
+}
+
+impl Default for ProjectionOptions {
+    fn default() -> Self {
+        ProjectionOptions {
+            method: ProjectionMethod::default(),
+            dimensions: 2,
+            neighbors: 15,
+            epochs: 200,
+            seed: 0,
+        }
+    }
+}
+
+/// A block placed in the projected space.
+#[derive(Serialize, Debug)]
+pub struct ProjectedPoint {
+    pub id: i64,
+    pub node_key: String,
+    pub function_name: Option<String>,
+    pub class_name: Option<String>,
+
+    /// The source file of the block, if it was located when it was indexed.
+    pub file_path: Option<String>,
+    pub cluster: Option<usize>,
+
+    /// The coordinates of the block, one per dimension.
+    pub coordinates: Vec<f32>,
+}
+
+/// The number of power iterations per principal component.
+const POWER_ITERATIONS: usize = 100;
+
+/// The number of random blocks each neighbour edge is pushed away from per epoch.
+const NEGATIVE_SAMPLES: usize = 5;
+
+/// The largest number of neighbours the UMAP-style layout keeps each block close to. Every
+/// epoch walks all the neighbour edges, so this bounds the work per epoch.
+pub const MAX_NEIGHBORS: usize = 100;
+
+/// The largest number of optimisation epochs of the UMAP-style layout.
+pub const MAX_EPOCHS: usize = 1000;
+
+/// Projects the vectors of a project's blocks to 2 or 3 dimensions for plotting.
+///
+/// # Arguments
+///
+/// * `blocks` - The blocks of a project, each with its whole-block vector.
+/// * `clusters` - The cluster of each block, by row id, if the project was clustered.
+/// * `options` - The projection method and number of dimensions.
+///
+/// # Returns
+///
+/// A point for every block.
+pub fn project(
+    blocks: Vec<(StoredBlock, [f32; VECTOR_SIZE])>,
+    clusters: &HashMap<i64, usize>,
+    options: &ProjectionOptions,
+) -> Vec<ProjectedPoint> {
+    let dimensions = options.dimensions.clamp(2, 3);
+
+    let points: Vec<Vec<f32>> = blocks
+        .par_iter()
+        .map(|(_, vector)| flat::normalize(vector))
+        .collect();
+
+    let mut layout = pca(&points, dimensions);
+
+    let neighbors = options.neighbors.clamp(1, MAX_NEIGHBORS);
+    let epochs = options.epochs.clamp(1, MAX_EPOCHS);
+
+    if options.method == ProjectionMethod::Umap && points.len() > neighbors {
+        refine(&points, &mut layout, neighbors, epochs, options.seed);
+    }
+
+    blocks
+        .into_iter()
+        .zip(layout)
+        .map(|((block, _), coordinates)| ProjectedPoint {
+            id: block.id,
+            cluster: clusters.get(&block.id).copied(),
+            node_key: block.block.node_key,
+            function_name: block.block.function_name,
+            class_name: block.block.class_name,
+            file_path: block.location.map(|location| location.file_path),
+            coordinates,
+        })
+        .collect()
+}
+
+/// Formats projected points as CSV, with a header row and one `x`, `y` (and `z`) column per
+/// dimension.
+///
+/// # Arguments
+///
+/// * `points` - The projected points.
+pub fn to_csv(points: &[ProjectedPoint]) -> String {
+    let dimensions = points.first().map_or(2, |point| point.coordinates.len());
+    let axes = ["x", "y", "z"];
+
+    let mut csv = String::from("id,node_key,function_name,class_name,file_path,cluster");
+    for axis in axes.iter().take(dimensions) {
+        csv.push(',');
+        csv.push_str(axis);
+    }
+    csv.push('\n');
+
+    for point in points {
+        let mut fields = vec![
+            point.id.to_string(),
+            csv_field(&point.node_key),
+            csv_field(point.function_name.as_deref().unwrap_or_default()),
+            csv_field(point.class_name.as_deref().unwrap_or_default()),
+            csv_field(point.file_path.as_deref().unwrap_or_default()),
+            point
+                .cluster
+                .map(|cluster| cluster.to_string())
+                .unwrap_or_default(),
+        ];
+        fields.extend(point.coordinates.iter().map(|value| value.to_string()));
+
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Projects points onto their first principal components, found by power iteration on the
+/// covariance matrix with deflation.
+fn pca(points: &[Vec<f32>], dimensions: usize) -> Vec<Vec<f32>> {
+    if points.is_empty() {
+        return Vec::new();
+    }
+
+    let count = points.len() as f64;
+
+    let mut mean = vec![0.0f32; VECTOR_SIZE];
+    for point in points {
+        for (mean, value) in mean.iter_mut().zip(point) {
+            *mean += value;
+        }
+    }
+    for value in mean.iter_mut() {
+        *value /= count as f32;
+    }
+
+    let centered: Vec<Vec<f32>> = points
+        .par_iter()
+        .map(|point| point.iter().zip(&mean).map(|(x, m)| x - m).collect())
+        .collect();
+
+    // The upper triangle of the covariance matrix, accumulated in f64 for stability.
+    let mut covariance = centered
+        .par_iter()
+        .fold(
+            || vec![0.0f64; VECTOR_SIZE * VECTOR_SIZE],
+            |mut covariance, point| {
+                for (i, x) in point.iter().enumerate() {
+                    let row = &mut covariance[i * VECTOR_SIZE..(i + 1) * VECTOR_SIZE];
+                    for (cell, y) in row[i..].iter_mut().zip(&point[i..]) {
+                        *cell += (*x as f64) * (*y as f64);
+                    }
+                }
+                covariance
+            },
+        )
+        .reduce(
+            || vec![0.0f64; VECTOR_SIZE * VECTOR_SIZE],
+            |mut a, b| {
+                for (a, b) in a.iter_mut().zip(b) {
+                    *a += b;
+                }
+                a
+            },
+        );
+
+    for i in 0..VECTOR_SIZE {
+        for j in 0..i {
+            covariance[i * VECTOR_SIZE + j] = covariance[j * VECTOR_SIZE + i];
+        }
+    }
+    for cell in covariance.iter_mut() {
+        *cell /= count;
+    }
+
+    let mut components: Vec<Vec<f64>> = Vec::with_capacity(dimensions);
+    for component in 0..dimensions {
+        // A deterministic start vector that isn't orthogonal to any likely component.
+        let mut vector: Vec<f64> = (0..VECTOR_SIZE)
+            .map(|i| 1.0 + ((i + component) % 7) as f64 / 7.0)
+            .collect();
+
+        for _ in 0..POWER_ITERATIONS {
+            let mut next: Vec<f64> = covariance
+                .chunks_exact(VECTOR_SIZE)
+                .map(|row| row.iter().zip(&vector).map(|(a, b)| a * b).sum())
+                .collect();
+
+            for previous in &components {
+                let projection: f64 = next.iter().zip(previous).map(|(a, b)| a * b).sum();
+                for (value, p) in next.iter_mut().zip(previous) {
+                    *value -= projection * p;
+                }
+            }
+
+            let norm = next.iter().map(|x| x * x).sum::<f64>().sqrt();
+            if norm == 0.0 {
+                break;
+            }
+            vector = next.into_iter().map(|x| x / norm).collect();
+        }
+
+        components.push(vector);
+    }
+
+    centered
+        .par_iter()
+        .map(|point| {
+            components
+                .iter()
+                .map(|component| {
+                    point
+                        .iter()
+                        .zip(component)
+                        .map(|(x, c)| *x as f64 * c)
+                        .sum::<f64>() as f32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Refines a layout UMAP-style: attracts every point to its nearest neighbours in the embedding
+/// space and repels it from random other points, with a learning rate decaying over the epochs.
+fn refine(
+    points: &[Vec<f32>],
+    layout: &mut [Vec<f32>],
+    neighbors: usize,
+    epochs: usize,
+    seed: u64,
+) {
+    let index = FlatIndex::build(
+        points
+            .iter()
+            .enumerate()
+            .map(|(row, point)| Vector {
+                id: row as i64,
+                point: point.as_slice().try_into().unwrap(),
+                code: String::new(),
+            })
+            .collect(),
+    );
+
+    let edges: Vec<(usize, usize)> = (0..points.len())
+        .into_par_iter()
+        .flat_map_iter(|row| {
+            index
+                .search(index.row(row), neighbors + 1)
+                .into_iter()
+                .filter(move |(neighbor, _)| *neighbor != row)
+                .take(neighbors)
+                .map(move |(neighbor, _)| (row, neighbor))
+        })
+        .collect();
+
+    scale_layout(layout, 10.0);
+
+    let mut random = SplitMix64::new(seed);
+
+    for epoch in 0..epochs {
+        let rate = 1.0 - epoch as f32 / epochs as f32;
+
+        for (a, b) in &edges {
+            let (a, b) = (*a, *b);
+
+            let distance = squared_distance(&layout[a], &layout[b]);
+            let attraction = -2.0 / (1.0 + distance);
+            move_apart(layout, a, b, attraction * rate);
+
+            for _ in 0..NEGATIVE_SAMPLES {
+                let c = random.below(layout.len());
+                let distance = squared_distance(&layout[a], &layout[c]);
+                let repulsion = 2.0 / ((0.001 + distance) * (1.0 + distance));
+                move_apart(layout, a, c, repulsion * rate);
+            }
+        }
+    }
+}
+
+/// Moves point `a` along the direction from `b` to `a` by `coefficient` (clipped), and `b` the
+/// opposite way. A negative coefficient moves them closer together.
+fn move_apart(layout: &mut [Vec<f32>], a: usize, b: usize, coefficient: f32) {
+    let (point_a, point_b) = match a.cmp(&b) {
+        std::cmp::Ordering::Equal => return,
+        std::cmp::Ordering::Less => {
+            let (head, tail) = layout.split_at_mut(b);
+            (&mut head[a], &mut tail[0])
+        }
+        std::cmp::Ordering::Greater => {
+            let (head, tail) = layout.split_at_mut(a);
+            (&mut tail[0], &mut head[b])
+        }
+    };
+
+    for (x, y) in point_a.iter_mut().zip(point_b.iter_mut()) {
+        let gradient = (coefficient * (*x - *y)).clamp(-4.0, 4.0);
+
+        *x += gradient;
+        *y -= gradient;
+    }
+}
+
+fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+/// Scales a layout so its largest absolute coordinate is `extent`.
+fn scale_layout(layout: &mut [Vec<f32>], extent: f32) {
+    let max = layout
+        .iter()
+        .flatten()
+        .fold(0.0f32, |max, value| max.max(value.abs()));
+
+    if max > 0.0 {
+        for value in layout.iter_mut().flatten() {
+            *value *= extent / max;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Blocks whose vectors spread along axis 0, each nudged towards its own axis.
+    fn blocks(count: usize) -> Vec<(StoredBlock, [f32; VECTOR_SIZE])> {
+        (0..count)
+            .map(|i| {
+                let mut vector = [0.0; VECTOR_SIZE];
+                vector[0] = 1.0 + i as f32;
+                vector[1 + i % (VECTOR_SIZE - 1)] = 1.0;
+
+                let name = format!("f{}", i);
+                (
+                    StoredBlock::for_test(i as i64, None, Some(&name), ""),
+                    vector,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pca_follows_the_axis_of_largest_variance() {
+        let offsets = [-2.0, -1.0, 0.0, 1.0, 2.0];
+        let points: Vec<Vec<f32>> = offsets
+            .iter()
+            .enumerate()
+            .map(|(i, offset)| {
+                let mut point = vec![0.0; VECTOR_SIZE];
+                point[0] = *offset;
+                point[1] = if i % 2 == 0 { 0.1 } else { -0.1 };
+                point
+            })
+            .collect();
+
+        let layout = pca(&points, 2);
+
+        assert_eq!(layout.len(), 5);
+        for (coordinates, offset) in layout.iter().zip(offsets) {
+            assert_eq!(coordinates.len(), 2);
+            assert!((coordinates[0].abs() - offset.abs()).abs() < 1e-3);
+            assert!(coordinates[1].abs() < 0.2);
+        }
+    }
+
+    #[test]
+    fn projects_every_block_with_its_cluster() {
+        let clusters = HashMap::from([(0, 3), (2, 1)]);
+        let options = ProjectionOptions {
+            dimensions: 5,
+            ..ProjectionOptions::default()
+        };
+
+        let points = project(blocks(4), &clusters, &options);
+
+        assert_eq!(points.len(), 4);
+        assert!(points.iter().all(|point| point.coordinates.len() == 3));
+        assert_eq!(
+            points.iter().map(|point| point.cluster).collect::<Vec<_>>(),
+            vec![Some(3), None, Some(1), None]
+        );
+        assert_eq!(points[1].function_name.as_deref(), Some("f1"));
+    }
+
+    #[test]
+    fn umap_layout_places_every_block() {
+        let options = ProjectionOptions {
+            method: ProjectionMethod::Umap,
+            neighbors: 3,
+            epochs: 20,
+            ..ProjectionOptions::default()
+        };
+
+        let points = project(blocks(30), &HashMap::new(), &options);
+
+        assert_eq!(points.len(), 30);
+        for point in &points {
+            assert_eq!(point.coordinates.len(), 2);
+            assert!(point.coordinates.iter().all(|value| value.is_finite()));
+        }
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn formats_points_as_csv() {
+        let points = vec![ProjectedPoint {
+            id: 7,
+            node_key: "key".to_owned(),
+            function_name: Some("parse".to_owned()),
+            class_name: None,
+            file_path: Some("src/a,b.rs".to_owned()),
+            cluster: Some(2),
+            coordinates: vec![1.5, -2.0],
+        }];
+
+        assert_eq!(
+            to_csv(&points),
+            "id,node_key,function_name,class_name,file_path,cluster,x,y\n7,key,parse,,\"src/a,b.rs\",2,1.5,-2\n"
+        );
+    }
+}
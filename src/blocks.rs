@@ -1,4 +1,12 @@
+use jwalk::WalkDir;
+use rayon::prelude::*;
 use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::embeddings::encoder::VectorKind;
+use crate::embeddings::tokens::TokenCount;
 
 /// Represents a code block with its associated vector embedding.
 #[derive(Debug, Clone)]
@@ -8,6 +16,172 @@ pub struct EmbeddedBlock {
 
     /// The vector embedding of the code block.
     pub vectors: Vec<f32>,
+
+    /// Additional named vector embeddings of parts of the code block (signature, docstring, ...).
+    pub named_vectors: HashMap<VectorKind, Vec<f32>>,
+
+    /// Where the code block was found, if it could be located.
+    pub location: Option<BlockLocation>,
+
+    /// The number of tokens of the code block's content.
+    pub tokens: TokenCount,
+}
+
+/// Where a code block is in its project.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct BlockLocation {
+    /// The path of the source file, relative to the indexed project path.
+    pub file_path: String,
+
+    /// The first line of the block, starting at 1.
+    pub start_line: usize,
+
+    /// The last line of the block, inclusive.
+    pub end_line: usize,
+
+    /// The byte offset of the start of the block in its file.
+    pub start_byte: usize,
+
+    /// The byte offset of the end of the block in its file, exclusive.
+    pub end_byte: usize,
+
+    /// The language of the source file, e.g. `rust`.
+    pub language: String,
+}
+
+/// Guesses the language of a source file from its extension.
+///
+/// # Returns
+///
+/// The name of the language, or `None` if the file isn't a known kind of source file.
+pub fn language_of(path: &Path) -> Option<&'static str> {
+    let language = match path.extension()?.to_str()? {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "js" | "mjs" | "cjs" | "jsx" => "javascript",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "tsx",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "scala" => "scala",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" => "cpp",
+        "cs" => "c_sharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "swift" => "swift",
+        "lua" => "lua",
+        "sh" | "bash" => "bash",
+        _ => return None,
+    };
+
+    Some(language)
+}
+
+/// Finds the source file and span of every block by searching the project's source files for
+/// the block's content.
+///
+/// Files are scanned line by line for the first line of each block, and a block is located
+/// where its whole content follows. When the same content occurs several times, the
+/// occurrences are handed out to the blocks with that content in order.
+///
+/// # Arguments
+///
+/// * `project_path` - The filesystem path the blocks were indexed from.
+/// * `blocks` - The blocks to locate.
+///
+/// # Returns
+///
+/// The location of each block, or `None` for blocks that weren't found.
+pub fn locate_blocks(
+    project_path: &str,
+    blocks: &[asterisk::block::Block],
+) -> Vec<Option<BlockLocation>> {
+    let mut first_lines: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, block) in blocks.iter().enumerate() {
+        if let Some(line) = block.content.trim().lines().next() {
+            first_lines.entry(line.trim()).or_default().push(i);
+        }
+    }
+
+    let mut files: Vec<(String, &'static str)> = WalkDir::new(project_path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let language = language_of(&path)?;
+            Some((path.to_string_lossy().into_owned(), language))
+        })
+        .collect();
+    files.sort();
+
+    let root = Path::new(project_path);
+
+    // Every occurrence of a block: (block, file, location), in file order.
+    let occurrences: Vec<(usize, usize, BlockLocation)> = files
+        .par_iter()
+        .enumerate()
+        .flat_map_iter(|(file, (file_path, language))| {
+            let text = fs::read_to_string(file_path).unwrap_or_default();
+            let relative_path = Path::new(file_path).strip_prefix(root).map_or_else(
+                |_| file_path.to_owned(),
+                |path| path.to_string_lossy().into_owned(),
+            );
+            let mut occurrences = Vec::new();
+
+            let mut offset = 0;
+            for (line_index, line) in text.split_inclusive('\n').enumerate() {
+                if let Some(candidates) = first_lines.get(line.trim()) {
+                    let start_byte = offset + line.len() - line.trim_start().len();
+
+                    for &block in candidates {
+                        let content = blocks[block].content.trim();
+                        if !text[start_byte..].starts_with(content) {
+                            continue;
+                        }
+
+                        occurrences.push((
+                            block,
+                            file,
+                            BlockLocation {
+                                file_path: relative_path.to_owned(),
+                                start_line: line_index + 1,
+                                end_line: line_index + 1 + content.matches('\n').count(),
+                                start_byte,
+                                end_byte: start_byte + content.len(),
+                                language: language.to_string(),
+                            },
+                        ));
+                    }
+                }
+
+                offset += line.len();
+            }
+
+            occurrences
+        })
+        .collect();
+
+    let mut by_block: HashMap<usize, Vec<(usize, BlockLocation)>> = HashMap::new();
+    for (block, file, location) in occurrences {
+        by_block.entry(block).or_default().push((file, location));
+    }
+
+    let mut taken: HashSet<(usize, usize)> = HashSet::new();
+    (0..blocks.len())
+        .map(|block| {
+            let occurrences = by_block.get(&block)?;
+            let (file, location) = occurrences
+                .iter()
+                .find(|(file, location)| !taken.contains(&(*file, location.start_byte)))
+                .or_else(|| occurrences.first())?;
+
+            taken.insert((*file, location.start_byte));
+            Some(location.clone())
+        })
+        .collect()
 }
 
 /// Represents a set of related code blocks.
@@ -28,3 +202,82 @@ pub struct BlockSet {
     /// A string representation of the outgoing function calls from the code blocks.
     pub outgoing_calls: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector_store::sqlite::StoredBlock;
+
+    /// Creates an empty project directory with a `src` directory.
+    fn project_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        dir
+    }
+
+    fn block(content: &str) -> asterisk::block::Block {
+        StoredBlock::for_test(0, None, Some("f"), content).block
+    }
+
+    #[test]
+    fn guesses_languages_from_extensions() {
+        assert_eq!(language_of(Path::new("src/main.rs")), Some("rust"));
+        assert_eq!(language_of(Path::new("app/view.tsx")), Some("tsx"));
+        assert_eq!(language_of(Path::new("README.md")), None);
+        assert_eq!(language_of(Path::new("Makefile")), None);
+    }
+
+    #[test]
+    fn locates_blocks_in_nested_files() {
+        let dir = project_dir();
+        let source = "use std::fs;\n\nfn parse() {\n    read();\n}\n";
+        fs::write(dir.path().join("src/lib.rs"), source).unwrap();
+        fs::write(
+            dir.path().join("notes.txt"),
+            "fn parse() {\n    read();\n}\n",
+        )
+        .unwrap();
+
+        let blocks = vec![
+            block("fn parse() {\n    read();\n}"),
+            block("fn missing() {}"),
+        ];
+        let locations = locate_blocks(dir.path().to_str().unwrap(), &blocks);
+
+        let start_byte = source.find("fn parse").unwrap();
+        assert_eq!(
+            locations[0],
+            Some(BlockLocation {
+                file_path: Path::new("src")
+                    .join("lib.rs")
+                    .to_string_lossy()
+                    .into_owned(),
+                start_line: 3,
+                end_line: 5,
+                start_byte,
+                end_byte: source.find("}\n").unwrap() + 1,
+                language: "rust".to_owned(),
+            })
+        );
+        assert_eq!(locations[1], None);
+    }
+
+    #[test]
+    fn hands_out_repeated_content_in_order() {
+        let dir = project_dir();
+        fs::write(
+            dir.path().join("src/a.py"),
+            "def f():\n    pass\n\ndef f():\n    pass\n",
+        )
+        .unwrap();
+
+        let blocks = vec![block("def f():\n    pass"), block("def f():\n    pass")];
+        let locations = locate_blocks(dir.path().to_str().unwrap(), &blocks);
+
+        let lines: Vec<usize> = locations
+            .iter()
+            .map(|location| location.as_ref().unwrap().start_line)
+            .collect();
+        assert_eq!(lines, vec![1, 4]);
+    }
+}
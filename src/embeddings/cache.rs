@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// A store of previously generated embeddings, keyed by model and content hash.
+///
+/// Identical blocks (forks, vendored code, branches of the same repository) only
+/// need to be embedded once, no matter which project they are indexed into.
+pub trait EmbeddingCache {
+    /// Looks up cached embeddings for a list of content hashes.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The name of the model the embeddings were generated with.
+    /// * `hashes` - The content hashes to look up.
+    ///
+    /// # Returns
+    ///
+    /// A map from content hash to embedding for every hash that was found in the cache.
+    fn get_cached_embeddings(
+        &self,
+        model: &str,
+        hashes: &[String],
+    ) -> Result<HashMap<String, Vec<f32>>>;
+
+    /// Stores freshly generated embeddings in the cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The name of the model the embeddings were generated with.
+    /// * `entries` - Pairs of content hash and embedding to store.
+    fn insert_cached_embeddings(&self, model: &str, entries: &[(String, Vec<f32>)]) -> Result<()>;
+}
+
+/// A cache shared behind a lock is only locked for each lookup and insert, so the model can
+/// embed the misses of a lookup without holding the lock.
+impl<C: EmbeddingCache> EmbeddingCache for Mutex<C> {
+    fn get_cached_embeddings(
+        &self,
+        model: &str,
+        hashes: &[String],
+    ) -> Result<HashMap<String, Vec<f32>>> {
+        self.lock().get_cached_embeddings(model, hashes)
+    }
+
+    fn insert_cached_embeddings(&self, model: &str, entries: &[(String, Vec<f32>)]) -> Result<()> {
+        self.lock().insert_cached_embeddings(model, entries)
+    }
+}
+
+/// Hit-rate statistics for the embedding cache.
+#[derive(Serialize, Debug)]
+pub struct CacheStats {
+    /// The number of cached entries, across all models.
+    pub entries: i64,
+
+    /// The number of blocks served from the cache since the server started.
+    pub hits: u64,
+
+    /// The number of blocks that had to be embedded since the server started.
+    pub misses: u64,
+
+    /// `hits / (hits + misses)`, or `0.0` if the cache has not been consulted yet.
+    pub hit_rate: f64,
+}
+
+impl CacheStats {
+    /// Builds a `CacheStats` snapshot from the in-process counters.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - The number of entries currently stored in the cache.
+    pub fn snapshot(entries: i64) -> Self {
+        let hits = CACHE_HITS.load(Ordering::Relaxed);
+        let misses = CACHE_MISSES.load(Ordering::Relaxed);
+        let total = hits + misses;
+
+        CacheStats {
+            entries,
+            hits,
+            misses,
+            hit_rate: if total == 0 {
+                0.0
+            } else {
+                hits as f64 / total as f64
+            },
+        }
+    }
+}
+
+/// Records the outcome of a cache lookup in the in-process hit-rate counters.
+pub fn record_lookup(hits: usize, misses: usize) {
+    CACHE_HITS.fetch_add(hits as u64, Ordering::Relaxed);
+    CACHE_MISSES.fetch_add(misses as u64, Ordering::Relaxed);
+}
+
+/// Computes the hex-encoded SHA-256 hash of a block's content.
+pub fn content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -1,11 +1,28 @@
-use fastembed::{EmbeddingBase, FlagEmbedding};
+use fastembed::{EmbeddingBase, FlagEmbedding, InitOptions};
 
+use anyhow::anyhow;
 use anyhow::Result;
 use once_cell::sync::Lazy;
-use serde::Serialize;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::blocks::BlockLocation;
+use crate::embeddings::cache::{self, EmbeddingCache};
+use crate::embeddings::index::IndexType;
+use crate::embeddings::preprocess::{self, PreprocessConfig};
+use crate::embeddings::rerank::RerankerKind;
+
+/// The name of the embedding model and embedding path, used to key cached embeddings.
+pub const MODEL_NAME: &str = "BAAI/bge-small-en-v1.5/passage";
 
 pub static MODEL: Lazy<FlagEmbedding> =
-    Lazy::new(|| FlagEmbedding::try_new(Default::default()).unwrap());
+    Lazy::new(|| FlagEmbedding::try_new(model_options()).unwrap());
+
+/// The options the embedding model is loaded with, including where fastembed downloads it to.
+pub fn model_options() -> InitOptions {
+    InitOptions::default()
+}
 
 use kd_tree::{KdPoint, KdTree, KdTreeN};
 
@@ -13,6 +30,8 @@ pub const VECTOR_SIZE: usize = 384;
 
 #[derive(Debug, Clone)]
 pub struct Vector {
+    /// The row id of the block the vector belongs to, or `0` if it isn't stored.
+    pub id: i64,
     pub point: [f32; VECTOR_SIZE],
     pub code: String,
 }
@@ -33,15 +52,297 @@ pub struct Embeddings {
     pub kd_tree: VectorKdTree,
 }
 
+/// A named vector stored for a code block.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorKind {
+    /// The whole block, as stored in the project table.
+    Block,
+
+    /// The declaration of the block.
+    Signature,
+
+    /// The doc comments and docstrings of the block.
+    Docstring,
+
+    /// The code of the block after its signature.
+    Body,
+
+    /// A natural-language summary of the block, e.g. generated by an LLM.
+    Summary,
+}
+
+impl VectorKind {
+    /// The name of the vector kind, as stored in the database and used in search weights.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VectorKind::Block => "block",
+            VectorKind::Signature => "signature",
+            VectorKind::Docstring => "docstring",
+            VectorKind::Body => "body",
+            VectorKind::Summary => "summary",
+        }
+    }
+
+    /// Parses a vector kind from its name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `name` is not a known vector kind.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim() {
+            "block" => Ok(VectorKind::Block),
+            "signature" => Ok(VectorKind::Signature),
+            "docstring" => Ok(VectorKind::Docstring),
+            "body" => Ok(VectorKind::Body),
+            "summary" => Ok(VectorKind::Summary),
+            other => Err(anyhow!("Unknown vector kind: {}", other)),
+        }
+    }
+
+    /// Parses search weights of the form `signature:1,docstring:0.5,body:2`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any entry has an unknown vector kind or a weight that isn't a
+    /// non-negative number, or if no weight is positive.
+    pub fn parse_weights(weights: &str) -> Result<HashMap<VectorKind, f32>> {
+        let weights = weights
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (kind, weight) = entry
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Expected <kind>:<weight>, got {}", entry))?;
+
+                let weight = weight.trim().parse::<f32>()?;
+                if !weight.is_finite() || weight < 0.0 {
+                    return Err(anyhow!(
+                        "Weights must be non-negative numbers, got {}",
+                        entry
+                    ));
+                }
+
+                Ok((Self::parse(kind)?, weight))
+            })
+            .collect::<Result<HashMap<VectorKind, f32>>>()?;
+
+        if !weights.values().any(|weight| *weight > 0.0) {
+            return Err(anyhow!("At least one weight must be positive"));
+        }
+
+        Ok(weights)
+    }
+}
+
+/// A code block with all of its named vectors.
+#[derive(Debug, Clone)]
+pub struct MultiVector {
+    pub id: i64,
+    pub vectors: HashMap<VectorKind, Vec<f32>>,
+}
+
+impl MultiVector {
+    /// The approximate number of bytes the named vectors take up in memory.
+    pub fn size_bytes(&self) -> usize {
+        std::mem::size_of::<MultiVector>()
+            + self.vectors.capacity()
+                * (std::mem::size_of::<VectorKind>() + std::mem::size_of::<Vec<f32>>())
+            + self
+                .vectors
+                .values()
+                .map(|vector| vector.capacity() * std::mem::size_of::<f32>())
+                .sum::<usize>()
+    }
+}
+
+/// How a search query should be embedded.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryType {
+    /// A natural-language question, embedded with the model's query prefix.
+    Text,
+
+    /// A code snippet, embedded exactly like the stored code blocks.
+    #[default]
+    Code,
+}
+
 #[derive(Serialize, Debug)]
 pub struct NearestVectors {
     pub nearest: String,
     pub k_nearest: Vec<String>,
+    /// The score of each of the `k_nearest` blocks, higher is better.
+    pub scores: Vec<f32>,
+    /// The row id of each of the `k_nearest` blocks.
+    pub ids: Vec<i64>,
+    /// The location of each of the `k_nearest` blocks, if known. Filled in by the vector store.
+    pub locations: Vec<Option<BlockLocation>>,
+    /// The number of tokens of each of the `k_nearest` blocks. Filled in by the vector store.
+    pub tokens: Vec<usize>,
+}
+
+impl NearestVectors {
+    /// Builds a `NearestVectors` struct from ranked candidates, best first. Without candidates,
+    /// e.g. when a search filter matches no blocks, `nearest` is empty and so are the matches.
+    pub fn from_candidates(candidates: Vec<Candidate>) -> Self {
+        let nearest = candidates
+            .first()
+            .map(|candidate| candidate.code.to_owned())
+            .unwrap_or_default();
+
+        let ids = candidates.iter().map(|candidate| candidate.id).collect();
+        let (k_nearest, scores): (Vec<String>, Vec<f32>) = candidates
+            .into_iter()
+            .map(|candidate| (candidate.code, candidate.score))
+            .unzip();
+
+        NearestVectors {
+            nearest,
+            locations: vec![None; k_nearest.len()],
+            tokens: vec![0; k_nearest.len()],
+            k_nearest,
+            scores,
+            ids,
+        }
+    }
+
+    /// Keeps the best matches whose blocks fit a token budget together, in rank order. Matches
+    /// that don't fit the remaining budget are skipped, so smaller ones further down the list
+    /// may still be kept. `nearest` becomes the best match kept, or empty if none fits.
+    pub fn fit_budget(&mut self, max_tokens: usize) {
+        let mut remaining = max_tokens;
+        let keep: Vec<bool> = self
+            .tokens
+            .iter()
+            .map(|tokens| {
+                let fits = *tokens <= remaining;
+                if fits {
+                    remaining -= tokens;
+                }
+                fits
+            })
+            .collect();
+
+        retain_kept(&mut self.k_nearest, &keep);
+        retain_kept(&mut self.scores, &keep);
+        retain_kept(&mut self.ids, &keep);
+        retain_kept(&mut self.locations, &keep);
+        retain_kept(&mut self.tokens, &keep);
+
+        self.nearest = self.k_nearest.first().cloned().unwrap_or_default();
+    }
+}
+
+/// Keeps the values whose flag in `keep` is set.
+fn retain_kept<T>(values: &mut Vec<T>, keep: &[bool]) {
+    let mut keep = keep.iter();
+    values.retain(|_| keep.next().copied().unwrap_or(false));
+}
+
+/// A match of a search across several projects.
+#[derive(Serialize, Debug)]
+pub struct ProjectMatch {
+    /// The project the matching block belongs to.
+    pub project: String,
+
+    pub code: String,
+
+    /// The location of the matching block in its project, if known.
+    pub location: Option<BlockLocation>,
+
+    /// The score of the match, higher is better.
+    pub score: f32,
+
+    /// The number of tokens of the matching block.
+    pub tokens: usize,
+}
+
+/// A search result candidate, before the final ranking stage.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    /// The row id of the candidate block.
+    pub id: i64,
+
+    /// The code of the candidate block.
+    pub code: String,
+
+    /// The whole-block vector of the candidate.
+    pub point: Vec<f32>,
+
+    /// The similarity of the candidate to the query, higher is better.
+    pub score: f32,
+
+    /// The centrality of the candidate block in its project's call graph, from `0` to `1`.
+    /// Filled in by the vector store.
+    pub centrality: f32,
+}
+
+/// Options controlling how a search is embedded and ranked.
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Whether the query is a natural-language question or a code snippet.
+    pub query_type: QueryType,
+
+    /// The nearest-neighbour index to search with.
+    pub index: IndexType,
+
+    /// The number of matches to return.
+    pub matches: usize,
+
+    /// The reranker to rescore the first-stage candidates with, if any.
+    pub rerank: Option<RerankerKind>,
+
+    /// The number of first-stage candidates to rerank or diversify.
+    pub rerank_candidates: usize,
+
+    /// If given, diversify the matches with maximal marginal relevance. `1.0` ranks purely by
+    /// relevance, `0.0` purely by dissimilarity to the matches already selected.
+    pub lambda: Option<f32>,
+
+    /// If given, only return the best matches whose blocks fit this token budget together.
+    pub max_tokens: Option<usize>,
+
+    /// If given, add the centrality of each block in the call graph, weighted by this factor,
+    /// to its score, so widely-called functions outrank one-offs of similar relevance.
+    pub centrality: Option<f32>,
+
+    /// The preprocessing the searched blocks were embedded with, applied to code queries too.
+    pub preprocess: PreprocessConfig,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            query_type: QueryType::default(),
+            index: IndexType::default(),
+            matches: 5,
+            rerank: None,
+            rerank_candidates: 50,
+            lambda: None,
+            max_tokens: None,
+            centrality: None,
+            preprocess: PreprocessConfig::default(),
+        }
+    }
+}
+
+impl SearchOptions {
+    /// The number of candidates the first stage of the search has to retrieve.
+    pub fn candidate_count(&self) -> usize {
+        if self.rerank.is_some() || self.lambda.is_some() || self.centrality.is_some() {
+            self.rerank_candidates.max(self.matches)
+        } else {
+            self.matches
+        }
+    }
 }
 
 impl Embeddings {
     /// Generates a vector embedding for a given code block.
     ///
+    /// The block is embedded as a document (passage), the same way stored blocks are.
+    ///
     /// # Arguments
     ///
     /// * `code` - The code block to generate an embedding for.
@@ -56,20 +357,53 @@ impl Embeddings {
     pub fn generate_code_vector(code: String) -> Result<Vector> {
         let mut code = code;
 
-        let output = MODEL.embed(vec![code.to_owned()], None)?;
+        let output = MODEL.passage_embed(vec![code.to_owned()], None)?;
         let vector: [f32; VECTOR_SIZE] = output[0].as_slice().try_into().unwrap();
 
         Ok(Vector {
+            id: 0,
             point: vector,
             code: code,
         })
     }
 
+    /// Generates a vector embedding for a natural-language search query.
+    ///
+    /// The query is embedded with the model's query prefix, so questions such as
+    /// "where do we retry HTTP requests" land close to the code that answers them.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The search query to generate an embedding for.
+    ///
+    /// # Returns
+    ///
+    /// A `Vector` struct containing the generated embedding and original query.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the embedding model fails to generate a vector.
+    pub fn generate_query_vector(query: String) -> Result<Vector> {
+        let output = MODEL.query_embed(query.to_owned())?;
+        let vector: [f32; VECTOR_SIZE] = output.as_slice().try_into().unwrap();
+
+        Ok(Vector {
+            id: 0,
+            point: vector,
+            code: query,
+        })
+    }
+
     /// Generates a set of vector embeddings for a list of code blocks.
     ///
+    /// Blocks whose content has already been embedded (in any project) are served from
+    /// `cache`; only the remaining blocks are passed to the model, and their embeddings
+    /// are added to the cache.
+    ///
     /// # Arguments
     ///
     /// * `code_blocks` - A list of code blocks to generate embeddings for.
+    /// * `cache` - The embedding cache to consult and populate.
     ///
     /// # Returns
     ///
@@ -77,22 +411,125 @@ impl Embeddings {
     ///
     /// # Errors
     ///
-    /// Returns an error if the embedding model fails to generate any of the vectors.
-    pub fn generate_vector_set(code_blocks: Vec<String>) -> Result<Vec<Vector>> {
-        let output: Vec<Vec<f32>> = MODEL.embed(code_blocks.to_owned(), None)?;
+    /// Returns an error if the embedding model fails to generate any of the vectors,
+    /// or if the cache cannot be read or written.
+    pub fn generate_vector_set(
+        code_blocks: Vec<String>,
+        cache: &impl EmbeddingCache,
+    ) -> Result<Vec<Vector>> {
+        let hashes: Vec<String> = code_blocks
+            .iter()
+            .map(|code| cache::content_hash(code))
+            .collect();
 
-        let vector_set: Vec<Vector> = output
+        let mut cached = cache.get_cached_embeddings(MODEL_NAME, &hashes)?;
+
+        // Embed each distinct uncached block once, even if it appears several times.
+        let mut missing: HashMap<&str, &str> = HashMap::new();
+        for (hash, code) in hashes.iter().zip(code_blocks.iter()) {
+            if !cached.contains_key(hash) {
+                missing.entry(hash).or_insert(code);
+            }
+        }
+
+        let (missing_hashes, missing_code): (Vec<&str>, Vec<&str>) = missing.into_iter().unzip();
+        let hits = code_blocks.len() - missing_code.len();
+        cache::record_lookup(hits, missing_code.len());
+
+        if !missing_code.is_empty() {
+            let output: Vec<Vec<f32>> = MODEL.passage_embed(missing_code, None)?;
+
+            let entries: Vec<(String, Vec<f32>)> = missing_hashes
+                .into_iter()
+                .map(|hash| hash.to_owned())
+                .zip(output)
+                .collect();
+
+            cache.insert_cached_embeddings(MODEL_NAME, &entries)?;
+            cached.extend(entries);
+        }
+
+        let vector_set: Vec<Vector> = hashes
             .iter()
-            .zip(code_blocks.iter())
-            .map(|(x, y)| Vector {
-                point: x.as_slice().try_into().unwrap(),
-                code: y.clone(),
+            .zip(code_blocks.into_iter())
+            .map(|(hash, code)| Vector {
+                id: 0,
+                point: cached[hash].as_slice().try_into().unwrap(),
+                code,
             })
             .collect();
 
         Ok(vector_set)
     }
 
+    /// Generates the named vectors (signature, docstring, body and summary) of a list of code blocks.
+    ///
+    /// Signatures and bodies are preprocessed with `config`; docstrings and summaries are
+    /// embedded as-is. Parts that are empty, and summaries that weren't provided, are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `blocks` - The code blocks to generate named vectors for.
+    /// * `summaries` - Natural-language summaries of the blocks, keyed by node key.
+    /// * `config` - The preprocessing steps to apply to signatures and bodies.
+    /// * `cache` - The embedding cache to consult and populate.
+    ///
+    /// # Returns
+    ///
+    /// One map of named vectors per code block, in the same order as `blocks`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the embedding model fails to generate any of the vectors.
+    pub fn generate_named_vectors(
+        blocks: &[asterisk::block::Block],
+        summaries: &HashMap<String, String>,
+        config: &PreprocessConfig,
+        cache: &impl EmbeddingCache,
+    ) -> Result<Vec<HashMap<VectorKind, Vec<f32>>>> {
+        let mut texts: HashMap<VectorKind, (Vec<usize>, Vec<String>)> = HashMap::new();
+
+        for (i, block) in blocks.iter().enumerate() {
+            let parts = preprocess::block_parts(block);
+
+            let mut push = |kind: VectorKind, text: String| {
+                if !text.trim().is_empty() {
+                    let (indices, texts) = texts.entry(kind).or_default();
+                    indices.push(i);
+                    texts.push(text);
+                }
+            };
+
+            push(
+                VectorKind::Signature,
+                preprocess::preprocess_code(&parts.signature, config),
+            );
+            push(
+                VectorKind::Body,
+                preprocess::preprocess_code(&parts.body, config),
+            );
+            if let Some(docstring) = parts.docstring {
+                push(VectorKind::Docstring, docstring);
+            }
+            if let Some(summary) = summaries.get(&block.node_key) {
+                push(VectorKind::Summary, summary.to_owned());
+            }
+        }
+
+        let mut named_vectors: Vec<HashMap<VectorKind, Vec<f32>>> =
+            vec![HashMap::new(); blocks.len()];
+
+        for (kind, (indices, texts)) in texts {
+            let vector_set = Self::generate_vector_set(texts, cache)?;
+
+            for (i, vector) in indices.into_iter().zip(vector_set) {
+                named_vectors[i].insert(kind, vector.point.to_vec());
+            }
+        }
+
+        Ok(named_vectors)
+    }
+
     /// Generates an `Embeddings` struct containing vector embeddings and a KD tree index for a list of code blocks.
     ///
     /// # Arguments
@@ -113,6 +550,7 @@ impl Embeddings {
             .iter()
             .zip(code_blocks.iter())
             .map(|(x, y)| Vector {
+                id: 0,
                 point: x.as_slice().try_into().unwrap(),
                 code: y.clone(),
             })
@@ -147,46 +585,335 @@ impl Embeddings {
         let nearest = self.kd_tree.nearest(&query).unwrap();
 
         let mut code_blocks = Vec::new();
+        let mut scores = Vec::new();
+        let mut ids = Vec::new();
         let k_nearest = self.kd_tree.nearests(&query, matches);
 
         for nearest in k_nearest {
             code_blocks.push(nearest.item.code.to_owned());
+            scores.push(cosine_similarity(&query.point, &nearest.item.point));
+            ids.push(nearest.item.id);
         }
 
         Ok(NearestVectors {
             nearest: nearest.item.code.to_owned(),
+            locations: vec![None; code_blocks.len()],
+            tokens: vec![0; code_blocks.len()],
             k_nearest: code_blocks,
+            scores,
+            ids,
         })
     }
 
-    /// Searches a list of `Vector` structs for the closest matches to a given code block.
+    /// Embeds a search query the way its `QueryType` calls for. Code queries are preprocessed
+    /// with `config` first.
     ///
     /// # Arguments
     ///
-    /// * `vector_set` - The list of `Vector` structs to search.
-    /// * `code` - The code block to search for matches to.
-    /// * `matches` - The number of closest matches to return.
+    /// * `code` - The code block or natural-language query to embed.
+    /// * `query_type` - Whether `code` is a natural-language query or a code snippet.
+    /// * `config` - The preprocessing the searched blocks were embedded with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the embedding model fails to generate a vector.
+    pub fn embed_query(
+        code: String,
+        query_type: QueryType,
+        config: &PreprocessConfig,
+    ) -> Result<Vector> {
+        match query_type {
+            QueryType::Text => Self::generate_query_vector(code),
+            QueryType::Code => {
+                // Code snippets are preprocessed like the stored blocks (see `preprocess_block`),
+                // so identifiers are split into the same words on both sides.
+                let preprocessed = preprocess::preprocess_code(&code, config);
+
+                let mut vector = Self::generate_code_vector(preprocessed)?;
+                vector.code = code;
+
+                Ok(vector)
+            }
+        }
+    }
+
+    /// Embeds a search query for several projects whose blocks may have been preprocessed
+    /// differently, once per distinct preprocessing. Natural-language queries aren't
+    /// preprocessed, so they are embedded once.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The code block or natural-language query to embed.
+    /// * `query_type` - Whether `code` is a natural-language query or a code snippet.
+    /// * `configs` - The preprocessing of each project's blocks.
     ///
     /// # Returns
     ///
-    /// A `NearestVectors` struct containing the closest matching code block and a list of the top `matches` closest matches.
-    pub fn search(vector_set: Vec<Vector>, code: String, matches: usize) -> Result<NearestVectors> {
-        let query: Vector = Self::generate_code_vector(code)?;
+    /// One query vector per config, in the same order as `configs`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the embedding model fails to generate a vector.
+    pub fn embed_queries(
+        code: &str,
+        query_type: QueryType,
+        configs: &[&PreprocessConfig],
+    ) -> Result<Vec<Vector>> {
+        let mut embedded: Vec<(&PreprocessConfig, Vector)> = Vec::new();
+        let mut queries = Vec::with_capacity(configs.len());
 
-        let kdtree: VectorKdTree = KdTree::par_build_by_ordered_float(vector_set.to_owned());
+        for config in configs {
+            let existing = embedded.iter().find(|(embedded_config, _)| {
+                query_type == QueryType::Text || embedded_config == config
+            });
 
-        let nearest = kdtree.nearest(&query).unwrap();
+            let query = match existing {
+                Some((_, query)) => query.clone(),
+                None => {
+                    let query = Self::embed_query(code.to_owned(), query_type, config)?;
+                    embedded.push((config, query.clone()));
+                    query
+                }
+            };
 
-        let mut code_blocks = Vec::new();
-        let k_nearest = kdtree.nearests(&query, matches);
+            queries.push(query);
+        }
 
-        for nearest in k_nearest {
-            code_blocks.push(nearest.item.code.to_owned());
+        Ok(queries)
+    }
+
+    /// Searches for the closest matches to a given code block.
+    ///
+    /// The first stage of the search is delegated to `find_candidates`, so the caller decides
+    /// which index to search and where the vectors and code come from. The candidates it returns
+    /// are then reranked and diversified according to `options`.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The code block or natural-language query to search for matches to.
+    /// * `options` - The query type, number of matches and ranking options of the search.
+    /// * `find_candidates` - Given the embedded query and the number of candidates needed,
+    ///   returns the nearest candidates, closest first, including their code.
+    ///
+    /// # Returns
+    ///
+    /// A `NearestVectors` struct containing the closest matching code block and a list of the top `options.matches` closest matches.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the embedding model fails to generate a vector for the search query,
+    /// if `find_candidates` fails, or if reranking fails.
+    pub fn search(
+        code: String,
+        options: &SearchOptions,
+        find_candidates: impl FnOnce(&Vector, usize) -> Result<Vec<Candidate>>,
+    ) -> Result<NearestVectors> {
+        let query: Vector =
+            Self::embed_query(code.to_owned(), options.query_type, &options.preprocess)?;
+        let candidates = find_candidates(&query, options.candidate_count())?;
+
+        Self::rank(&code, candidates, options)
+    }
+
+    /// Scores a list of `MultiVector` structs by a weighted average of the cosine similarity
+    /// between the query and each of their named vectors, and returns the best ones as
+    /// candidates, without their code.
+    ///
+    /// Only the kinds a block actually has count towards its average, so blocks without
+    /// doc comments aren't penalised for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `blocks` - The named vectors of the blocks to score.
+    /// * `block_vector` - Looks up the whole-block vector of a block by row id. It is scored as
+    ///   the `block` kind and becomes the point of the block's candidate.
+    /// * `query` - The embedded search query.
+    /// * `weights` - The weight of each vector kind. Kinds without a weight are ignored.
+    /// * `count` - The number of candidates to return.
+    ///
+    /// # Returns
+    ///
+    /// The best `count` candidates, best first. Blocks without any of the weighted vector kinds
+    /// are left out.
+    pub fn weighted_candidates(
+        blocks: Vec<&MultiVector>,
+        block_vector: impl Fn(i64) -> Option<[f32; VECTOR_SIZE]> + Sync,
+        query: &Vector,
+        weights: &HashMap<VectorKind, f32>,
+        count: usize,
+    ) -> Vec<Candidate> {
+        let mut candidates: Vec<Candidate> = blocks
+            .into_par_iter()
+            .filter_map(|block| {
+                let point = block_vector(block.id);
+                let mut score = 0.0;
+                let mut total_weight = 0.0;
+
+                for (kind, weight) in weights {
+                    let vector = match kind {
+                        VectorKind::Block => point.as_ref().map(|point| point.as_slice()),
+                        kind => block.vectors.get(kind).map(Vec::as_slice),
+                    };
+
+                    if let Some(vector) = vector {
+                        score += weight * cosine_similarity(&query.point, vector);
+                        total_weight += weight;
+                    }
+                }
+
+                if total_weight > 0.0 {
+                    Some(Candidate {
+                        id: block.id,
+                        code: String::new(),
+                        point: point.map(|point| point.to_vec()).unwrap_or_default(),
+                        score: score / total_weight,
+                        centrality: 0.0,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        candidates.par_sort_unstable_by(|a, b| b.score.total_cmp(&a.score));
+        candidates.truncate(count);
+
+        candidates
+    }
+
+    /// Applies the final ranking stage to a list of first-stage candidates: optionally reranks
+    /// them against the query, blends in their centrality and diversifies them, then keeps the
+    /// top `options.matches`.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The search query, as sent by the user.
+    /// * `candidates` - The first-stage candidates, best first.
+    /// * `options` - The ranking options of the search.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reranking fails.
+    pub fn rank(
+        query: &str,
+        candidates: Vec<Candidate>,
+        options: &SearchOptions,
+    ) -> Result<NearestVectors> {
+        Ok(NearestVectors::from_candidates(Self::rank_candidates(
+            query, candidates, options,
+        )?))
+    }
+
+    /// Like `rank`, but returns the ranked candidates themselves.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The search query, as sent by the user.
+    /// * `candidates` - The first-stage candidates, best first.
+    /// * `options` - The ranking options of the search.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reranking fails.
+    pub fn rank_candidates(
+        query: &str,
+        mut candidates: Vec<Candidate>,
+        options: &SearchOptions,
+    ) -> Result<Vec<Candidate>> {
+        if let Some(reranker) = options.rerank {
+            let scores = reranker.reranker().rerank(query, &candidates)?;
+
+            for (candidate, score) in candidates.iter_mut().zip(scores) {
+                candidate.score = score;
+            }
+
+            candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
         }
 
-        Ok(NearestVectors {
-            nearest: nearest.item.code.to_owned(),
-            k_nearest: code_blocks,
-        })
+        if let Some(weight) = options.centrality {
+            for candidate in candidates.iter_mut() {
+                candidate.score += weight * candidate.centrality;
+            }
+
+            candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+        }
+
+        if let Some(lambda) = options.lambda {
+            candidates = Self::diversify(candidates, lambda, options.matches);
+        }
+
+        candidates.truncate(options.matches);
+
+        Ok(candidates)
+    }
+
+    /// Selects `matches` candidates by maximal marginal relevance, greedily picking the candidate
+    /// that maximises `lambda * score - (1 - lambda) * max_similarity_to_selected`.
+    ///
+    /// This keeps near-copies of the same helper from crowding out everything else.
+    ///
+    /// # Arguments
+    ///
+    /// * `candidates` - The candidates to select from, with their relevance scores.
+    /// * `lambda` - The trade-off between relevance (`1.0`) and diversity (`0.0`).
+    /// * `matches` - The number of candidates to select.
+    ///
+    /// # Returns
+    ///
+    /// The selected candidates, in selection order.
+    pub fn diversify(
+        mut candidates: Vec<Candidate>,
+        lambda: f32,
+        matches: usize,
+    ) -> Vec<Candidate> {
+        let lambda = lambda.clamp(0.0, 1.0);
+        let mut selected: Vec<Candidate> = Vec::with_capacity(matches);
+
+        // The highest similarity of each remaining candidate to any selected candidate.
+        let mut redundancy = vec![f32::NEG_INFINITY; candidates.len()];
+
+        while selected.len() < matches && !candidates.is_empty() {
+            let (best, _) = candidates
+                .iter()
+                .zip(redundancy.iter())
+                .map(|(candidate, &redundancy)| {
+                    let redundancy = if redundancy.is_finite() {
+                        redundancy
+                    } else {
+                        0.0
+                    };
+                    lambda * candidate.score - (1.0 - lambda) * redundancy
+                })
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+
+            let chosen = candidates.swap_remove(best);
+            redundancy.swap_remove(best);
+
+            for (candidate, redundancy) in candidates.iter().zip(redundancy.iter_mut()) {
+                *redundancy = redundancy.max(cosine_similarity(&candidate.point, &chosen.point));
+            }
+
+            selected.push(chosen);
+        }
+
+        selected
+    }
+}
+
+/// Computes the cosine similarity between two vectors.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let (dot, norm_a, norm_b) = a
+        .iter()
+        .zip(b.iter())
+        .fold((0.0, 0.0, 0.0), |(dot, norm_a, norm_b), (x, y)| {
+            (dot + x * y, norm_a + x * x, norm_b + y * y)
+        });
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a.sqrt() * norm_b.sqrt())
     }
 }
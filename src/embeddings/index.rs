@@ -0,0 +1,397 @@
+use std::collections::HashSet;
+
+use kd_tree::KdTree;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::embeddings::encoder::{cosine_similarity, Candidate, Vector, VectorKdTree};
+use crate::embeddings::flat::FlatIndex;
+use crate::embeddings::quantize::{Quantization, QuantizedIndex};
+
+/// The nearest-neighbour index a project is searched with.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexType {
+    /// A KD tree over the vectors.
+    #[default]
+    KdTree,
+
+    /// An exact, brute-force SIMD scan. See `FlatIndex`.
+    Flat,
+}
+
+impl IndexType {
+    /// The name of the index type, as stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IndexType::KdTree => "kdtree",
+            IndexType::Flat => "flat",
+        }
+    }
+
+    /// Parses an index type from its name, falling back to the default for unknown names.
+    pub fn parse(name: &str) -> Self {
+        match name {
+            "flat" => IndexType::Flat,
+            _ => IndexType::KdTree,
+        }
+    }
+}
+
+/// A built nearest-neighbour index over a project's vectors.
+pub enum ProjectIndex {
+    KdTree(VectorKdTree),
+    Flat(FlatIndex),
+}
+
+impl ProjectIndex {
+    /// Builds an index of the given type.
+    ///
+    /// # Arguments
+    ///
+    /// * `vector_set` - The vectors to index.
+    /// * `index_type` - The type of index to build.
+    pub fn build(vector_set: Vec<Vector>, index_type: IndexType) -> Self {
+        match index_type {
+            IndexType::KdTree => {
+                ProjectIndex::KdTree(KdTree::par_build_by_ordered_float(vector_set))
+            }
+            IndexType::Flat => ProjectIndex::Flat(FlatIndex::build(vector_set)),
+        }
+    }
+
+    /// The approximate number of bytes the index takes up in memory.
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            ProjectIndex::KdTree(kdtree) => kdtree
+                .iter()
+                .map(|vector| std::mem::size_of::<Vector>() + vector.code.capacity())
+                .sum(),
+            ProjectIndex::Flat(flat) => {
+                flat.ids.capacity() * std::mem::size_of::<i64>()
+                    + flat.codes.iter().map(|code| code.capacity()).sum::<usize>()
+                    + flat.codes.capacity() * std::mem::size_of::<String>()
+                    + flat.data.capacity() * std::mem::size_of::<f32>()
+            }
+        }
+    }
+
+    /// Finds the nearest neighbours of a query vector.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The embedded search query.
+    /// * `count` - The number of nearest neighbours to return.
+    ///
+    /// # Returns
+    ///
+    /// Up to `count` candidates, closest first, scored by cosine similarity to the query.
+    pub fn search(&self, query: &Vector, count: usize) -> Vec<Candidate> {
+        match self {
+            ProjectIndex::KdTree(kdtree) => kdtree
+                .nearests(query, count)
+                .into_iter()
+                .map(|nearest| Candidate {
+                    id: nearest.item.id,
+                    code: nearest.item.code.to_owned(),
+                    point: nearest.item.point.to_vec(),
+                    score: cosine_similarity(&query.point, &nearest.item.point),
+                    centrality: 0.0,
+                })
+                .collect(),
+            ProjectIndex::Flat(flat) => flat
+                .search(&query.point, count)
+                .into_iter()
+                .map(|(row, score)| Candidate {
+                    id: flat.ids[row],
+                    code: flat.codes[row].to_owned(),
+                    point: flat.row(row).to_vec(),
+                    score,
+                    centrality: 0.0,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Scores vectors against a query at full precision and keeps the best `count`.
+///
+/// Used to rescore the shortlist produced by a `QuantizedIndex`.
+///
+/// # Arguments
+///
+/// * `query` - The embedded search query.
+/// * `vectors` - The full-precision vectors of the shortlisted blocks.
+/// * `count` - The number of candidates to keep.
+///
+/// # Returns
+///
+/// Up to `count` candidates, closest first, scored by cosine similarity to the query.
+pub fn rescore(query: &Vector, vectors: Vec<Vector>, count: usize) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = vectors
+        .into_par_iter()
+        .map(|vector| Candidate {
+            score: cosine_similarity(&query.point, &vector.point),
+            id: vector.id,
+            code: vector.code,
+            point: vector.point.to_vec(),
+            centrality: 0.0,
+        })
+        .collect();
+
+    candidates.par_sort_unstable_by(|a, b| b.score.total_cmp(&a.score));
+    candidates.truncate(count);
+
+    candidates
+}
+
+/// How closely an index's results match an exact search.
+#[derive(Serialize, Debug)]
+pub struct RecallReport {
+    /// The index whose recall was measured.
+    pub index: IndexType,
+
+    /// The quantization whose recall was measured. Quantized searches scan the quantized
+    /// vectors instead of `index`, then rescore the shortlist at full precision.
+    pub quantization: Quantization,
+
+    /// The number of nearest neighbours compared per query.
+    pub k: usize,
+
+    /// The number of queries sampled from the project's own vectors.
+    pub samples: usize,
+
+    /// The average fraction of the exact top `k` the index returned.
+    pub recall: f32,
+}
+
+/// The largest number of queries a recall measurement runs. Each query is searched both exactly
+/// and with the measured index, so this bounds the work of a measurement.
+pub const MAX_RECALL_SAMPLES: usize = 1000;
+
+/// The largest number of nearest neighbours a recall measurement compares per query.
+pub const MAX_RECALL_K: usize = 100;
+
+/// Measures the recall@k of an index against the exact flat search, using evenly spaced
+/// vectors of the project itself as queries.
+///
+/// # Arguments
+///
+/// * `vector_set` - The project's vectors.
+/// * `index_type` - The index to measure.
+/// * `quantization` - The quantization to measure. If set, it is measured instead of `index_type`.
+/// * `rescore_factor` - How many times `k` quantized candidates to rescore at full precision.
+/// * `k` - The number of nearest neighbours to compare per query, at most `MAX_RECALL_K`.
+/// * `samples` - The maximum number of queries to run, at most `MAX_RECALL_SAMPLES`.
+///
+/// # Returns
+///
+/// A `RecallReport` with the average recall over all queries.
+pub fn measure_recall(
+    vector_set: Vec<Vector>,
+    index_type: IndexType,
+    quantization: Quantization,
+    rescore_factor: usize,
+    k: usize,
+    samples: usize,
+) -> RecallReport {
+    let k = k.min(MAX_RECALL_K);
+    let samples = samples.min(MAX_RECALL_SAMPLES);
+
+    let step = (vector_set.len() / samples.max(1)).max(1);
+    let queries: Vec<Vector> = vector_set
+        .iter()
+        .step_by(step)
+        .take(samples)
+        .cloned()
+        .collect();
+
+    let exact = ProjectIndex::build(vector_set.clone(), IndexType::Flat);
+
+    let search: Box<dyn Fn(&Vector) -> Vec<Candidate> + Sync> = match quantization {
+        Quantization::None => {
+            let index = ProjectIndex::build(vector_set, index_type);
+            Box::new(move |query| index.search(query, k))
+        }
+        quantization => {
+            let quantized = QuantizedIndex::build(
+                quantization,
+                vector_set
+                    .iter()
+                    .map(|vector| (vector.id, &vector.point[..])),
+            );
+
+            Box::new(move |query| {
+                let shortlist: HashSet<i64> = quantized
+                    .search(&query.point, k * rescore_factor.max(1))
+                    .into_iter()
+                    .map(|(id, _)| id)
+                    .collect();
+
+                let vectors = vector_set
+                    .iter()
+                    .filter(|vector| shortlist.contains(&vector.id))
+                    .cloned()
+                    .collect();
+
+                rescore(query, vectors, k)
+            })
+        }
+    };
+
+    let recalls: Vec<f32> = queries
+        .par_iter()
+        .map(|query| {
+            let expected: HashSet<i64> = exact
+                .search(query, k)
+                .into_iter()
+                .map(|candidate| candidate.id)
+                .collect();
+
+            if expected.is_empty() {
+                return 1.0;
+            }
+
+            let found = search(query)
+                .into_iter()
+                .filter(|candidate| expected.contains(&candidate.id))
+                .count();
+
+            found as f32 / expected.len() as f32
+        })
+        .collect();
+
+    RecallReport {
+        index: index_type,
+        quantization,
+        k,
+        samples: recalls.len(),
+        recall: if recalls.is_empty() {
+            1.0
+        } else {
+            recalls.iter().sum::<f32>() / recalls.len() as f32
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::random::SplitMix64;
+    use crate::embeddings::encoder::VECTOR_SIZE;
+
+    /// Seeded random vectors with components in `[-1, 1)`.
+    fn random_vectors(count: usize, seed: u64) -> Vec<Vector> {
+        let mut random = SplitMix64::new(seed);
+
+        (0..count)
+            .map(|id| {
+                let mut point = [0.0; VECTOR_SIZE];
+                for value in point.iter_mut() {
+                    *value = random.unit() * 2.0 - 1.0;
+                }
+
+                Vector {
+                    id: id as i64,
+                    point,
+                    code: String::new(),
+                }
+            })
+            .collect()
+    }
+
+    /// The row ids of the `k` vectors most similar to a query, by sorting every score.
+    fn brute_force(vectors: &[Vector], query: &Vector, k: usize) -> Vec<i64> {
+        let mut scores: Vec<(i64, f32)> = vectors
+            .iter()
+            .map(|vector| (vector.id, cosine_similarity(&query.point, &vector.point)))
+            .collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        scores.into_iter().take(k).map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn flat_search_matches_brute_force() {
+        let vectors = random_vectors(500, 1);
+        let index = ProjectIndex::build(vectors.clone(), IndexType::Flat);
+
+        for query in &random_vectors(20, 2) {
+            let ids: Vec<i64> = index
+                .search(query, 10)
+                .into_iter()
+                .map(|candidate| candidate.id)
+                .collect();
+
+            assert_eq!(ids, brute_force(&vectors, query, 10));
+        }
+    }
+
+    #[test]
+    fn flat_search_returns_every_vector_when_asked_for_more() {
+        let vectors = random_vectors(5, 3);
+        let index = ProjectIndex::build(vectors, IndexType::Flat);
+
+        assert_eq!(index.search(&random_vectors(1, 4)[0], 10).len(), 5);
+    }
+
+    #[test]
+    fn exact_search_has_full_recall() {
+        let report = measure_recall(
+            random_vectors(500, 5),
+            IndexType::Flat,
+            Quantization::None,
+            1,
+            10,
+            20,
+        );
+
+        assert_eq!(report.samples, 20);
+        assert_eq!(report.recall, 1.0);
+    }
+
+    #[test]
+    fn rescoring_every_quantized_candidate_has_full_recall() {
+        let report = measure_recall(
+            random_vectors(500, 6),
+            IndexType::Flat,
+            Quantization::Int8,
+            50,
+            10,
+            20,
+        );
+
+        assert!(report.recall > 0.99, "recall {}", report.recall);
+    }
+
+    #[test]
+    fn binary_quantization_without_rescoring_loses_recall() {
+        let report = measure_recall(
+            random_vectors(500, 7),
+            IndexType::Flat,
+            Quantization::Binary,
+            1,
+            10,
+            20,
+        );
+
+        // Each query is one of the vectors, so it always finds itself.
+        assert!(report.recall >= 0.1, "recall {}", report.recall);
+        assert!(report.recall < 1.0, "recall {}", report.recall);
+    }
+
+    #[test]
+    fn caps_the_neighbours_compared() {
+        let report = measure_recall(
+            random_vectors(50, 8),
+            IndexType::Flat,
+            Quantization::None,
+            1,
+            MAX_RECALL_K * 10,
+            5,
+        );
+
+        assert_eq!(report.k, MAX_RECALL_K);
+        assert_eq!(report.recall, 1.0);
+    }
+}
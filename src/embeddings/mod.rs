@@ -1 +1,8 @@
+pub mod cache;
 pub mod encoder;
+pub mod flat;
+pub mod index;
+pub mod preprocess;
+pub mod quantize;
+pub mod rerank;
+pub mod tokens;
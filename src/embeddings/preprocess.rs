@@ -0,0 +1,629 @@
+use serde::{Deserialize, Serialize};
+
+/// What to do with a class of text (comments or string literals) before embedding.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TextMode {
+    /// Leave the text in place.
+    Keep,
+
+    /// Remove the text entirely.
+    Strip,
+
+    /// Remove the text from the code and append it as its own labelled section.
+    Separate,
+}
+
+/// Configures how a code block is turned into the text that gets embedded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct PreprocessConfig {
+    /// Split `camelCase` and `snake_case` identifiers into lowercase words.
+    pub split_identifiers: bool,
+
+    /// How to handle comments, including Python docstrings.
+    pub comments: TextMode,
+
+    /// How to handle string literals.
+    pub string_literals: TextMode,
+
+    /// Prepend the class and function names of the block.
+    pub prepend_context: bool,
+
+    /// Also prepend the path of the block's file, when `prepend_context` is set. Off by default,
+    /// since identical blocks at different paths (vendored copies, forks) then no longer share
+    /// their cached embedding.
+    pub prepend_file_path: bool,
+}
+
+impl Default for PreprocessConfig {
+    fn default() -> Self {
+        PreprocessConfig {
+            split_identifiers: true,
+            comments: TextMode::Keep,
+            string_literals: TextMode::Keep,
+            prepend_context: true,
+            prepend_file_path: false,
+        }
+    }
+}
+
+/// A lexical segment of a code block.
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Code(String),
+    Comment(String),
+    StringLiteral(String),
+}
+
+/// The separately embeddable parts of a code block.
+#[derive(Debug, Clone)]
+pub struct BlockParts {
+    /// The declaration of the block, e.g. `class Foo` or `fn parse(input: &str) -> Ast`.
+    pub signature: String,
+
+    /// The doc comments and docstrings of the block, if it has any.
+    pub docstring: Option<String>,
+
+    /// The code of the block after its signature, without comments.
+    pub body: String,
+}
+
+/// Splits a code block into its signature, doc comments and body.
+///
+/// The signature runs up to the opening `{` or the `:` ending a Python `def`/`class` line
+/// (whichever comes first outside of brackets), and is prefixed with the class name of methods.
+///
+/// # Arguments
+///
+/// * `block` - The code block to split.
+///
+/// # Returns
+///
+/// A `BlockParts` struct containing the signature, docstring and body of the block.
+pub fn block_parts(block: &asterisk::block::Block) -> BlockParts {
+    let mut code = String::new();
+    let mut docs = Vec::new();
+
+    for segment in segments(&block.content) {
+        match segment {
+            Segment::Code(text) | Segment::StringLiteral(text) => code.push_str(&text),
+            Segment::Comment(text) => docs.push(comment_text(&text)),
+        }
+    }
+
+    docs.retain(|doc| !doc.is_empty());
+
+    let split = signature_end(&code);
+    let mut signature = code[..split].trim().to_owned();
+    let body = code[split..].trim().to_owned();
+
+    if let (Some(class_name), Some(_)) = (&block.class_name, &block.function_name) {
+        signature = format!("{}: {}", class_name, signature);
+    }
+
+    BlockParts {
+        signature,
+        docstring: if docs.is_empty() {
+            None
+        } else {
+            Some(docs.join("\n"))
+        },
+        body,
+    }
+}
+
+/// Returns the byte index at which the signature of a piece of comment-free code ends.
+fn signature_end(code: &str) -> usize {
+    let mut depth = 0i32;
+
+    for (i, c) in code.char_indices() {
+        match c {
+            '(' | '[' | '<' => depth += 1,
+            ')' | ']' | '>' => depth -= 1,
+            '{' | ';' if depth <= 0 => return i,
+            ':' if depth <= 0 => {
+                let rest = code[i + 1..].trim_start_matches(|c: char| c == ' ' || c == '\t');
+                if rest.is_empty() || rest.starts_with('\n') || rest.starts_with('\r') {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    code.find('\n').unwrap_or(code.len())
+}
+
+/// Builds the text to embed for a code block.
+///
+/// # Arguments
+///
+/// * `block` - The code block to preprocess.
+/// * `source_file` - The path of the file the block was found in, if known.
+/// * `config` - The preprocessing steps to apply.
+///
+/// # Returns
+///
+/// The preprocessed text, ready to be passed to the embedding model.
+pub fn preprocess_block(
+    block: &asterisk::block::Block,
+    source_file: Option<&str>,
+    config: &PreprocessConfig,
+) -> String {
+    let mut output = String::new();
+
+    if config.prepend_context {
+        if let Some(source_file) = source_file.filter(|_| config.prepend_file_path) {
+            output.push_str(&format!("file: {}\n", source_file));
+        }
+        if let Some(class_name) = &block.class_name {
+            output.push_str(&format!("class: {}\n", class_name));
+        }
+        if let Some(function_name) = &block.function_name {
+            output.push_str(&format!("function: {}\n", function_name));
+        }
+    }
+
+    output.push_str(&preprocess_code(&block.content, config));
+
+    output
+}
+
+/// Applies the comment, string literal and identifier steps of `config` to raw code.
+///
+/// # Arguments
+///
+/// * `code` - The code to preprocess.
+/// * `config` - The preprocessing steps to apply.
+///
+/// # Returns
+///
+/// The preprocessed code, followed by the separated comment and string literal sections if requested.
+pub fn preprocess_code(code: &str, config: &PreprocessConfig) -> String {
+    let mut body = String::new();
+    let mut comments = Vec::new();
+    let mut string_literals = Vec::new();
+
+    for segment in segments(code) {
+        match segment {
+            Segment::Code(text) => {
+                if config.split_identifiers {
+                    body.push_str(&split_identifiers(&text));
+                } else {
+                    body.push_str(&text);
+                }
+            }
+            Segment::Comment(text) => match config.comments {
+                TextMode::Keep => body.push_str(&text),
+                TextMode::Strip => {}
+                TextMode::Separate => comments.push(comment_text(&text)),
+            },
+            Segment::StringLiteral(text) => match config.string_literals {
+                TextMode::Keep => body.push_str(&text),
+                TextMode::Strip => body.push_str("\"\""),
+                TextMode::Separate => {
+                    body.push_str("\"\"");
+                    string_literals.push(unquote(&text));
+                }
+            },
+        }
+    }
+
+    let mut output = body;
+
+    comments.retain(|comment| !comment.is_empty());
+    if !comments.is_empty() {
+        output.push_str("\ncomments: ");
+        output.push_str(&comments.join("\n"));
+    }
+
+    string_literals.retain(|literal| !literal.is_empty());
+    if !string_literals.is_empty() {
+        output.push_str("\nstrings: ");
+        output.push_str(&string_literals.join(", "));
+    }
+
+    output
+}
+
+/// Splits `camelCase`, `PascalCase` and `snake_case` identifiers in a piece of code into
+/// space separated lowercase words, leaving everything else untouched.
+///
+/// # Arguments
+///
+/// * `code` - The code to rewrite.
+pub fn split_identifiers(code: &str) -> String {
+    let mut output = String::with_capacity(code.len());
+    let mut identifier = String::new();
+
+    for c in code.chars() {
+        if c.is_alphanumeric() || c == '_' {
+            identifier.push(c);
+            continue;
+        }
+
+        output.push_str(&split_identifier(&identifier));
+        identifier.clear();
+        output.push(c);
+    }
+
+    output.push_str(&split_identifier(&identifier));
+
+    output
+}
+
+/// Splits a single identifier into lowercase words, e.g. `parseHTTPResponse_v2` into
+/// `parse http response v2`. Identifiers made of a single word are returned unchanged.
+fn split_identifier(identifier: &str) -> String {
+    let chars: Vec<char> = identifier.chars().collect();
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+
+        let previous = if i > 0 { Some(chars[i - 1]) } else { None };
+        let next = chars.get(i + 1);
+
+        let boundary = match previous {
+            Some(previous) if c.is_uppercase() => {
+                previous.is_lowercase()
+                    || previous.is_numeric()
+                    || (previous.is_uppercase() && next.map_or(false, |n| n.is_lowercase()))
+            }
+            _ => false,
+        };
+
+        if boundary && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+
+        word.push(c);
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    if words.len() <= 1 {
+        return identifier.to_owned();
+    }
+
+    words
+        .iter()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Strips comment markers from a comment, leaving only its text.
+fn comment_text(comment: &str) -> String {
+    comment
+        .lines()
+        .map(|line| {
+            line.trim()
+                .trim_start_matches("/**")
+                .trim_start_matches("/*")
+                .trim_end_matches("*/")
+                .trim_start_matches("///")
+                .trim_start_matches("//!")
+                .trim_start_matches("//")
+                .trim_start_matches('#')
+                .trim_start_matches('*')
+                .trim_matches('"')
+                .trim()
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Removes the opening and closing quote of a string literal.
+fn unquote(literal: &str) -> String {
+    let mut chars = literal.chars();
+    chars.next();
+    if literal.len() > 1 {
+        chars.next_back();
+    }
+
+    chars.as_str().to_owned()
+}
+
+/// Splits code into code, comment and string literal segments.
+///
+/// This is a language-agnostic lexer covering the languages blockoli indexes: `//` and
+/// `/* */` comments, `#` comments (but not `#[attr]`, `#!` or `#include`-style directives),
+/// double-quoted strings, single-quoted strings (but not Rust lifetimes), and Python
+/// triple-quoted docstrings, which are treated as comments.
+fn segments(code: &str) -> Vec<Segment> {
+    let chars: Vec<char> = code.chars().collect();
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut i = 0;
+
+    let flush = |current: &mut String, segments: &mut Vec<Segment>| {
+        if !current.is_empty() {
+            segments.push(Segment::Code(std::mem::take(current)));
+        }
+    };
+
+    let starts_with = |i: usize, pattern: &str| {
+        pattern
+            .chars()
+            .enumerate()
+            .all(|(offset, p)| chars.get(i + offset) == Some(&p))
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        let end = if starts_with(i, "//") {
+            Some((find_line_end(&chars, i), false))
+        } else if starts_with(i, "/*") {
+            Some((find_pattern(&chars, i + 2, "*/"), false))
+        } else if c == '#'
+            && chars
+                .get(i + 1)
+                .map_or(true, |n| n.is_whitespace() || *n == '#')
+        {
+            Some((find_line_end(&chars, i), false))
+        } else if starts_with(i, "\"\"\"") || starts_with(i, "'''") {
+            let quote: String = chars[i..i + 3].iter().collect();
+            Some((find_pattern(&chars, i + 3, &quote), false))
+        } else if c == '"' {
+            Some((find_quote(&chars, i, '"'), true))
+        } else if c == '\'' && !is_lifetime(&chars, i) {
+            Some((find_quote(&chars, i, '\''), true))
+        } else {
+            None
+        };
+
+        match end {
+            Some((end, is_string)) => {
+                flush(&mut current, &mut segments);
+                let text: String = chars[i..end].iter().collect();
+                segments.push(if is_string {
+                    Segment::StringLiteral(text)
+                } else {
+                    Segment::Comment(text)
+                });
+                i = end;
+            }
+            None => {
+                current.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    flush(&mut current, &mut segments);
+
+    segments
+}
+
+/// Returns the index of the end of the line starting at or after `start`, excluding the newline.
+fn find_line_end(chars: &[char], start: usize) -> usize {
+    chars[start..]
+        .iter()
+        .position(|&c| c == '\n')
+        .map_or(chars.len(), |offset| start + offset)
+}
+
+/// Returns the index just past the first occurrence of `pattern` at or after `start`.
+fn find_pattern(chars: &[char], start: usize, pattern: &str) -> usize {
+    let pattern: Vec<char> = pattern.chars().collect();
+
+    (start..chars.len())
+        .find(|&i| chars[i..].starts_with(&pattern))
+        .map_or(chars.len(), |i| i + pattern.len())
+}
+
+/// Returns the index just past the closing `quote` of a string literal starting at `start`,
+/// honouring backslash escapes.
+fn find_quote(chars: &[char], start: usize, quote: char) -> usize {
+    let mut i = start + 1;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+
+    chars.len()
+}
+
+/// Checks whether the single quote at `start` opens a Rust lifetime or label (`'a`, `'static`)
+/// rather than a character or string literal.
+fn is_lifetime(chars: &[char], start: usize) -> bool {
+    let identifier_length = chars[start + 1..]
+        .iter()
+        .take_while(|c| c.is_alphanumeric() || **c == '_')
+        .count();
+
+    identifier_length > 0 && chars.get(start + 1 + identifier_length) != Some(&'\'')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(text: &str) -> Segment {
+        Segment::Code(text.to_owned())
+    }
+
+    fn comment(text: &str) -> Segment {
+        Segment::Comment(text.to_owned())
+    }
+
+    fn string_literal(text: &str) -> Segment {
+        Segment::StringLiteral(text.to_owned())
+    }
+
+    #[test]
+    fn lexes_line_and_block_comments() {
+        assert_eq!(
+            segments("let x = 1; // one\n/* two */ y"),
+            vec![
+                code("let x = 1; "),
+                comment("// one"),
+                code("\n"),
+                comment("/* two */"),
+                code(" y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lexes_hash_comments_but_not_attributes() {
+        assert_eq!(
+            segments("#[derive(Debug)]\n# note\n#!/bin/sh"),
+            vec![
+                code("#[derive(Debug)]\n"),
+                comment("# note"),
+                code("\n#!/bin/sh")
+            ]
+        );
+    }
+
+    #[test]
+    fn lexes_strings_with_escaped_quotes() {
+        assert_eq!(
+            segments(r#"say("a \" b", 'c')"#),
+            vec![
+                code("say("),
+                string_literal(r#""a \" b""#),
+                code(", "),
+                string_literal("'c'"),
+                code(")"),
+            ]
+        );
+    }
+
+    #[test]
+    fn comment_markers_inside_strings_are_not_comments() {
+        assert_eq!(
+            segments(r#"url = "http://example.com""#),
+            vec![code("url = "), string_literal(r#""http://example.com""#)]
+        );
+    }
+
+    #[test]
+    fn rust_lifetimes_are_not_strings() {
+        assert_eq!(
+            segments("fn f<'a>(x: &'a str) -> char { 'x' }"),
+            vec![
+                code("fn f<'a>(x: &'a str) -> char { "),
+                string_literal("'x'"),
+                code(" }"),
+            ]
+        );
+    }
+
+    #[test]
+    fn python_docstrings_are_comments() {
+        assert_eq!(
+            segments("def f():\n    \"\"\"Does \"things\".\"\"\"\n    pass"),
+            vec![
+                code("def f():\n    "),
+                comment("\"\"\"Does \"things\".\"\"\""),
+                code("\n    pass"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_segments_run_to_the_end() {
+        assert_eq!(segments("x /* open"), vec![code("x "), comment("/* open")]);
+        assert_eq!(segments("\"open"), vec![string_literal("\"open")]);
+    }
+
+    #[test]
+    fn splits_identifiers_into_words() {
+        assert_eq!(
+            split_identifier("parseHTTPResponse_v2"),
+            "parse http response v2"
+        );
+        assert_eq!(split_identifier("snake_case_name"), "snake case name");
+        assert_eq!(split_identifier("Vector"), "Vector");
+        assert_eq!(
+            split_identifiers("let maxTokens = get_budget();"),
+            "let max tokens = get budget();"
+        );
+    }
+
+    #[test]
+    fn strips_comment_markers() {
+        assert_eq!(
+            comment_text("/// Parses input.\n/// Twice."),
+            "Parses input. Twice."
+        );
+        assert_eq!(comment_text("/**\n * Block.\n */"), "Block.");
+        assert_eq!(comment_text("# hash"), "hash");
+    }
+
+    #[test]
+    fn finds_the_end_of_signatures() {
+        let code = "fn parse(input: &str) -> Ast {\n    body()\n}";
+        assert_eq!(
+            &code[..signature_end(code)],
+            "fn parse(input: &str) -> Ast "
+        );
+
+        let code = "def parse(input: str):\n    return body()";
+        assert_eq!(&code[..signature_end(code)], "def parse(input: str):");
+    }
+
+    #[test]
+    fn separates_comments_and_strings() {
+        let config = PreprocessConfig {
+            split_identifiers: false,
+            comments: TextMode::Separate,
+            string_literals: TextMode::Separate,
+            prepend_context: false,
+            prepend_file_path: false,
+        };
+
+        assert_eq!(
+            preprocess_code("log(\"hello\"); // greet", &config),
+            "log(\"\"); \ncomments: greet\nstrings: hello"
+        );
+    }
+
+    #[test]
+    fn prepends_the_file_path_only_when_asked() {
+        let block = asterisk::block::Block {
+            node_key: "block_1".to_owned(),
+            block_type: asterisk::block::BlockType::Function,
+            content: "fn parse() {}".to_owned(),
+            class_name: None,
+            function_name: Some("parse".to_owned()),
+            outgoing_calls: Vec::new(),
+        };
+        let config = PreprocessConfig {
+            split_identifiers: false,
+            ..PreprocessConfig::default()
+        };
+
+        assert_eq!(
+            preprocess_block(&block, Some("src/parse.rs"), &config),
+            "function: parse\nfn parse() {}"
+        );
+
+        let config = PreprocessConfig {
+            prepend_file_path: true,
+            ..config
+        };
+        assert_eq!(
+            preprocess_block(&block, Some("src/parse.rs"), &config),
+            "file: src/parse.rs\nfunction: parse\nfn parse() {}"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::embeddings::encoder::VECTOR_SIZE;
+use crate::embeddings::flat::{self, normalize};
+
+/// How a project's vectors are compressed for the first stage of a search.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    /// Full-precision `f32` vectors.
+    #[default]
+    None,
+
+    /// One signed byte per dimension plus a per-vector scale, 4x smaller than `f32`.
+    Int8,
+
+    /// One sign bit per dimension, 32x smaller than `f32`.
+    Binary,
+}
+
+impl Quantization {
+    /// The name of the quantization, as stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quantization::None => "none",
+            Quantization::Int8 => "int8",
+            Quantization::Binary => "binary",
+        }
+    }
+
+    /// Parses a quantization from its name, falling back to no quantization for unknown names.
+    pub fn parse(name: &str) -> Self {
+        match name {
+            "int8" => Quantization::Int8,
+            "binary" => Quantization::Binary,
+            _ => Quantization::None,
+        }
+    }
+
+    /// The number of bytes a quantized vector takes up.
+    pub fn code_size(&self) -> usize {
+        match self {
+            Quantization::None => VECTOR_SIZE * 4,
+            Quantization::Int8 => 4 + VECTOR_SIZE,
+            Quantization::Binary => VECTOR_SIZE / 8,
+        }
+    }
+
+    /// Quantizes a vector.
+    ///
+    /// Int8 codes are a little-endian `f32` scale followed by one `i8` per dimension of the
+    /// normalised vector divided by that scale. Binary codes are the sign bits of the vector,
+    /// packed eight dimensions per byte.
+    ///
+    /// # Arguments
+    ///
+    /// * `vector` - The full-precision vector to quantize.
+    pub fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        match self {
+            Quantization::None => vector.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            Quantization::Int8 => {
+                let vector = normalize(vector);
+                let scale = vector.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+                let scale = if scale == 0.0 { 1.0 } else { scale };
+
+                let mut code = Vec::with_capacity(self.code_size());
+                code.extend_from_slice(&scale.to_le_bytes());
+                code.extend(
+                    vector
+                        .iter()
+                        .map(|x| (x / scale * 127.0).round().clamp(-127.0, 127.0) as i8 as u8),
+                );
+
+                code
+            }
+            Quantization::Binary => vector
+                .chunks(8)
+                .map(|chunk| {
+                    chunk.iter().enumerate().fold(0u8, |byte, (bit, x)| {
+                        if *x > 0.0 {
+                            byte | (1 << bit)
+                        } else {
+                            byte
+                        }
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A compressed, brute-force index used to cheaply shortlist candidates that are then
+/// rescored at full precision.
+#[derive(Debug, Clone)]
+pub struct QuantizedIndex {
+    /// The quantization of the codes.
+    pub quantization: Quantization,
+
+    /// The row id of each vector.
+    pub ids: Vec<i64>,
+
+    /// The quantized vectors, `quantization.code_size()` bytes per row.
+    pub codes: Vec<u8>,
+}
+
+impl QuantizedIndex {
+    /// Builds a quantized index from full-precision vectors.
+    ///
+    /// # Arguments
+    ///
+    /// * `quantization` - The quantization to apply.
+    /// * `vectors` - Pairs of row id and full-precision vector.
+    pub fn build<'a>(
+        quantization: Quantization,
+        vectors: impl Iterator<Item = (i64, &'a [f32])>,
+    ) -> Self {
+        let mut index = QuantizedIndex {
+            quantization,
+            ids: Vec::new(),
+            codes: Vec::new(),
+        };
+
+        for (id, vector) in vectors {
+            index.ids.push(id);
+            index.codes.extend(quantization.encode(vector));
+        }
+
+        index
+    }
+
+    /// The approximate number of bytes the index takes up in memory.
+    pub fn size_bytes(&self) -> usize {
+        self.ids.capacity() * std::mem::size_of::<i64>() + self.codes.capacity()
+    }
+
+    /// Finds the `count` vectors with the highest approximate similarity to a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The full-precision query vector.
+    /// * `count` - The number of candidates to return.
+    ///
+    /// # Returns
+    ///
+    /// Pairs of row id and approximate cosine similarity, most similar first.
+    pub fn search(&self, query: &[f32], count: usize) -> Vec<(i64, f32)> {
+        let code_size = self.quantization.code_size();
+        let query = normalize(query);
+
+        let scores: Vec<(usize, f32)> = match self.quantization {
+            Quantization::None => self
+                .codes
+                .par_chunks_exact(code_size)
+                .map(|code| {
+                    let vector: Vec<f32> = code
+                        .chunks_exact(4)
+                        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                        .collect();
+                    flat::dot(&normalize(&vector), &query)
+                })
+                .enumerate()
+                .collect(),
+            Quantization::Int8 => self
+                .codes
+                .par_chunks_exact(code_size)
+                .map(|code| {
+                    let scale = f32::from_le_bytes(code[..4].try_into().unwrap());
+                    let dot: f32 = code[4..]
+                        .iter()
+                        .zip(query.iter())
+                        .map(|(q, y)| (*q as i8) as f32 * y)
+                        .sum();
+
+                    dot * scale / 127.0
+                })
+                .enumerate()
+                .collect(),
+            Quantization::Binary => {
+                let query_code = self.quantization.encode(&query);
+
+                self.codes
+                    .par_chunks_exact(code_size)
+                    .map(|code| {
+                        let hamming: u32 = code
+                            .iter()
+                            .zip(query_code.iter())
+                            .map(|(a, b)| (a ^ b).count_ones())
+                            .sum();
+
+                        1.0 - 2.0 * hamming as f32 / VECTOR_SIZE as f32
+                    })
+                    .enumerate()
+                    .collect()
+            }
+        };
+
+        flat::top_k(scores, count)
+            .into_iter()
+            .map(|(row, score)| (self.ids[row], score))
+            .collect()
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::embeddings::encoder::Candidate;
+use crate::embeddings::preprocess;
+
+/// A second-stage scoring function that rescores the candidates returned by the vector search.
+///
+/// Rerankers see the query text and the full code of every candidate, so they can afford to be
+/// far more expensive per candidate than the first-stage nearest-neighbour search.
+pub trait Reranker: Send + Sync {
+    /// Scores each candidate against the query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The search query, as sent by the user.
+    /// * `candidates` - The candidates to rescore, with their first-stage scores.
+    ///
+    /// # Returns
+    ///
+    /// One score per candidate, in the same order as `candidates`. Higher is better.
+    fn rerank(&self, query: &str, candidates: &[Candidate]) -> Result<Vec<f32>>;
+}
+
+/// The rerankers that can be selected per search request.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RerankerKind {
+    /// Blends the vector similarity with a BM25 keyword score. See `LexicalReranker`.
+    Lexical,
+}
+
+impl RerankerKind {
+    /// Creates the reranker of this kind.
+    pub fn reranker(&self) -> Box<dyn Reranker> {
+        match self {
+            RerankerKind::Lexical => Box::new(LexicalReranker::default()),
+        }
+    }
+}
+
+/// Reranks candidates by blending their vector similarity with a BM25 score of the query terms
+/// against the candidate's code, with identifiers split into words.
+///
+/// Embeddings are good at meaning but fuzzy on exact names; the keyword score pulls up
+/// candidates that literally mention what the query asks for.
+pub struct LexicalReranker {
+    /// The weight of the first-stage vector similarity, between `0.0` and `1.0`.
+    /// The BM25 score gets the remaining weight.
+    pub semantic_weight: f32,
+
+    /// BM25 term frequency saturation.
+    pub k1: f32,
+
+    /// BM25 document length normalisation.
+    pub b: f32,
+}
+
+impl Default for LexicalReranker {
+    fn default() -> Self {
+        LexicalReranker {
+            semantic_weight: 0.5,
+            k1: 1.2,
+            b: 0.75,
+        }
+    }
+}
+
+impl Reranker for LexicalReranker {
+    fn rerank(&self, query: &str, candidates: &[Candidate]) -> Result<Vec<f32>> {
+        let query_terms: HashSet<String> = terms(query).into_iter().collect();
+        let documents: Vec<Vec<String>> = candidates
+            .iter()
+            .map(|candidate| terms(&candidate.code))
+            .collect();
+
+        let average_length = documents.iter().map(|terms| terms.len()).sum::<usize>() as f32
+            / documents.len().max(1) as f32;
+
+        let mut document_frequency: HashMap<&str, usize> = HashMap::new();
+        for document in &documents {
+            let unique: HashSet<&str> = document.iter().map(|term| term.as_str()).collect();
+            for term in unique {
+                *document_frequency.entry(term).or_default() += 1;
+            }
+        }
+
+        let total = documents.len() as f32;
+        let keyword_scores: Vec<f32> = documents
+            .iter()
+            .map(|document| {
+                let mut term_frequency: HashMap<&str, usize> = HashMap::new();
+                for term in document {
+                    *term_frequency.entry(term.as_str()).or_default() += 1;
+                }
+
+                let length_norm =
+                    1.0 - self.b + self.b * document.len() as f32 / average_length.max(1.0);
+
+                query_terms
+                    .iter()
+                    .filter_map(|term| {
+                        let frequency = *term_frequency.get(term.as_str())? as f32;
+                        let containing = document_frequency[term.as_str()] as f32;
+                        let idf = ((total - containing + 0.5) / (containing + 0.5) + 1.0).ln();
+
+                        Some(
+                            idf * frequency * (self.k1 + 1.0) / (frequency + self.k1 * length_norm),
+                        )
+                    })
+                    .sum()
+            })
+            .collect();
+
+        let max_keyword_score = keyword_scores.iter().cloned().fold(0.0, f32::max);
+
+        Ok(candidates
+            .iter()
+            .zip(keyword_scores)
+            .map(|(candidate, keyword_score)| {
+                let keyword_score = if max_keyword_score > 0.0 {
+                    keyword_score / max_keyword_score
+                } else {
+                    0.0
+                };
+
+                self.semantic_weight * candidate.score
+                    + (1.0 - self.semantic_weight) * keyword_score
+            })
+            .collect())
+    }
+}
+
+/// Splits text into lowercase word terms, splitting identifiers into their words.
+fn terms(text: &str) -> Vec<String> {
+    preprocess::split_identifiers(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.len() > 1)
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: i64, code: &str, score: f32) -> Candidate {
+        Candidate {
+            id,
+            code: code.to_owned(),
+            point: Vec::new(),
+            score,
+            centrality: 0.0,
+        }
+    }
+
+    #[test]
+    fn terms_split_identifiers_and_drop_single_characters() {
+        assert_eq!(
+            terms("parseHttpResponse(x)"),
+            vec!["parse", "http", "response"]
+        );
+    }
+
+    #[test]
+    fn keyword_matches_outrank_closer_vectors() {
+        let candidates = vec![
+            candidate(0, "fn load_config() {}", 0.9),
+            candidate(1, "fn parse_response() {}", 0.1),
+        ];
+
+        let scores = LexicalReranker::default()
+            .rerank("parseResponse", &candidates)
+            .unwrap();
+
+        assert!(scores[1] > scores[0]);
+    }
+
+    #[test]
+    fn keyword_scores_are_normalised() {
+        let reranker = LexicalReranker {
+            semantic_weight: 0.0,
+            ..LexicalReranker::default()
+        };
+        let candidates = vec![
+            candidate(0, "fn load_config() {}", 0.9),
+            candidate(1, "fn parse_response() {}", 0.1),
+        ];
+
+        let scores = reranker.rerank("parse response", &candidates).unwrap();
+
+        assert_eq!(scores[0], 0.0);
+        assert!((scores[1] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn without_keyword_matches_only_the_vector_score_counts() {
+        let candidates = vec![
+            candidate(0, "fn load_config() {}", 0.8),
+            candidate(1, "fn parse_response() {}", 0.2),
+        ];
+
+        let scores = LexicalReranker::default()
+            .rerank("unrelated", &candidates)
+            .unwrap();
+
+        assert!((scores[0] - 0.4).abs() < 1e-6);
+        assert!((scores[1] - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn no_candidates() {
+        let scores = LexicalReranker::default().rerank("query", &[]).unwrap();
+
+        assert!(scores.is_empty());
+    }
+}
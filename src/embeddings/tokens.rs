@@ -0,0 +1,122 @@
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use rayon::prelude::*;
+use serde::Serialize;
+use tokenizers::Tokenizer;
+
+use crate::embeddings::encoder::{model_options, MODEL};
+
+/// Where fastembed unpacks the embedding model, tokenizer included: a directory named after the
+/// model in the cache directory the model is loaded with.
+fn embedding_tokenizer_path() -> PathBuf {
+    let options = model_options();
+
+    options
+        .cache_dir
+        .join(options.model_name.to_string())
+        .join("tokenizer.json")
+}
+
+/// The tokenizer of the embedding model, without its truncation to the model's input length.
+/// Override its path with the `BLOCKOLI_EMBEDDING_TOKENIZER` environment variable, or set it to
+/// an empty string to estimate token counts without loading the model.
+static EMBEDDING_TOKENIZER: Lazy<Option<Tokenizer>> = Lazy::new(|| {
+    match std::env::var("BLOCKOLI_EMBEDDING_TOKENIZER") {
+        Ok(path) if path.is_empty() => None,
+        Ok(path) => load_tokenizer(&path),
+        Err(_) => {
+            // The tokenizer is downloaded along with the model.
+            Lazy::force(&MODEL);
+
+            load_tokenizer(&embedding_tokenizer_path().to_string_lossy())
+        }
+    }
+});
+
+/// The tokenizer of the LLM the blocks are fed to, if one is configured with the
+/// `BLOCKOLI_LLM_TOKENIZER` environment variable: either the path of a `tokenizer.json` file, or
+/// the name of a tokenizer on the Hugging Face Hub, e.g. `Xenova/gpt-4o`.
+static LLM_TOKENIZER: Lazy<Option<Tokenizer>> = Lazy::new(|| {
+    let tokenizer = std::env::var("BLOCKOLI_LLM_TOKENIZER").ok()?;
+
+    load_tokenizer(&tokenizer)
+});
+
+/// The token counts of a block.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenCount {
+    /// The number of tokens according to the embedding model's tokenizer.
+    pub embedding: Option<usize>,
+
+    /// The number of tokens according to the configured LLM tokenizer.
+    pub llm: Option<usize>,
+}
+
+impl TokenCount {
+    /// The number of tokens a budget is charged for: the LLM count if there is one, then the
+    /// embedding model's count. Blocks indexed before tokens were counted are counted from
+    /// their text.
+    pub fn budget(&self, text: &str) -> usize {
+        self.llm
+            .or(self.embedding)
+            .unwrap_or_else(|| count_tokens(text))
+    }
+}
+
+fn load_tokenizer(tokenizer: &str) -> Option<Tokenizer> {
+    let loaded = if Path::new(tokenizer).exists() {
+        Tokenizer::from_file(tokenizer)
+    } else {
+        Tokenizer::from_pretrained(tokenizer, None)
+    };
+
+    let mut tokenizer = match loaded {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("Unable to load tokenizer {}: {}", tokenizer, err);
+            return None;
+        }
+    };
+
+    // Blocks are counted whole, even when they are longer than a model's input.
+    tokenizer.with_truncation(None).ok()?;
+    tokenizer.with_padding(None);
+
+    Some(tokenizer)
+}
+
+fn count_with(tokenizer: &Option<Tokenizer>, text: &str) -> Option<usize> {
+    let encoding = tokenizer.as_ref()?.encode(text, false).ok()?;
+
+    Some(encoding.len())
+}
+
+/// Estimates the number of tokens of a text, at about four characters per token.
+fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Counts the tokens of a text with the LLM tokenizer if one is configured, then the embedding
+/// model's tokenizer, then an estimate.
+pub fn count_tokens(text: &str) -> usize {
+    count_with(&LLM_TOKENIZER, text)
+        .or_else(|| count_with(&EMBEDDING_TOKENIZER, text))
+        .unwrap_or_else(|| estimate_tokens(text))
+}
+
+/// Counts the tokens of blocks with the embedding model's tokenizer, and the LLM tokenizer if
+/// one is configured.
+///
+/// # Arguments
+///
+/// * `texts` - The contents of the blocks.
+pub fn count_block_tokens(texts: &[String]) -> Vec<TokenCount> {
+    texts
+        .par_iter()
+        .map(|text| TokenCount {
+            embedding: count_with(&EMBEDDING_TOKENIZER, text),
+            llm: count_with(&LLM_TOKENIZER, text),
+        })
+        .collect()
+}
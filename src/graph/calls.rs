@@ -0,0 +1,333 @@
+use std::collections::{HashMap, HashSet};
+
+use asterisk::block::Block;
+use serde::Serialize;
+
+use crate::vector_store::sqlite::StoredBlock;
+
+/// A call from one block to a function, resolved to the block defining it when possible.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CallEdge {
+    /// The row id of the calling block.
+    pub caller: i64,
+    pub caller_key: String,
+
+    /// The row id of the called block, or `None` if no block of the project defines the
+    /// function (library and builtin calls).
+    pub callee: Option<i64>,
+    pub callee_key: Option<String>,
+
+    /// The called function, as written at the call site.
+    pub callee_name: String,
+}
+
+/// The direct callers and callees of a block.
+#[derive(Serialize, Debug)]
+pub struct BlockCalls {
+    /// The calls made to the block.
+    pub callers: Vec<CallEdge>,
+
+    /// The calls made by the block, including calls to functions outside the project.
+    pub callees: Vec<CallEdge>,
+}
+
+/// Splits a call as recorded by the indexer, e.g. `self.store.insert(x)` or `Vec::new`, into
+/// its qualifier (`self.store`, `Vec`) and the name of the called function (`insert`, `new`).
+pub fn split_call(call: &str) -> (Option<&str>, &str) {
+    let call = call.split('(').next().unwrap_or_default().trim();
+
+    let separator = ["::", ".", "->"]
+        .iter()
+        .filter_map(|separator| {
+            call.rfind(separator)
+                .map(|index| (index, index + separator.len()))
+        })
+        .max();
+
+    match separator {
+        Some((end, start)) => (Some(call[..end].trim()), call[start..].trim()),
+        None => (None, call),
+    }
+}
+
+/// Adds the calls recorded by the indexer for a whole directory to the outgoing calls of the
+/// blocks making them, so that `resolve_calls` sees them.
+///
+/// # Arguments
+///
+/// * `blocks` - The blocks returned by the indexer.
+/// * `calls` - The names of the functions each function calls, keyed by the node key or the
+///   name of the calling function. Calls that are already outgoing calls of the block are skipped.
+pub fn merge_indexed_calls<C, K, N>(blocks: &mut [Block], calls: C)
+where
+    C: IntoIterator<Item = (K, N)>,
+    K: AsRef<str>,
+    N: IntoIterator,
+    N::Item: AsRef<str>,
+{
+    let mut callers: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, block) in blocks.iter().enumerate() {
+        callers.entry(&block.node_key).or_default().push(index);
+        if let Some(function_name) = &block.function_name {
+            callers.entry(function_name).or_default().push(index);
+        }
+    }
+
+    let mut added: Vec<(usize, String)> = Vec::new();
+    for (caller, callees) in calls {
+        let Some(indexes) = callers.get(caller.as_ref()) else {
+            continue;
+        };
+
+        let callees: Vec<String> = callees
+            .into_iter()
+            .map(|callee| callee.as_ref().to_owned())
+            .collect();
+
+        for &index in indexes {
+            added.extend(callees.iter().map(|callee| (index, callee.to_owned())));
+        }
+    }
+
+    for (index, callee) in added {
+        let outgoing_calls = &mut blocks[index].outgoing_calls;
+        if !outgoing_calls.contains(&callee) {
+            outgoing_calls.push(callee);
+        }
+    }
+}
+
+/// Resolves the outgoing calls of a project's blocks to the blocks defining the called functions.
+///
+/// A call resolves to the functions of the project with the called name. When several functions
+/// share the name, the candidates are narrowed down, in order, to the ones of the class the call
+/// is qualified with (`Store::insert`), of the caller's own class for unqualified and `self` calls,
+/// and of the caller's file. Calls that stay ambiguous get an edge to every remaining candidate.
+///
+/// # Arguments
+///
+/// * `blocks` - Every block of a project.
+///
+/// # Returns
+///
+/// One edge per distinct caller, callee and called name.
+pub fn resolve_calls(blocks: &[StoredBlock]) -> Vec<CallEdge> {
+    let mut functions: HashMap<&str, Vec<&StoredBlock>> = HashMap::new();
+    for block in blocks {
+        if let Some(function_name) = &block.block.function_name {
+            functions.entry(function_name).or_default().push(block);
+        }
+    }
+
+    let mut edges = Vec::new();
+    let mut seen: HashSet<(i64, Option<i64>, &str)> = HashSet::new();
+
+    for caller in blocks {
+        for call in &caller.block.outgoing_calls {
+            let (qualifier, name) = split_call(call);
+            if name.is_empty() {
+                continue;
+            }
+
+            let candidates = functions
+                .get(name)
+                .map(|candidates| narrow_candidates(caller, qualifier, candidates))
+                .unwrap_or_default();
+
+            if candidates.is_empty() {
+                if seen.insert((caller.id, None, name)) {
+                    edges.push(CallEdge {
+                        caller: caller.id,
+                        caller_key: caller.block.node_key.to_owned(),
+                        callee: None,
+                        callee_key: None,
+                        callee_name: name.to_owned(),
+                    });
+                }
+                continue;
+            }
+
+            for callee in candidates {
+                if seen.insert((caller.id, Some(callee.id), name)) {
+                    edges.push(CallEdge {
+                        caller: caller.id,
+                        caller_key: caller.block.node_key.to_owned(),
+                        callee: Some(callee.id),
+                        callee_key: Some(callee.block.node_key.to_owned()),
+                        callee_name: name.to_owned(),
+                    });
+                }
+            }
+        }
+    }
+
+    edges
+}
+
+/// Narrows down the functions a call may resolve to. See `resolve_calls`.
+fn narrow_candidates<'a>(
+    caller: &StoredBlock,
+    qualifier: Option<&str>,
+    candidates: &[&'a StoredBlock],
+) -> Vec<&'a StoredBlock> {
+    let mut candidates = candidates.to_vec();
+
+    let class_name = match qualifier {
+        None | Some("self") | Some("this") | Some("Self") => caller.block.class_name.as_deref(),
+        Some(qualifier) => Some(split_call(qualifier).1),
+    };
+
+    let file_path = caller
+        .location
+        .as_ref()
+        .map(|location| location.file_path.as_str());
+
+    let narrowings: [&dyn Fn(&StoredBlock) -> bool; 2] = [
+        &|block| class_name.is_some() && block.block.class_name.as_deref() == class_name,
+        &|block| {
+            file_path.is_some()
+                && block
+                    .location
+                    .as_ref()
+                    .map(|location| location.file_path.as_str())
+                    == file_path
+        },
+    ];
+
+    for narrowing in narrowings {
+        if candidates.len() <= 1 {
+            break;
+        }
+
+        let narrowed: Vec<&StoredBlock> = candidates
+            .iter()
+            .copied()
+            .filter(|block| narrowing(block))
+            .collect();
+
+        if !narrowed.is_empty() {
+            candidates = narrowed;
+        }
+    }
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::BlockLocation;
+
+    fn function(id: i64, class_name: Option<&str>, name: &str, calls: &[&str]) -> StoredBlock {
+        let mut block = StoredBlock::for_test(id, class_name, Some(name), "");
+        block.block.outgoing_calls = calls.iter().map(|call| call.to_string()).collect();
+        block
+    }
+
+    fn in_file(mut block: StoredBlock, file_path: &str) -> StoredBlock {
+        block.location = Some(BlockLocation {
+            file_path: file_path.to_owned(),
+            start_line: 1,
+            end_line: 1,
+            start_byte: 0,
+            end_byte: 0,
+            language: "rust".to_owned(),
+        });
+        block
+    }
+
+    /// The `(caller, callee)` pairs of a project's resolved edges.
+    fn pairs(blocks: &[StoredBlock]) -> Vec<(i64, Option<i64>)> {
+        resolve_calls(blocks)
+            .into_iter()
+            .map(|edge| (edge.caller, edge.callee))
+            .collect()
+    }
+
+    #[test]
+    fn splits_calls_into_qualifier_and_name() {
+        assert_eq!(split_call("parse"), (None, "parse"));
+        assert_eq!(split_call("Vec::new()"), (Some("Vec"), "new"));
+        assert_eq!(
+            split_call("self.store.insert(x, y)"),
+            (Some("self.store"), "insert")
+        );
+        assert_eq!(split_call("node->next"), (Some("node"), "next"));
+    }
+
+    #[test]
+    fn unknown_functions_have_no_callee() {
+        let blocks = vec![function(1, None, "main", &["println", "run"])];
+
+        let edges = resolve_calls(&blocks);
+
+        assert_eq!(edges.len(), 2);
+        assert!(edges.iter().all(|edge| edge.callee.is_none()));
+        assert_eq!(edges[0].callee_name, "println");
+    }
+
+    #[test]
+    fn qualified_calls_resolve_to_their_class() {
+        let blocks = vec![
+            function(1, None, "main", &["Store::insert"]),
+            function(2, Some("Store"), "insert", &[]),
+            function(3, Some("Cache"), "insert", &[]),
+        ];
+
+        assert_eq!(pairs(&blocks), vec![(1, Some(2))]);
+    }
+
+    #[test]
+    fn self_calls_resolve_to_the_callers_class() {
+        let blocks = vec![
+            function(1, Some("Cache"), "get", &["self.insert(key)"]),
+            function(2, Some("Store"), "insert", &[]),
+            function(3, Some("Cache"), "insert", &[]),
+        ];
+
+        assert_eq!(pairs(&blocks), vec![(1, Some(3))]);
+    }
+
+    #[test]
+    fn ambiguous_calls_prefer_the_callers_file() {
+        let blocks = vec![
+            in_file(function(1, None, "main", &["helper"]), "src/main.rs"),
+            in_file(function(2, None, "helper", &[]), "src/lib.rs"),
+            in_file(function(3, None, "helper", &[]), "src/main.rs"),
+        ];
+
+        assert_eq!(pairs(&blocks), vec![(1, Some(3))]);
+    }
+
+    #[test]
+    fn calls_that_stay_ambiguous_resolve_to_every_candidate() {
+        let blocks = vec![
+            function(1, None, "main", &["helper", "helper()"]),
+            function(2, None, "helper", &[]),
+            function(3, None, "helper", &[]),
+        ];
+
+        assert_eq!(pairs(&blocks), vec![(1, Some(2)), (1, Some(3))]);
+    }
+
+    #[test]
+    fn merges_indexed_calls_into_outgoing_calls() {
+        let mut blocks = vec![
+            function(1, None, "main", &["run"]).block,
+            function(2, None, "run", &[]).block,
+        ];
+        let indexed = HashMap::from([
+            (
+                "main".to_owned(),
+                vec!["run".to_owned(), "setup".to_owned()],
+            ),
+            ("block_2".to_owned(), vec!["step".to_owned()]),
+            ("unknown".to_owned(), vec!["ignored".to_owned()]),
+        ]);
+
+        merge_indexed_calls(&mut blocks, indexed);
+
+        assert_eq!(blocks[0].outgoing_calls, vec!["run", "setup"]);
+        assert_eq!(blocks[1].outgoing_calls, vec!["step"]);
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::graph::calls::CallEdge;
+
+/// The probability of following a call rather than jumping to a random block.
+const DAMPING: f64 = 0.85;
+
+/// The maximum number of PageRank iterations.
+const MAX_ITERATIONS: usize = 100;
+
+/// The total change in PageRank under which the iteration has converged.
+const TOLERANCE: f64 = 1e-9;
+
+/// How central a block is to its project's call graph.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Centrality {
+    /// The number of distinct blocks calling the block, recursive calls aside.
+    pub in_degree: usize,
+
+    /// The PageRank of the block. The PageRanks of a project sum to 1.
+    pub pagerank: f64,
+
+    /// The PageRank on a log scale between the project's least (`0`) and most (`1`) central
+    /// blocks, for blending into search scores.
+    pub score: f32,
+}
+
+/// Computes the in-degree and PageRank of every block of a project from its call edges.
+///
+/// Calls to functions outside the project and recursive calls are ignored, and a block calling
+/// another several times counts once. Blocks that call nothing spread their rank over every
+/// block, as if they called them all.
+///
+/// # Arguments
+///
+/// * `ids` - The row ids of every block of the project.
+/// * `edges` - The call edges of the project.
+///
+/// # Returns
+///
+/// The centrality of each block, by row id.
+pub fn compute_centrality(ids: &[i64], edges: &[CallEdge]) -> HashMap<i64, Centrality> {
+    let count = ids.len();
+    if count == 0 {
+        return HashMap::new();
+    }
+
+    let index: HashMap<i64, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+
+    let calls: HashSet<(usize, usize)> = edges
+        .iter()
+        .filter_map(|edge| {
+            let caller = *index.get(&edge.caller)?;
+            let callee = *index.get(&edge.callee?)?;
+            (caller != callee).then_some((caller, callee))
+        })
+        .collect();
+
+    let mut callers: Vec<Vec<usize>> = vec![Vec::new(); count];
+    let mut out_degrees = vec![0usize; count];
+    for (caller, callee) in &calls {
+        callers[*callee].push(*caller);
+        out_degrees[*caller] += 1;
+    }
+
+    let mut ranks = vec![1.0 / count as f64; count];
+    for _ in 0..MAX_ITERATIONS {
+        let dangling: f64 = (0..count)
+            .filter(|block| out_degrees[*block] == 0)
+            .map(|block| ranks[block])
+            .sum();
+        let base = (1.0 - DAMPING + DAMPING * dangling) / count as f64;
+
+        let next: Vec<f64> = callers
+            .iter()
+            .map(|callers| {
+                base + DAMPING
+                    * callers
+                        .iter()
+                        .map(|caller| ranks[*caller] / out_degrees[*caller] as f64)
+                        .sum::<f64>()
+            })
+            .collect();
+
+        let change: f64 = next
+            .iter()
+            .zip(&ranks)
+            .map(|(next, rank)| (next - rank).abs())
+            .sum();
+        ranks = next;
+
+        if change < TOLERANCE {
+            break;
+        }
+    }
+
+    let min = ranks.iter().copied().fold(f64::INFINITY, f64::min).ln();
+    let max = ranks.iter().copied().fold(0.0, f64::max).ln();
+
+    ids.iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let score = if max > min {
+                ((ranks[i].ln() - min) / (max - min)) as f32
+            } else {
+                0.0
+            };
+
+            let centrality = Centrality {
+                in_degree: callers[i].len(),
+                pagerank: ranks[i],
+                score,
+            };
+
+            (*id, centrality)
+        })
+        .collect()
+}
+
+/// A block of a project, ranked by its centrality.
+#[derive(Serialize, Debug)]
+pub struct CentralBlock {
+    /// The row id of the block.
+    pub id: i64,
+    pub node_key: String,
+    pub function_name: Option<String>,
+    pub class_name: Option<String>,
+    pub file_path: Option<String>,
+
+    #[serde(flatten)]
+    pub centrality: Centrality,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(caller: i64, callee: Option<i64>) -> CallEdge {
+        CallEdge {
+            caller,
+            caller_key: format!("block_{}", caller),
+            callee,
+            callee_key: callee.map(|callee| format!("block_{}", callee)),
+            callee_name: "f".to_owned(),
+        }
+    }
+
+    #[test]
+    fn called_blocks_rank_highest() {
+        let edges = vec![edge(1, Some(4)), edge(2, Some(4)), edge(3, Some(4))];
+
+        let centrality = compute_centrality(&[1, 2, 3, 4], &edges);
+
+        assert_eq!(centrality[&4].in_degree, 3);
+        assert_eq!(centrality[&4].score, 1.0);
+        for id in [1, 2, 3] {
+            assert_eq!(centrality[&id].in_degree, 0);
+            assert_eq!(centrality[&id].score, 0.0);
+            assert!(centrality[&id].pagerank < centrality[&4].pagerank);
+        }
+
+        let total: f64 = centrality.values().map(|block| block.pagerank).sum();
+        assert!((total - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ignores_recursive_repeated_and_external_calls() {
+        let edges = vec![
+            edge(1, Some(1)),
+            edge(1, Some(2)),
+            edge(1, Some(2)),
+            edge(1, None),
+            edge(1, Some(99)),
+        ];
+
+        let centrality = compute_centrality(&[1, 2], &edges);
+
+        assert_eq!(centrality[&1].in_degree, 0);
+        assert_eq!(centrality[&2].in_degree, 1);
+        assert!(centrality[&2].pagerank > centrality[&1].pagerank);
+    }
+
+    #[test]
+    fn without_calls_every_block_ranks_the_same() {
+        let centrality = compute_centrality(&[1, 2, 3, 4], &[]);
+
+        for block in centrality.values() {
+            assert!((block.pagerank - 0.25).abs() < 1e-9);
+            assert_eq!(block.score, 0.0);
+        }
+    }
+
+    #[test]
+    fn no_blocks() {
+        assert!(compute_centrality(&[], &[edge(1, Some(2))]).is_empty());
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::embeddings::tokens::count_tokens;
+use crate::vector_store::sqlite::StoredBlock;
+
+/// Options of a context assembly.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ContextOptions {
+    /// The token budget of the assembled context, file headers included.
+    pub max_tokens: usize,
+
+    /// The number of search hits to expand.
+    pub hits: usize,
+
+    /// Whether to add the functions each hit calls.
+    pub callees: bool,
+
+    /// Whether to add the functions calling each hit.
+    pub callers: bool,
+
+    /// The maximum number of callees and of callers added per hit.
+    pub neighbours: usize,
+
+    /// Whether to add the block of the class each hit is a method of.
+    pub class_context: bool,
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        ContextOptions {
+            max_tokens: 4000,
+            hits: 5,
+            callees: true,
+            callers: true,
+            neighbours: 3,
+            class_context: true,
+        }
+    }
+}
+
+/// Why a block is part of a context.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextRole {
+    /// The block matched the query.
+    Hit,
+
+    /// The block is called by a hit.
+    Callee,
+
+    /// The block calls a hit.
+    Caller,
+
+    /// The block is the class a hit is a method of.
+    Class,
+}
+
+impl ContextRole {
+    /// How much a block of this role is worth relative to the hit it was added for.
+    fn weight(self) -> f32 {
+        match self {
+            ContextRole::Hit => 1.0,
+            ContextRole::Callee => 0.6,
+            ContextRole::Class => 0.5,
+            ContextRole::Caller => 0.4,
+        }
+    }
+}
+
+/// A block considered for a context.
+#[derive(Debug, Clone)]
+pub struct ContextCandidate {
+    /// The row id of the block.
+    pub id: i64,
+    pub role: ContextRole,
+
+    /// The rank of the search hit the block was added for, starting at 0.
+    pub hit: usize,
+
+    /// The search score of the hit the block was added for.
+    pub score: f32,
+}
+
+/// A block packed into a context.
+#[derive(Serialize, Debug)]
+pub struct ContextBlock {
+    pub id: i64,
+    pub node_key: String,
+    pub function_name: Option<String>,
+    pub class_name: Option<String>,
+    pub file_path: Option<String>,
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+    pub role: ContextRole,
+
+    /// The search score of the hit the block was added for.
+    pub score: f32,
+
+    /// The number of tokens of the block's content.
+    pub tokens: usize,
+}
+
+/// A prompt-ready context.
+#[derive(Serialize, Debug)]
+pub struct Context {
+    /// The packed blocks, grouped by file under a header naming the file, with each block's
+    /// line span and the reason it was included.
+    pub context: String,
+
+    /// The packed blocks, in the order they appear in `context`.
+    pub blocks: Vec<ContextBlock>,
+
+    /// The number of tokens of `context`, as the sum of its blocks, headers and fences.
+    pub tokens: usize,
+    pub max_tokens: usize,
+
+    /// The number of candidate blocks that didn't fit the budget.
+    pub omitted: usize,
+}
+
+/// A block packed into a context, with the candidate it was packed for.
+type PackedBlock<'a> = (&'a StoredBlock, &'a ContextCandidate);
+
+/// Packs candidate blocks into a context within a token budget.
+///
+/// Candidates are deduplicated, keeping the most valuable role of each block, and packed in
+/// order of value: the hits in rank order first, then the blocks added for them, weighted by
+/// their role. Blocks that don't fit the remaining budget are skipped, so smaller blocks further
+/// down the list may still make it in. The packed blocks are then grouped by file, files in the
+/// order of their best block, and ordered by position within each file so they read naturally.
+///
+/// # Arguments
+///
+/// * `candidates` - The hits of a search and the blocks of the call graph around them.
+/// * `blocks` - The blocks of the candidates, by row id.
+/// * `max_tokens` - The token budget, file headers included.
+pub fn assemble(
+    candidates: Vec<ContextCandidate>,
+    blocks: &HashMap<i64, StoredBlock>,
+    max_tokens: usize,
+) -> Context {
+    let value = |candidate: &ContextCandidate| candidate.role.weight() / (candidate.hit + 1) as f32;
+
+    let mut best: HashMap<i64, ContextCandidate> = HashMap::new();
+    for candidate in candidates {
+        if !blocks.contains_key(&candidate.id) {
+            continue;
+        }
+
+        match best.get(&candidate.id) {
+            Some(existing) if value(existing) >= value(&candidate) => {}
+            _ => {
+                best.insert(candidate.id, candidate);
+            }
+        }
+    }
+
+    let mut candidates: Vec<ContextCandidate> = best.into_values().collect();
+    candidates.sort_by(|a, b| value(b).total_cmp(&value(a)).then(a.id.cmp(&b.id)));
+
+    let mut tokens = 0;
+    let mut omitted = 0;
+    let mut files: Vec<(Option<&str>, Vec<PackedBlock>)> = Vec::new();
+
+    for candidate in &candidates {
+        let block = &blocks[&candidate.id];
+        let file_path = block
+            .location
+            .as_ref()
+            .map(|location| location.file_path.as_str());
+
+        let file = files.iter().position(|(file, _)| *file == file_path);
+        let mut cost = count_tokens(&block_header(block, candidate.role))
+            + block.token_count()
+            + count_tokens(BLOCK_FOOTER);
+        if file.is_none() {
+            cost += count_tokens(&file_header(file_path));
+        }
+
+        if tokens + cost > max_tokens {
+            omitted += 1;
+            continue;
+        }
+
+        tokens += cost;
+        match file {
+            Some(file) => files[file].1.push((block, candidate)),
+            None => files.push((file_path, vec![(block, candidate)])),
+        }
+    }
+
+    let mut context = String::new();
+    let mut packed = Vec::new();
+
+    for (file_path, mut file_blocks) in files {
+        file_blocks.sort_by_key(|(block, _)| {
+            block
+                .location
+                .as_ref()
+                .map_or((usize::MAX, block.id), |location| {
+                    (location.start_line, block.id)
+                })
+        });
+
+        context.push_str(&file_header(file_path));
+
+        for (block, candidate) in file_blocks {
+            context.push_str(&block_header(block, candidate.role));
+            context.push_str(block.block.content.trim_end());
+            context.push_str(BLOCK_FOOTER);
+
+            let location = block.location.as_ref();
+            packed.push(ContextBlock {
+                id: block.id,
+                node_key: block.block.node_key.to_owned(),
+                function_name: block.block.function_name.to_owned(),
+                class_name: block.block.class_name.to_owned(),
+                file_path: location.map(|location| location.file_path.to_owned()),
+                start_line: location.map(|location| location.start_line),
+                end_line: location.map(|location| location.end_line),
+                role: candidate.role,
+                score: candidate.score,
+                tokens: block.token_count(),
+            });
+        }
+    }
+
+    Context {
+        tokens,
+        context,
+        blocks: packed,
+        max_tokens,
+        omitted,
+    }
+}
+
+/// The header of a file's section of a context.
+fn file_header(file_path: Option<&str>) -> String {
+    format!("### {}\n\n", file_path.unwrap_or("(unknown file)"))
+}
+
+/// The end of a block's section of a context, closing its code fence.
+const BLOCK_FOOTER: &str = "\n```\n\n";
+
+/// The start of a block's section of a context: the block's line span and role, and the opening
+/// of the code fence holding its content.
+fn block_header(block: &StoredBlock, role: ContextRole) -> String {
+    let role = match role {
+        ContextRole::Hit => "match",
+        ContextRole::Callee => "called by a match",
+        ContextRole::Caller => "calls a match",
+        ContextRole::Class => "class of a match",
+    };
+
+    let (language, lines) = match &block.location {
+        Some(location) => (
+            location.language.as_str(),
+            format!(
+                "Lines {}-{} ({})",
+                location.start_line, location.end_line, role
+            ),
+        ),
+        None => ("", format!("Block {} ({})", block.block.node_key, role)),
+    };
+
+    format!("{}:\n```{}\n", lines, language)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::BlockLocation;
+    use crate::embeddings::tokens::TokenCount;
+
+    /// Blocks with known token counts, so only the headers are estimated, without loading the
+    /// embedding model's tokenizer.
+    fn blocks(specs: &[(i64, usize, Option<(&str, usize)>)]) -> HashMap<i64, StoredBlock> {
+        std::env::set_var("BLOCKOLI_EMBEDDING_TOKENIZER", "");
+
+        specs
+            .iter()
+            .map(|(id, tokens, location)| {
+                let name = format!("f{}", id);
+                let mut block =
+                    StoredBlock::for_test(*id, None, Some(&name), &format!("fn {}() {{}}", name));
+                block.tokens = TokenCount {
+                    embedding: Some(*tokens),
+                    llm: None,
+                };
+                block.location = location.map(|(file_path, start_line)| BlockLocation {
+                    file_path: file_path.to_owned(),
+                    start_line,
+                    end_line: start_line + 1,
+                    start_byte: 0,
+                    end_byte: 0,
+                    language: "rust".to_owned(),
+                });
+                (*id, block)
+            })
+            .collect()
+    }
+
+    fn candidate(id: i64, role: ContextRole, hit: usize) -> ContextCandidate {
+        ContextCandidate {
+            id,
+            role,
+            hit,
+            score: 1.0 / (hit + 1) as f32,
+        }
+    }
+
+    fn ids(context: &Context) -> Vec<i64> {
+        context.blocks.iter().map(|block| block.id).collect()
+    }
+
+    #[test]
+    fn keeps_the_most_valuable_role_of_each_block() {
+        let blocks = blocks(&[(1, 10, None), (2, 10, None)]);
+        let candidates = vec![
+            candidate(1, ContextRole::Hit, 0),
+            candidate(2, ContextRole::Callee, 0),
+            candidate(2, ContextRole::Hit, 1),
+            candidate(99, ContextRole::Hit, 2),
+        ];
+
+        let context = assemble(candidates, &blocks, 10_000);
+
+        assert_eq!(ids(&context), vec![1, 2]);
+        assert_eq!(context.blocks[1].role, ContextRole::Callee);
+        assert_eq!(context.omitted, 0);
+    }
+
+    #[test]
+    fn skips_blocks_that_dont_fit_the_budget() {
+        let blocks = blocks(&[(1, 10, None), (2, 1000, None), (3, 10, None)]);
+        let candidates = vec![
+            candidate(1, ContextRole::Hit, 0),
+            candidate(2, ContextRole::Hit, 1),
+            candidate(3, ContextRole::Hit, 2),
+        ];
+
+        let context = assemble(candidates, &blocks, 100);
+
+        assert_eq!(ids(&context), vec![1, 3]);
+        assert_eq!(context.omitted, 1);
+        assert!(context.tokens <= 100);
+    }
+
+    #[test]
+    fn groups_blocks_by_file_in_line_order() {
+        let blocks = blocks(&[
+            (1, 10, Some(("src/a.rs", 20))),
+            (2, 10, Some(("src/b.rs", 1))),
+            (3, 10, Some(("src/a.rs", 5))),
+        ]);
+        let candidates = vec![
+            candidate(1, ContextRole::Hit, 0),
+            candidate(2, ContextRole::Hit, 1),
+            candidate(3, ContextRole::Hit, 2),
+        ];
+
+        let context = assemble(candidates, &blocks, 10_000);
+
+        assert_eq!(ids(&context), vec![3, 1, 2]);
+        assert_eq!(context.context.matches("### src/a.rs").count(), 1);
+        assert!(context
+            .context
+            .starts_with("### src/a.rs\n\nLines 5-6 (match):\n```rust\n"));
+    }
+}
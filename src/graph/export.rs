@@ -0,0 +1,439 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::graph::calls::CallEdge;
+use crate::vector_store::sqlite::StoredBlock;
+
+/// The format a call graph is exported in.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    /// A JSON list of nodes and edges.
+    #[default]
+    Json,
+
+    /// Graphviz DOT.
+    Dot,
+
+    /// GraphML, for Gephi, yEd, networkx, ...
+    Graphml,
+}
+
+/// Options of a call graph export.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub format: GraphFormat,
+
+    /// Only export the functions within `depth` calls of this function, in either direction.
+    /// The name can be qualified with its class, e.g. `VectorStore::search`.
+    pub function: Option<String>,
+
+    /// The number of calls to follow from `function`.
+    pub depth: usize,
+
+    /// Only export the functions of source files whose path starts with this prefix.
+    pub path_prefix: Option<String>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            format: GraphFormat::default(),
+            function: None,
+            depth: 2,
+            path_prefix: None,
+        }
+    }
+}
+
+/// A function of a call graph.
+#[derive(Serialize, Debug, Clone)]
+pub struct GraphNode {
+    /// The row id of the function's block.
+    pub id: i64,
+    pub node_key: String,
+    pub function_name: Option<String>,
+    pub class_name: Option<String>,
+    pub file_path: Option<String>,
+}
+
+/// A call between two functions of a call graph.
+#[derive(Serialize, Debug, Clone)]
+pub struct GraphEdge {
+    /// The row id of the calling block.
+    pub source: i64,
+
+    /// The row id of the called block.
+    pub target: i64,
+
+    /// The called function, as written at the call site.
+    pub callee_name: String,
+}
+
+/// A project's call graph, or part of it.
+#[derive(Serialize, Debug)]
+pub struct CallGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl CallGraph {
+    /// Builds the call graph of a project: its functions, and any other block making calls, as
+    /// nodes, and the calls between them as edges. Calls to functions outside the project are
+    /// left out.
+    ///
+    /// # Arguments
+    ///
+    /// * `blocks` - Every block of the project.
+    /// * `edges` - The resolved call edges of the project.
+    pub fn build(blocks: &[StoredBlock], edges: &[CallEdge]) -> Self {
+        let edges: Vec<GraphEdge> = edges
+            .iter()
+            .filter_map(|edge| {
+                Some(GraphEdge {
+                    source: edge.caller,
+                    target: edge.callee?,
+                    callee_name: edge.callee_name.to_owned(),
+                })
+            })
+            .collect();
+
+        let connected: HashSet<i64> = edges
+            .iter()
+            .flat_map(|edge| [edge.source, edge.target])
+            .collect();
+
+        let nodes = blocks
+            .iter()
+            .filter(|block| block.block.function_name.is_some() || connected.contains(&block.id))
+            .map(|block| GraphNode {
+                id: block.id,
+                node_key: block.block.node_key.to_owned(),
+                function_name: block.block.function_name.to_owned(),
+                class_name: block.block.class_name.to_owned(),
+                file_path: block
+                    .location
+                    .as_ref()
+                    .map(|location| location.file_path.to_owned()),
+            })
+            .collect();
+
+        CallGraph { nodes, edges }
+    }
+
+    /// Keeps the nodes matching a predicate, and the edges between them.
+    pub fn retain(&mut self, mut keep: impl FnMut(&GraphNode) -> bool) {
+        self.nodes.retain(|node| keep(node));
+
+        let ids: HashSet<i64> = self.nodes.iter().map(|node| node.id).collect();
+        self.edges
+            .retain(|edge| ids.contains(&edge.source) && ids.contains(&edge.target));
+    }
+
+    /// Keeps the nodes within `depth` calls of the roots, following calls in either direction.
+    ///
+    /// # Arguments
+    ///
+    /// * `roots` - The row ids of the blocks to keep the neighbourhood of.
+    /// * `depth` - The number of calls to follow from the roots.
+    pub fn retain_neighbourhood(&mut self, roots: &[i64], depth: usize) {
+        let mut neighbours: HashMap<i64, Vec<i64>> = HashMap::new();
+        for edge in &self.edges {
+            neighbours.entry(edge.source).or_default().push(edge.target);
+            neighbours.entry(edge.target).or_default().push(edge.source);
+        }
+
+        let mut distances: HashMap<i64, usize> = roots.iter().map(|root| (*root, 0)).collect();
+        let mut queue: VecDeque<i64> = roots.iter().copied().collect();
+
+        while let Some(id) = queue.pop_front() {
+            let distance = distances[&id];
+            if distance >= depth {
+                continue;
+            }
+
+            for neighbour in neighbours.get(&id).into_iter().flatten() {
+                if !distances.contains_key(neighbour) {
+                    distances.insert(*neighbour, distance + 1);
+                    queue.push_back(*neighbour);
+                }
+            }
+        }
+
+        self.retain(|node| distances.contains_key(&node.id));
+    }
+
+    /// Formats the graph as Graphviz DOT, with a node per function labelled with its class and
+    /// name, and grouped into a cluster per source file.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph calls {\n    node [shape=box];\n");
+
+        let mut files: Vec<(Option<&str>, Vec<&GraphNode>)> = Vec::new();
+        for node in &self.nodes {
+            let file_path = node.file_path.as_deref();
+            match files.iter_mut().find(|(file, _)| *file == file_path) {
+                Some((_, nodes)) => nodes.push(node),
+                None => files.push((file_path, vec![node])),
+            }
+        }
+
+        for (cluster, (file_path, nodes)) in files.into_iter().enumerate() {
+            let indent = match file_path {
+                Some(file_path) => {
+                    dot.push_str(&format!(
+                        "    subgraph cluster_{} {{\n        label={};\n",
+                        cluster,
+                        dot_string(file_path)
+                    ));
+                    "        "
+                }
+                None => "    ",
+            };
+
+            for node in nodes {
+                let mut attributes = vec![
+                    format!("label={}", dot_string(&node_label(node))),
+                    format!("node_key={}", dot_string(&node.node_key)),
+                ];
+                if let Some(class_name) = &node.class_name {
+                    attributes.push(format!("class={}", dot_string(class_name)));
+                }
+                if let Some(file_path) = &node.file_path {
+                    attributes.push(format!("file={}", dot_string(file_path)));
+                }
+
+                dot.push_str(&format!(
+                    "{}n{} [{}];\n",
+                    indent,
+                    node.id,
+                    attributes.join(", ")
+                ));
+            }
+
+            if file_path.is_some() {
+                dot.push_str("    }\n");
+            }
+        }
+
+        for edge in &self.edges {
+            dot.push_str(&format!(
+                "    n{} -> n{} [label={}];\n",
+                edge.source,
+                edge.target,
+                dot_string(&edge.callee_name)
+            ));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Formats the graph as GraphML, with the block metadata as node attributes and the called
+    /// name as edge attribute.
+    pub fn to_graphml(&self) -> String {
+        let mut graphml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+            "  <key id=\"node_key\" for=\"node\" attr.name=\"node_key\" attr.type=\"string\"/>\n",
+            "  <key id=\"function\" for=\"node\" attr.name=\"function\" attr.type=\"string\"/>\n",
+            "  <key id=\"class\" for=\"node\" attr.name=\"class\" attr.type=\"string\"/>\n",
+            "  <key id=\"file\" for=\"node\" attr.name=\"file\" attr.type=\"string\"/>\n",
+            "  <key id=\"callee\" for=\"edge\" attr.name=\"callee\" attr.type=\"string\"/>\n",
+            "  <graph id=\"calls\" edgedefault=\"directed\">\n",
+        ));
+
+        for node in &self.nodes {
+            graphml.push_str(&format!("    <node id=\"n{}\">\n", node.id));
+
+            let attributes = [
+                ("label", Some(node_label(node))),
+                ("node_key", Some(node.node_key.to_owned())),
+                ("function", node.function_name.to_owned()),
+                ("class", node.class_name.to_owned()),
+                ("file", node.file_path.to_owned()),
+            ];
+            for (key, value) in attributes {
+                if let Some(value) = value {
+                    graphml.push_str(&format!(
+                        "      <data key=\"{}\">{}</data>\n",
+                        key,
+                        xml_escape(&value)
+                    ));
+                }
+            }
+
+            graphml.push_str("    </node>\n");
+        }
+
+        for (i, edge) in self.edges.iter().enumerate() {
+            graphml.push_str(&format!(
+                "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">\n      <data key=\"callee\">{}</data>\n    </edge>\n",
+                i,
+                edge.source,
+                edge.target,
+                xml_escape(&edge.callee_name)
+            ));
+        }
+
+        graphml.push_str("  </graph>\n</graphml>\n");
+        graphml
+    }
+}
+
+/// The display name of a node: `Class::function`, the function name, or the node key of blocks
+/// that aren't functions.
+fn node_label(node: &GraphNode) -> String {
+    match (&node.class_name, &node.function_name) {
+        (Some(class_name), Some(function_name)) => format!("{}::{}", class_name, function_name),
+        (None, Some(function_name)) => function_name.to_owned(),
+        (_, None) => node.node_key.to_owned(),
+    }
+}
+
+/// Quotes a DOT string.
+fn dot_string(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+/// Escapes the XML special characters of a string.
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: i64, class_name: Option<&str>, function_name: &str) -> GraphNode {
+        GraphNode {
+            id,
+            node_key: format!("block_{}", id),
+            function_name: Some(function_name.to_owned()),
+            class_name: class_name.map(str::to_owned),
+            file_path: None,
+        }
+    }
+
+    fn edge(source: i64, target: i64, callee_name: &str) -> GraphEdge {
+        GraphEdge {
+            source,
+            target,
+            callee_name: callee_name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn escapes_dot_strings() {
+        assert_eq!(dot_string("plain"), "\"plain\"");
+        assert_eq!(
+            dot_string("say \"hi\"\\\nbye"),
+            "\"say \\\"hi\\\"\\\\\\nbye\""
+        );
+    }
+
+    #[test]
+    fn escapes_xml() {
+        assert_eq!(
+            xml_escape("a < b && c > \"d\" 'e'"),
+            "a &lt; b &amp;&amp; c &gt; &quot;d&quot; &apos;e&apos;"
+        );
+    }
+
+    #[test]
+    fn exports_escaped_names() {
+        let graph = CallGraph {
+            nodes: vec![node(1, Some("Vec<T>"), "push"), node(2, None, "\"quoted\"")],
+            edges: vec![edge(1, 2, "a&b")],
+        };
+
+        let dot = graph.to_dot();
+        assert!(dot.contains("n1 [label=\"Vec<T>::push\""));
+        assert!(dot.contains("n2 [label=\"\\\"quoted\\\"\""));
+        assert!(dot.contains("n1 -> n2 [label=\"a&b\"];"));
+
+        let graphml = graph.to_graphml();
+        assert!(graphml.contains("<data key=\"label\">Vec&lt;T&gt;::push</data>"));
+        assert!(graphml.contains("<data key=\"function\">&quot;quoted&quot;</data>"));
+        assert!(graphml.contains("<data key=\"callee\">a&amp;b</data>"));
+    }
+
+    #[test]
+    fn groups_dot_nodes_by_file() {
+        let mut a = node(1, None, "a");
+        a.file_path = Some("src/a.rs".to_owned());
+        let mut b = node(2, None, "b");
+        b.file_path = Some("src/a.rs".to_owned());
+
+        let graph = CallGraph {
+            nodes: vec![a, b, node(3, None, "c")],
+            edges: Vec::new(),
+        };
+
+        let dot = graph.to_dot();
+        assert_eq!(dot.matches("subgraph cluster_").count(), 1);
+        assert!(dot.contains("label=\"src/a.rs\";"));
+    }
+
+    #[test]
+    fn builds_graphs_without_external_calls() {
+        let blocks = vec![
+            StoredBlock::for_test(1, None, Some("main"), ""),
+            StoredBlock::for_test(2, None, Some("run"), ""),
+            StoredBlock::for_test(3, Some("Store"), None, ""),
+        ];
+        let edges = vec![
+            CallEdge {
+                caller: 1,
+                caller_key: "block_1".to_owned(),
+                callee: Some(2),
+                callee_key: Some("block_2".to_owned()),
+                callee_name: "run".to_owned(),
+            },
+            CallEdge {
+                caller: 2,
+                caller_key: "block_2".to_owned(),
+                callee: None,
+                callee_key: None,
+                callee_name: "println".to_owned(),
+            },
+        ];
+
+        let graph = CallGraph::build(&blocks, &edges);
+
+        assert_eq!(
+            graph.nodes.iter().map(|node| node.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(graph.edges.len(), 1);
+    }
+
+    #[test]
+    fn keeps_the_neighbourhood_of_roots() {
+        let mut graph = CallGraph {
+            nodes: (1..=4).map(|id| node(id, None, "f")).collect(),
+            edges: vec![edge(1, 2, "f"), edge(2, 3, "f"), edge(4, 3, "f")],
+        };
+
+        graph.retain_neighbourhood(&[1], 1);
+
+        assert_eq!(
+            graph.nodes.iter().map(|node| node.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(graph.edges.len(), 1);
+    }
+}
@@ -0,0 +1,5 @@
+pub mod calls;
+pub mod centrality;
+pub mod context;
+pub mod export;
+pub mod tree;
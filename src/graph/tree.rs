@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::blocks::BlockLocation;
+use crate::graph::calls::CallEdge;
+use crate::vector_store::sqlite::StoredBlock;
+
+/// Which way a call tree follows the call edges.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// From a function to the functions calling it.
+    Callers,
+
+    /// From a function to the functions it calls.
+    Callees,
+}
+
+/// A function in a call tree.
+#[derive(Serialize, Debug)]
+pub struct CallTreeNode {
+    /// The row id of the function's block, or `None` for functions outside the project.
+    pub id: Option<i64>,
+    pub node_key: Option<String>,
+    pub function_name: Option<String>,
+    pub class_name: Option<String>,
+    pub location: Option<BlockLocation>,
+
+    /// The number of calls between the root and this function.
+    pub depth: usize,
+
+    /// Whether the function already appears on the path from the root, i.e. the calls are
+    /// recursive. Its calls are not followed again.
+    pub cycle: bool,
+
+    /// Whether the function appears elsewhere in the tree, closer to the root or earlier at the
+    /// same depth, where its calls are followed.
+    pub repeated: bool,
+
+    /// Whether the function has calls that weren't followed because the tree reached its depth.
+    pub truncated: bool,
+
+    pub children: Vec<CallTreeNode>,
+}
+
+/// The maximum depth of a call tree, so the nested output stays bounded.
+pub const MAX_DEPTH: usize = 32;
+
+/// A node of the tree while it is being walked.
+struct Visit {
+    id: Option<i64>,
+    name: Option<String>,
+    depth: usize,
+    parent: Option<usize>,
+    cycle: bool,
+    repeated: bool,
+    truncated: bool,
+    children: Vec<usize>,
+}
+
+/// Walks the call edges from a set of root blocks breadth-first, up to a depth.
+///
+/// Every block is expanded once, at its first (shallowest) occurrence. Later occurrences are
+/// marked `repeated`, or `cycle` if the block is one of their own ancestors, and left as leaves,
+/// so the tree stays finite on recursive call graphs.
+///
+/// # Arguments
+///
+/// * `roots` - The row ids of the blocks to start from.
+/// * `direction` - Whether to follow calls to callers or to callees.
+/// * `depth` - The maximum number of calls to follow from a root, at most `MAX_DEPTH`.
+/// * `edges` - Retrieves the call edges of a block in the walked direction.
+///
+/// # Returns
+///
+/// A tree per root. Only the ids of the blocks, and the names of the functions outside the
+/// project, are filled in; see `fill_blocks`.
+///
+/// # Errors
+///
+/// Returns an error if the call edges of a block can't be retrieved.
+pub fn call_tree(
+    roots: &[i64],
+    direction: Direction,
+    depth: usize,
+    mut edges: impl FnMut(i64) -> Result<Vec<CallEdge>>,
+) -> Result<Vec<CallTreeNode>> {
+    let depth = depth.min(MAX_DEPTH);
+
+    let mut visits: Vec<Visit> = Vec::new();
+    let mut expanded: HashSet<i64> = HashSet::new();
+    let mut queue: VecDeque<usize> = VecDeque::new();
+
+    for root in roots {
+        visits.push(Visit {
+            id: Some(*root),
+            name: None,
+            depth: 0,
+            parent: None,
+            cycle: false,
+            repeated: !expanded.insert(*root),
+            truncated: false,
+            children: Vec::new(),
+        });
+        queue.push_back(visits.len() - 1);
+    }
+
+    while let Some(visit) = queue.pop_front() {
+        let Some(id) = visits[visit].id else {
+            continue;
+        };
+        if visits[visit].cycle || visits[visit].repeated {
+            continue;
+        }
+
+        let edges = edges(id)?;
+        if visits[visit].depth >= depth {
+            visits[visit].truncated = !edges.is_empty();
+            continue;
+        }
+
+        for edge in edges {
+            let (next, name) = match direction {
+                Direction::Callers => (Some(edge.caller), None),
+                Direction::Callees => (edge.callee, Some(edge.callee_name)),
+            };
+
+            let cycle = next.is_some_and(|next| is_ancestor(&visits, visit, next));
+            let repeated = !cycle && next.is_some_and(|next| !expanded.insert(next));
+
+            visits.push(Visit {
+                id: next,
+                name,
+                depth: visits[visit].depth + 1,
+                parent: Some(visit),
+                cycle,
+                repeated,
+                truncated: false,
+                children: Vec::new(),
+            });
+
+            let child = visits.len() - 1;
+            visits[visit].children.push(child);
+            queue.push_back(child);
+        }
+    }
+
+    let roots: Vec<usize> = (0..visits.len())
+        .filter(|visit| visits[*visit].parent.is_none())
+        .collect();
+
+    Ok(roots
+        .into_iter()
+        .map(|root| build_node(&mut visits, root))
+        .collect())
+}
+
+/// Whether a block is the block of a visit or of one of its ancestors.
+fn is_ancestor(visits: &[Visit], mut visit: usize, id: i64) -> bool {
+    loop {
+        if visits[visit].id == Some(id) {
+            return true;
+        }
+
+        match visits[visit].parent {
+            Some(parent) => visit = parent,
+            None => return false,
+        }
+    }
+}
+
+fn build_node(visits: &mut [Visit], visit: usize) -> CallTreeNode {
+    let children = std::mem::take(&mut visits[visit].children);
+    let children = children
+        .into_iter()
+        .map(|child| build_node(visits, child))
+        .collect();
+
+    let visit = &mut visits[visit];
+    CallTreeNode {
+        id: visit.id,
+        node_key: None,
+        function_name: visit.name.take(),
+        class_name: None,
+        location: None,
+        depth: visit.depth,
+        cycle: visit.cycle,
+        repeated: visit.repeated,
+        truncated: visit.truncated,
+        children,
+    }
+}
+
+/// Collects the row ids of every block of a call tree.
+pub fn block_ids(nodes: &[CallTreeNode], ids: &mut HashSet<i64>) {
+    for node in nodes {
+        ids.extend(node.id);
+        block_ids(&node.children, ids);
+    }
+}
+
+/// Fills in the node key, names and location of every block of a call tree.
+///
+/// # Arguments
+///
+/// * `nodes` - The call tree.
+/// * `blocks` - The blocks of the tree, by row id.
+pub fn fill_blocks(nodes: &mut [CallTreeNode], blocks: &HashMap<i64, StoredBlock>) {
+    for node in nodes {
+        if let Some(block) = node.id.and_then(|id| blocks.get(&id)) {
+            node.node_key = Some(block.block.node_key.to_owned());
+            node.function_name = block.block.function_name.to_owned();
+            node.class_name = block.block.class_name.to_owned();
+            node.location = block.location.to_owned();
+        }
+
+        fill_blocks(&mut node.children, blocks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `1 -> 2, 4`, `2 -> 3, 4, println` and `3 -> 1`.
+    fn edges() -> Vec<CallEdge> {
+        [
+            (1, Some(2), "b"),
+            (1, Some(4), "d"),
+            (2, Some(3), "c"),
+            (2, Some(4), "d"),
+            (2, None, "println"),
+            (3, Some(1), "a"),
+        ]
+        .into_iter()
+        .map(|(caller, callee, name)| CallEdge {
+            caller,
+            caller_key: format!("block_{}", caller),
+            callee,
+            callee_key: callee.map(|callee| format!("block_{}", callee)),
+            callee_name: name.to_owned(),
+        })
+        .collect()
+    }
+
+    fn walk(root: i64, direction: Direction, depth: usize) -> CallTreeNode {
+        let edges = edges();
+        let trees = call_tree(&[root], direction, depth, |id| {
+            Ok(edges
+                .iter()
+                .filter(|edge| match direction {
+                    Direction::Callers => edge.callee == Some(id),
+                    Direction::Callees => edge.caller == id,
+                })
+                .cloned()
+                .collect())
+        })
+        .unwrap();
+
+        assert_eq!(trees.len(), 1);
+        trees.into_iter().next().unwrap()
+    }
+
+    fn ids(nodes: &[CallTreeNode]) -> Vec<Option<i64>> {
+        nodes.iter().map(|node| node.id).collect()
+    }
+
+    #[test]
+    fn marks_cycles_and_repeated_functions() {
+        let root = walk(1, Direction::Callees, MAX_DEPTH);
+
+        assert_eq!(ids(&root.children), vec![Some(2), Some(4)]);
+
+        let b = &root.children[0];
+        assert_eq!(ids(&b.children), vec![Some(3), Some(4), None]);
+        assert!(!b.children[0].repeated && !b.children[0].cycle);
+        assert!(b.children[1].repeated);
+        assert_eq!(b.children[2].function_name.as_deref(), Some("println"));
+
+        let c = &b.children[0];
+        assert_eq!(ids(&c.children), vec![Some(1)]);
+        assert!(c.children[0].cycle);
+        assert!(c.children[0].children.is_empty());
+        assert_eq!(c.children[0].depth, 3);
+    }
+
+    #[test]
+    fn truncates_at_the_depth() {
+        let root = walk(1, Direction::Callees, 1);
+
+        assert_eq!(ids(&root.children), vec![Some(2), Some(4)]);
+        assert!(root.children[0].truncated);
+        assert!(root.children[0].children.is_empty());
+        assert!(!root.children[1].truncated);
+    }
+
+    #[test]
+    fn walks_callers() {
+        let root = walk(4, Direction::Callers, 1);
+
+        assert_eq!(ids(&root.children), vec![Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn fills_in_blocks() {
+        let mut trees = vec![walk(1, Direction::Callees, 1)];
+
+        let mut ids = HashSet::new();
+        block_ids(&trees, &mut ids);
+        assert_eq!(ids, HashSet::from([1, 2, 4]));
+
+        let blocks: HashMap<i64, StoredBlock> = ids
+            .iter()
+            .map(|id| {
+                let name = format!("f{}", id);
+                (
+                    *id,
+                    StoredBlock::for_test(*id, Some("Store"), Some(&name), ""),
+                )
+            })
+            .collect();
+        fill_blocks(&mut trees, &blocks);
+
+        let d = &trees[0].children[1];
+        assert_eq!(d.node_key.as_deref(), Some("block_4"));
+        assert_eq!(d.function_name.as_deref(), Some("f4"));
+        assert_eq!(d.class_name.as_deref(), Some("Store"));
+    }
+}
@@ -7,16 +7,25 @@ use mimalloc::MiMalloc;
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
+mod analysis;
 mod blocks;
 mod embeddings;
+mod graph;
 mod routes;
+mod symbols;
 mod vector_store;
 
 use routes::*;
+use vector_store::index_cache::IndexCache;
 use vector_store::vector_store::VectorStore;
 
+/// The default memory budget of the project index cache, in megabytes. Override it with the
+/// `BLOCKOLI_INDEX_CACHE_MB` environment variable.
+const DEFAULT_INDEX_CACHE_MB: usize = 1024;
+
 pub struct AppState {
     pub vector_store: Arc<Mutex<VectorStore>>,
+    pub index_cache: Arc<Mutex<IndexCache>>,
 }
 
 #[actix_web::main]
@@ -30,6 +39,12 @@ async fn main() -> std::io::Result<()> {
 
     let vector_store = Arc::new(Mutex::new(VectorStore::init_sqlite()));
 
+    let index_cache_mb = std::env::var("BLOCKOLI_INDEX_CACHE_MB")
+        .ok()
+        .and_then(|mb| mb.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_INDEX_CACHE_MB);
+    let index_cache = Arc::new(Mutex::new(IndexCache::new(index_cache_mb * 1024 * 1024)));
+
     let url = "127.0.0.1";
     println!("blockoli server starting on {}. Port: {}", url, port);
 
@@ -40,12 +55,16 @@ async fn main() -> std::io::Result<()> {
             .wrap(cors)
             .app_data(web::Data::new(AppState {
                 vector_store: vector_store.clone(),
+                index_cache: index_cache.clone(),
             }))
             .route("/project", web::post().to(create_project))
             .route("/project/{project_name}", web::get().to(project_info))
             .route("/project/{project_name}", web::delete().to(delete_project))
             .route("/project/generate", web::post().to(generate_embeddings))
+            .route("/search", web::post().to(search_projects))
             .route("/search/{project_name}", web::post().to(search_embeddings))
+            .route("/similar/{project_name}", web::get().to(search_similar))
+            .route("/context/{project_name}", web::post().to(assemble_context))
             .route(
                 "/get_blocks/{project_name}",
                 web::post().to(get_all_function_blocks),
@@ -58,6 +77,74 @@ async fn main() -> std::io::Result<()> {
                 "/search_by_function/{project_name}",
                 web::post().to(search_by_function_name),
             )
+            .route(
+                "/search_symbols/{project_name}",
+                web::post().to(search_symbols),
+            )
+            .route("/autocomplete/{project_name}", web::get().to(autocomplete))
+            .route(
+                "/project/{project_name}/settings",
+                web::get().to(get_project_settings),
+            )
+            .route(
+                "/project/{project_name}/settings",
+                web::put().to(update_project_settings),
+            )
+            .route(
+                "/project/{project_name}/recall",
+                web::get().to(measure_recall),
+            )
+            .route(
+                "/project/{project_name}/index_cache",
+                web::post().to(warm_index_cache),
+            )
+            .route(
+                "/project/{project_name}/index_cache",
+                web::delete().to(evict_index_cache),
+            )
+            .route(
+                "/project/{project_name}/duplicates",
+                web::get().to(find_duplicates),
+            )
+            .route(
+                "/project/{project_name}/dead_code",
+                web::get().to(find_dead_code),
+            )
+            .route(
+                "/project/{project_name}/clusters",
+                web::post().to(cluster_project),
+            )
+            .route(
+                "/project/{project_name}/clusters",
+                web::get().to(get_clusters),
+            )
+            .route(
+                "/project/{project_name}/clusters/{cluster}",
+                web::get().to(get_cluster_blocks),
+            )
+            .route(
+                "/project/{project_name}/projection",
+                web::get().to(project_embeddings),
+            )
+            .route(
+                "/project/{project_name}/calls",
+                web::get().to(get_block_calls),
+            )
+            .route(
+                "/graph/{project_name}/export",
+                web::get().to(export_call_graph),
+            )
+            .route(
+                "/graph/{project_name}/centrality",
+                web::get().to(get_central_blocks),
+            )
+            .route(
+                "/graph/{project_name}/{direction:callers|callees}/{function}",
+                web::get().to(get_call_tree),
+            )
+            .route("/index_cache", web::get().to(index_cache_stats))
+            .route("/embedding_cache", web::get().to(embedding_cache_stats))
+            .route("/embedding_cache", web::delete().to(evict_embedding_cache))
     })
     .bind(format!("{}:{}", url, port))?
     .run()
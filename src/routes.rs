@@ -1,4 +1,9 @@
-use crate::embeddings::encoder::Embeddings;
+use crate::embeddings::encoder::{Embeddings, ProjectMatch, QueryType, SearchOptions, VectorKind};
+use crate::embeddings::index::{self, IndexType};
+use crate::embeddings::preprocess::{self, PreprocessConfig};
+use crate::embeddings::quantize::Quantization;
+use crate::embeddings::rerank::RerankerKind;
+use crate::embeddings::tokens;
 use crate::AppState;
 use actix_web::http::header::CROSS_ORIGIN_EMBEDDER_POLICY;
 use actix_web::web;
@@ -7,14 +12,32 @@ use actix_web::{HttpRequest, HttpResponse};
 use jwalk::WalkDir;
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
+use std::sync::Arc;
 
-use crate::blocks::EmbeddedBlock;
+use crate::analysis::clusters::{self, ClusterOptions};
+use crate::analysis::dead_code::DeadCodeOptions;
+use crate::analysis::duplicates::{self, DuplicateOptions};
+use crate::analysis::projection::{self, ProjectionOptions};
+use crate::blocks::{self, EmbeddedBlock};
+use crate::graph::calls;
+use crate::graph::context::ContextOptions;
+use crate::graph::export::{ExportOptions, GraphFormat};
+use crate::graph::tree::{CallTreeNode, Direction, MAX_DEPTH};
+use crate::symbols::index::{SymbolIndex, SymbolKind};
+use crate::symbols::search::{self, SymbolSearchOptions};
+use crate::vector_store::index_cache::LoadedProject;
+use crate::vector_store::sqlite::{ProjectSettings, SearchFilter};
 
 #[derive(Deserialize)]
 pub struct EmbeddingsPayload {
     project_name: String,
     project_path: String,
+    #[serde(default)]
+    preprocess: PreprocessConfig,
+    #[serde(default)]
+    summaries: HashMap<String, String>,
 }
 
 #[derive(Serialize)]
@@ -29,6 +52,152 @@ pub struct ErrorResponse {
     message: String,
 }
 
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    query_type: QueryType,
+    weights: Option<String>,
+    rerank: Option<RerankerKind>,
+    rerank_candidates: Option<usize>,
+    lambda: Option<f32>,
+    cluster: Option<usize>,
+    file: Option<String>,
+    path_prefix: Option<String>,
+    max_tokens: Option<usize>,
+    centrality: Option<f32>,
+}
+
+impl SearchQuery {
+    /// Builds the `SearchOptions` requested by the query parameters.
+    fn options(&self) -> SearchOptions {
+        let defaults = SearchOptions::default();
+
+        SearchOptions {
+            query_type: self.query_type,
+            rerank: self.rerank,
+            rerank_candidates: self.rerank_candidates.unwrap_or(defaults.rerank_candidates),
+            lambda: self.lambda,
+            max_tokens: self.max_tokens,
+            centrality: self.centrality,
+            ..defaults
+        }
+    }
+
+    /// Builds the `SearchFilter` requested by the query parameters.
+    fn filter(&self) -> SearchFilter {
+        SearchFilter {
+            cluster: self.cluster,
+            file: self.file.to_owned(),
+            path_prefix: self.path_prefix.to_owned(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ProjectsQuery {
+    projects: String,
+}
+
+#[derive(Serialize)]
+pub struct MultiSearchResponse {
+    matches: Vec<ProjectMatch>,
+}
+
+#[derive(Deserialize)]
+pub struct SimilarQuery {
+    node_key: Option<String>,
+    id: Option<i64>,
+    #[serde(default = "default_exclude_source")]
+    exclude_source: bool,
+    #[serde(default)]
+    exclude_file: bool,
+}
+
+fn default_exclude_source() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+pub struct CallTreeQuery {
+    #[serde(default = "default_call_depth")]
+    depth: usize,
+}
+
+fn default_call_depth() -> usize {
+    3
+}
+
+#[derive(Serialize)]
+pub struct CallTreeResponse {
+    function: String,
+    direction: Direction,
+    depth: usize,
+    trees: Vec<CallTreeNode>,
+}
+
+#[derive(Deserialize)]
+pub struct CentralityQuery {
+    #[serde(default = "default_central_limit")]
+    limit: usize,
+}
+
+fn default_central_limit() -> usize {
+    20
+}
+
+#[derive(Deserialize)]
+pub struct AutocompleteQuery {
+    prefix: String,
+    kind: Option<SymbolKind>,
+    #[serde(default = "default_autocomplete_limit")]
+    limit: usize,
+}
+
+fn default_autocomplete_limit() -> usize {
+    10
+}
+
+#[derive(Deserialize)]
+pub struct BlockQuery {
+    node_key: Option<String>,
+    id: Option<i64>,
+}
+
+#[derive(Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PointsFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize)]
+pub struct PointsFormatQuery {
+    #[serde(default)]
+    format: PointsFormat,
+}
+
+#[derive(Deserialize)]
+pub struct RecallQuery {
+    index: Option<IndexType>,
+    quantization: Option<Quantization>,
+    rescore: Option<usize>,
+    k: Option<usize>,
+    samples: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct EvictCacheQuery {
+    model: Option<String>,
+    keep: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct EvictCacheResponse {
+    evicted: usize,
+    message: String,
+}
+
 #[derive(Deserialize)]
 pub struct CreateProject {
     project_name: String,
@@ -44,19 +213,40 @@ pub struct CreateProject {
 /// # Returns
 ///
 /// - `200 OK` if the project was created successfully.
-/// - `400 Bad Request` if the request body is missing required fields.
+/// - `400 Bad Request` if the request body is missing required fields, or if the project name
+///   has characters besides alphanumerics and underscores, or clashes with the tables of other
+///   projects (names ending with `_vectors`, `_calls`, ...).
 pub async fn create_project(
     data: web::Json<CreateProject>,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
     let project_name = data.project_name.to_owned();
 
+    let valid_name = app_state
+        .vector_store
+        .lock()
+        .check_project_name(&project_name)
+        .await;
+
+    if let Err(err) = valid_name {
+        return HttpResponse::BadRequest()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: err.to_string(),
+                })
+                .unwrap(),
+            );
+    }
+
     app_state
         .vector_store
         .lock()
         .create_project(&project_name)
         .await;
 
+    app_state.index_cache.lock().invalidate(&project_name);
+
     HttpResponse::Ok()
         .content_type("application/json")
         .status(StatusCode::OK)
@@ -85,6 +275,8 @@ pub async fn delete_project(
         .delete_project(&project_name)
         .await;
 
+    app_state.index_cache.lock().invalidate(&project_name);
+
     HttpResponse::Ok().content_type("application/json").body(
         serde_json::to_string_pretty(&ErrorResponse {
             message: format!("Deleted project {}", project_name),
@@ -125,6 +317,13 @@ pub async fn project_info(
 /// Expects a JSON body with the following fields:
 /// - `project_name`: The name of the project to generate embeddings for. Must already exist in the vector store.
 /// - `project_path`: The filesystem path to the project's code files.
+/// - `preprocess` (optional): How to preprocess each block before embedding it, with the fields
+///   `split_identifiers`, `comments` and `string_literals` (`keep`, `strip` or `separate`), `prepend_context`
+///   and `prepend_file_path`. It is stored with the project, and code queries are preprocessed the same way.
+/// - `summaries` (optional): Natural-language summaries of blocks (e.g. generated by an LLM), keyed by node key.
+///   They are embedded as an additional `summary` vector of their block.
+///
+/// Besides the whole-block vector, a `signature`, `docstring` and `body` vector is stored for each block.
 ///
 /// # Returns
 ///
@@ -158,24 +357,66 @@ pub async fn generate_embeddings(
     let toml_str = fs::read_to_string("../asterisk/asterisk.toml").expect("Unable to read file");
     let asterisk_config = asterisk::config::Config::from_toml(&toml_str).unwrap();
 
-    let (blocks, _, _) = asterisk::indexer::index_directory(&asterisk_config, &project_path);
+    let (mut blocks, call_stack, call_graph) =
+        asterisk::indexer::index_directory(&asterisk_config, &project_path);
+
+    // The indexer also records calls it resolved across the whole directory, which the blocks'
+    // own outgoing calls can miss. They are stored with the blocks and resolved into call edges.
+    calls::merge_indexed_calls(&mut blocks, call_stack);
+    calls::merge_indexed_calls(&mut blocks, call_graph);
 
-    let code_blocks: Vec<String> = blocks.iter().map(|block| block.content.clone()).collect();
-    let code_vectors = Embeddings::generate_vector_set(code_blocks).unwrap();
+    let mut locations = blocks::locate_blocks(&project_path, &blocks);
+
+    let code_blocks: Vec<String> = blocks
+        .iter()
+        .zip(&locations)
+        .map(|(block, location)| {
+            let source_file = location
+                .as_ref()
+                .map(|location| location.file_path.as_str());
+            preprocess::preprocess_block(block, source_file, &data.preprocess)
+        })
+        .collect();
+    // The vector store is only locked to look up and insert cached embeddings, not while the
+    // model embeds the misses.
+    let code_vectors =
+        Embeddings::generate_vector_set(code_blocks, &*app_state.vector_store).unwrap();
+    let mut named_vectors = Embeddings::generate_named_vectors(
+        &blocks,
+        &data.summaries,
+        &data.preprocess,
+        &*app_state.vector_store,
+    )
+    .unwrap();
+
+    let contents: Vec<String> = blocks
+        .iter()
+        .map(|block| block.content.to_owned())
+        .collect();
+    let token_counts = tokens::count_block_tokens(&contents);
 
     let mut embedded_blocks: Vec<EmbeddedBlock> = Vec::new();
     for (i, block) in blocks.iter().enumerate() {
         embedded_blocks.push(EmbeddedBlock {
             block: block.clone(),
             vectors: code_vectors[i].point.to_vec(),
+            named_vectors: std::mem::take(&mut named_vectors[i]),
+            location: locations[i].take(),
+            tokens: token_counts[i],
         });
     }
 
-    app_state
-        .vector_store
-        .lock()
-        .insert_blocks(&project_name, embedded_blocks.clone())
-        .await;
+    {
+        let vector_store = app_state.vector_store.lock();
+        vector_store
+            .insert_blocks(&project_name, embedded_blocks.clone())
+            .await;
+        vector_store
+            .set_preprocess_config(&project_name, &data.preprocess)
+            .await;
+    }
+
+    app_state.index_cache.lock().invalidate(&project_name);
 
     let response = EmbeddingsResponse {
         project_name: project_name.to_owned(),
@@ -188,19 +429,89 @@ pub async fn generate_embeddings(
         .body(serde_json::to_string_pretty(&response).unwrap())
 }
 
+/// Retrieves a project from the index cache, loading it from the vector store on a miss.
+///
+/// # Arguments
+///
+/// * `app_state` - The application state holding the vector store and the index cache.
+/// * `project_name` - The name of the project. Must exist in the vector store.
+async fn loaded_project(app_state: &AppState, project_name: &str) -> Arc<LoadedProject> {
+    if let Some(project) = app_state.index_cache.lock().get(project_name) {
+        return project;
+    }
+
+    let generation = app_state.index_cache.lock().generation(project_name);
+    let project = app_state
+        .vector_store
+        .lock()
+        .load_project(project_name)
+        .await;
+
+    app_state
+        .index_cache
+        .lock()
+        .insert(project_name, generation, project)
+}
+
+/// Retrieves the symbol index of a project from the index cache, building and caching it on a
+/// miss. Unlike `loaded_project`, this doesn't load the project's vectors.
+///
+/// # Arguments
+///
+/// * `app_state` - The application state holding the vector store and the index cache.
+/// * `project_name` - The name of the project. Must exist in the vector store.
+async fn symbol_index(app_state: &AppState, project_name: &str) -> Arc<SymbolIndex> {
+    if let Some(symbols) = app_state.index_cache.lock().get_symbols(project_name) {
+        return symbols;
+    }
+
+    let generation = app_state.index_cache.lock().generation(project_name);
+    let symbols = app_state
+        .vector_store
+        .lock()
+        .load_symbols(project_name)
+        .await;
+
+    app_state
+        .index_cache
+        .lock()
+        .insert_symbols(project_name, generation, symbols)
+}
+
 /// Searches a project for code blocks matching the given code query, using vector embeddings.
 ///
 /// # Arguments
 ///
 /// * `info` - A `web::Path<String>` containing the name of the project to search in. Must exist in the vector store.
-/// * `data` - The code to search for matches to, as a raw request body.
+/// * `query` - Optional query parameters:
+///   - `query_type`: `code` (default) to search with a code snippet, or `text` for natural-language questions.
+///   - `weights`: Score blocks by a weighted combination of their named vectors instead of the whole-block
+///     vector alone, e.g. `signature:1,docstring:0.5,body:1,summary:0.5,block:0`.
+///   - `rerank`: Rescore the top candidates with a second-stage reranker before returning the matches.
+///     `lexical` blends the vector similarity with a BM25 keyword score.
+///   - `rerank_candidates`: The number of top candidates to rerank or diversify (default 50).
+///   - `lambda`: Diversify the matches with maximal marginal relevance, trading relevance (`1.0`)
+///     against redundancy among the returned blocks (`0.0`). `0.5` to `0.7` works well.
+///   - `cluster`: Only search the blocks of this cluster (see `cluster_project`).
+///   - `file`: Only search the blocks of this source file, relative to the project path.
+///   - `path_prefix`: Only search the blocks of source files under this path, e.g. `src/routes/`.
+///   - `max_tokens`: Only return the best matches whose blocks fit this token budget together.
+///     Matches that don't fit are skipped, so smaller ones further down may still be returned.
+///   - `centrality`: Add each block's centrality in the call graph (from 0 to 1), weighted by this
+///     factor, to its score, so widely-called functions outrank one-offs of similar relevance.
+///     `0.05` to `0.1` breaks near-ties without overriding relevance.
+/// * `data` - The code or question to search for matches to, as a raw request body.
 ///  
 /// # Returns
 ///
-/// - `200 OK` with a JSON body containing the most similar code block and a list of the closest matching blocks.
+/// - `200 OK` with a JSON body containing the most similar code block and a list of the closest matching blocks,
+///   with the row id, source location and token count of each. Both are empty if no block matches the filters.
+/// - `400 Bad Request` if `weights` is malformed, has a negative weight or no positive one.
 /// - `404 Not Found` if no project with the given name exists in the vector store.
+/// - `500 Internal Server Error` if the query can't be embedded or the project can't be read.
 pub async fn search_embeddings(
     info: web::Path<String>,
+    query: web::Query<SearchQuery>,
     data: web::Bytes,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
@@ -224,14 +535,52 @@ pub async fn search_embeddings(
             );
     }
 
+    let weights = match query.weights.as_deref().map(VectorKind::parse_weights) {
+        Some(Ok(weights)) => Some(weights),
+        Some(Err(err)) => {
+            return HttpResponse::BadRequest()
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string_pretty(&ErrorResponse {
+                        message: format!("Invalid weights: {}", err),
+                    })
+                    .unwrap(),
+                );
+        }
+        None => None,
+    };
+
     let search_code = std::str::from_utf8(&data).unwrap().to_owned();
 
+    let project = loaded_project(&app_state, &project_name).await;
+
     let nearest_vectors = app_state
         .vector_store
         .lock()
-        .search(&project_name, search_code)
+        .search(
+            &project_name,
+            &project,
+            search_code,
+            weights,
+            &query.filter(),
+            &query.options(),
+        )
         .await;
 
+    let nearest_vectors = match nearest_vectors {
+        Ok(nearest_vectors) => nearest_vectors,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string_pretty(&ErrorResponse {
+                        message: format!("Search failed: {}", err),
+                    })
+                    .unwrap(),
+                );
+        }
+    };
+
     let res_json = serde_json::to_string_pretty(&nearest_vectors).unwrap();
 
     HttpResponse::Ok()
@@ -239,20 +588,35 @@ pub async fn search_embeddings(
         .body(res_json)
 }
 
-/// Retrieves all code blocks from a project that are non-empty functions.
-///  
+/// Assembles a prompt-ready context for a query, for feeding LLMs: the best matching blocks,
+/// each expanded with the functions it calls, the functions calling it and its class from the
+/// stored call graph, deduplicated and packed into a token budget, grouped by file under file
+/// headers.
+///
 /// # Arguments
 ///
-/// * `info` - A `web::Path<String>` containing the name of the project to retrieve blocks from. Must exist in the vector store.
-/// * `_req` - The HTTP request (unused).
+/// * `info` - A `web::Path<String>` containing the name of the project to search in. Must exist in the vector store.
+/// * `context` - Optional query parameters:
+///   - `max_tokens`: The token budget of the context, file headers included (default 4000).
+///   - `hits`: The number of search hits to expand (default 5).
+///   - `callees`, `callers`, `class_context`: Whether to add the callees, callers and class of
+///     each hit (all default `true`).
+///   - `neighbours`: The maximum number of callees and of callers added per hit (default 3).
+/// * `query` - The same optional query parameters as `search_embeddings`, except `weights`.
+/// * `data` - The code or question to assemble a context for, as a raw request body.
 ///
 /// # Returns
 ///
-/// - `200 OK` with a JSON body containing the retrieved code blocks.
+/// - `200 OK` with a JSON body containing the `context` text, the packed blocks with their role
+///   and token count, the number of tokens used and the number of blocks left out.
+/// - `400 Bad Request` if `weights` is given.
 /// - `404 Not Found` if no project with the given name exists in the vector store.
-pub async fn get_all_function_blocks(
+/// - `500 Internal Server Error` if the query can't be embedded or the project can't be read.
+pub async fn assemble_context(
     info: web::Path<String>,
-    _req: HttpRequest,
+    context: web::Query<ContextOptions>,
+    query: web::Query<SearchQuery>,
+    data: web::Bytes,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
     let project_name = info.to_owned();
@@ -275,33 +639,181 @@ pub async fn get_all_function_blocks(
             );
     }
 
-    let function_blocks = app_state
+    if query.weights.is_some() {
+        return HttpResponse::BadRequest()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: "Weights are not supported when assembling a context".to_owned(),
+                })
+                .unwrap(),
+            );
+    }
+
+    let search_code = std::str::from_utf8(&data).unwrap().to_owned();
+
+    let project = loaded_project(&app_state, &project_name).await;
+
+    let context = app_state
         .vector_store
         .lock()
-        .get_all_function_blocks(&project_name)
+        .assemble_context(
+            &project_name,
+            &project,
+            search_code,
+            &query.filter(),
+            &query.options(),
+            &context,
+        )
         .await;
 
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .body(serde_json::to_string_pretty(&function_blocks).unwrap())
+    match context {
+        Ok(context) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string_pretty(&context).unwrap()),
+        Err(err) => HttpResponse::InternalServerError()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Search failed: {}", err),
+                })
+                .unwrap(),
+            ),
+    }
 }
 
-/// Searches for code blocks matching a query in a project, filtering for non-empty functions.
+/// Searches several projects at once for code blocks matching the given query, merging the
+/// matches of all projects by score.
+///
+/// # Arguments
+///
+/// * `projects` - The `projects` query parameter: a comma-separated list of project names, or `all`.
+///   Each project is searched once, even if it is listed several times.
+/// * `query` - The same optional query parameters as `search_embeddings`, except `weights`.
+/// * `data` - The code or question to search for matches to, as a raw request body.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the best matches over all projects, each tagged with its project.
+/// - `400 Bad Request` if `weights` is given.
+/// - `404 Not Found` if any of the projects doesn't exist in the vector store.
+/// - `500 Internal Server Error` if the query can't be embedded or the projects can't be read.
+pub async fn search_projects(
+    projects: web::Query<ProjectsQuery>,
+    query: web::Query<SearchQuery>,
+    data: web::Bytes,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    if query.weights.is_some() {
+        return HttpResponse::BadRequest()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: "Weights are not supported when searching multiple projects"
+                        .to_owned(),
+                })
+                .unwrap(),
+            );
+    }
+
+    let project_names: Vec<String> = if projects.projects == "all" {
+        app_state.vector_store.lock().list_projects().await
+    } else {
+        let mut project_names: Vec<String> = Vec::new();
+        for name in projects.projects.split(',').map(str::trim) {
+            if !name.is_empty() && !project_names.iter().any(|project| project == name) {
+                project_names.push(name.to_owned());
+            }
+        }
+        project_names
+    };
+
+    let mut loaded = Vec::with_capacity(project_names.len());
+    for project_name in project_names {
+        // check if project exists
+        let project_info = app_state
+            .vector_store
+            .lock()
+            .does_project_exist(&project_name)
+            .await;
+
+        if !project_info {
+            return HttpResponse::NotFound()
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string_pretty(&ErrorResponse {
+                        message: format!("Project {} not found", project_name),
+                    })
+                    .unwrap(),
+                );
+        }
+
+        let project = loaded_project(&app_state, &project_name).await;
+        loaded.push((project_name, project));
+    }
+
+    let search_code = std::str::from_utf8(&data).unwrap().to_owned();
+    let options = query.options();
+
+    // the query is embedded before the vector store is locked, so other requests don't wait
+    // for the model
+    let configs: Vec<&PreprocessConfig> = loaded
+        .iter()
+        .map(|(_, project)| &project.preprocess)
+        .collect();
+    let queries = Embeddings::embed_queries(&search_code, options.query_type, &configs);
+
+    let matches = match queries {
+        Ok(queries) => {
+            app_state
+                .vector_store
+                .lock()
+                .search_projects(&loaded, &queries, search_code, &options)
+                .await
+        }
+        Err(err) => Err(err),
+    };
+
+    match matches {
+        Ok(matches) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string_pretty(&MultiSearchResponse { matches }).unwrap()),
+        Err(err) => HttpResponse::InternalServerError()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Search failed: {}", err),
+                })
+                .unwrap(),
+            ),
+    }
+}
+
+/// Searches a project for code blocks similar to one of its own blocks ("more like this"),
+/// reusing the block's stored vector instead of embedding its code again.
 ///
 /// # Arguments
 ///
 /// * `info` - A `web::Path<String>` containing the name of the project to search in. Must exist in the vector store.
-/// * `_req` - The HTTP request (unused).  
-/// * `data` - The code to search for matches to, as a raw request body.
+/// * `similar` - The source block, identified by one of the following query parameters:
+///   - `node_key`: The node key of the block.
+///   - `id`: The row id of the block.
+///
+///   And optionally `exclude_source` (default `true`) to leave the source block out of the matches,
+///   and `exclude_file` (default `false`) to leave out every block of the source block's file.
+/// * `query` - The same optional query parameters as `search_embeddings`, except `query_type` and `weights`.
 ///
 /// # Returns
 ///
-/// - `200 OK` with a JSON body containing the code blocks matching the query.
-/// - `404 Not Found` if no project with the given name exists in the vector store.
-pub async fn search_function_blocks(
+/// - `200 OK` with a JSON body containing the most similar code block and a list of the closest matching blocks.
+///   Both are empty if every other block is excluded.
+/// - `400 Bad Request` if neither `node_key` nor `id` is given, or if `weights` is given.
+/// - `404 Not Found` if the project or the source block doesn't exist.
+/// - `500 Internal Server Error` if the project can't be read.
+pub async fn search_similar(
     info: web::Path<String>,
-    _req: HttpRequest,
-    data: web::Bytes,
+    similar: web::Query<SimilarQuery>,
+    query: web::Query<SearchQuery>,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
     let project_name = info.to_owned();
@@ -324,35 +836,99 @@ pub async fn search_function_blocks(
             );
     }
 
-    let search_code = std::str::from_utf8(&data).unwrap().to_owned();
+    if query.weights.is_some() {
+        return HttpResponse::BadRequest()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: "Weights are not supported when searching by block".to_owned(),
+                })
+                .unwrap(),
+            );
+    }
 
-    let function_blocks = app_state
-        .vector_store
-        .lock()
-        .search_from_function_blocks(&project_name, search_code)
-        .await;
+    let block_id = match (&similar.node_key, similar.id) {
+        (_, Some(id)) => Some(id),
+        (Some(node_key), None) => {
+            app_state
+                .vector_store
+                .lock()
+                .find_block_id(&project_name, node_key)
+                .await
+        }
+        (None, None) => {
+            return HttpResponse::BadRequest()
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string_pretty(&ErrorResponse {
+                        message: "Either node_key or id is required".to_owned(),
+                    })
+                    .unwrap(),
+                );
+        }
+    };
 
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .body(serde_json::to_string_pretty(&function_blocks).unwrap())
+    let project = loaded_project(&app_state, &project_name).await;
+
+    let nearest_vectors = match block_id {
+        Some(block_id) => {
+            app_state
+                .vector_store
+                .lock()
+                .search_similar(
+                    &project_name,
+                    &project,
+                    block_id,
+                    similar.exclude_source,
+                    similar.exclude_file,
+                    &query.options(),
+                )
+                .await
+        }
+        None => Ok(None),
+    };
+
+    match nearest_vectors {
+        Ok(Some(nearest_vectors)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string_pretty(&nearest_vectors).unwrap()),
+        Err(err) => HttpResponse::InternalServerError()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Search failed: {}", err),
+                })
+                .unwrap(),
+            ),
+        Ok(None) => HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Block not found in project {}", project_name),
+                })
+                .unwrap(),
+            ),
+    }
 }
 
-/// Searches for code blocks with a specific function name in a project.
+/// Retrieves the direct callers and callees of a code block from the project's call graph.
 ///
 /// # Arguments
 ///
-/// * `info` - A `web::Path<String>` containing the name of the project to search in. Must exist in the vector store.
-/// * `_req` - The HTTP request (unused).
-/// * `data` - The function name to search for, as a raw request body.  
+/// * `info` - A `web::Path<String>` containing the name of the project. Must exist in the vector store.
+/// * `block` - The block, identified by one of the following query parameters:
+///   - `node_key`: The node key of the block.
+///   - `id`: The row id of the block.
 ///
 /// # Returns
 ///
-/// - `200 OK` with a JSON body containing the code blocks with the given function name.
-/// - `404 Not Found` if no project with the given name exists in the vector store.
-pub async fn search_by_function_name(
+/// - `200 OK` with a JSON body containing the `callers` and `callees` call edges of the block. Calls
+///   to functions that aren't defined in the project have no `callee`.
+/// - `400 Bad Request` if neither `node_key` nor `id` is given.
+/// - `404 Not Found` if the project or the block doesn't exist.
+pub async fn get_block_calls(
     info: web::Path<String>,
-    _req: HttpRequest,
-    data: web::Bytes,
+    block: web::Query<BlockQuery>,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
     let project_name = info.to_owned();
@@ -375,15 +951,1210 @@ pub async fn search_by_function_name(
             );
     }
 
-    let function_name = std::str::from_utf8(&data).unwrap().to_owned();
+    let block_id = match (&block.node_key, block.id) {
+        (_, Some(id)) => Some(id),
+        (Some(node_key), None) => {
+            app_state
+                .vector_store
+                .lock()
+                .find_block_id(&project_name, node_key)
+                .await
+        }
+        (None, None) => {
+            return HttpResponse::BadRequest()
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string_pretty(&ErrorResponse {
+                        message: "Either node_key or id is required".to_owned(),
+                    })
+                    .unwrap(),
+                );
+        }
+    };
 
-    let function_blocks = app_state
+    let Some(block_id) = block_id else {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Block not found in project {}", project_name),
+                })
+                .unwrap(),
+            );
+    };
+
+    let calls = app_state
         .vector_store
         .lock()
-        .search_by_function_name(&project_name, function_name)
+        .get_calls(&project_name, block_id)
         .await;
 
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .body(serde_json::to_string_pretty(&function_blocks).unwrap())
+    let Some(calls) = calls else {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Block {} not found in project {}", block_id, project_name),
+                })
+                .unwrap(),
+            );
+    };
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&calls).unwrap())
+}
+
+/// Walks a project's call graph breadth-first from a function, answering "what ultimately calls
+/// this?" (`callers`) or "what does this end up calling?" (`callees`).
+///
+/// Every function is expanded once, at its shallowest occurrence. Functions that call back into
+/// one of their ancestors are flagged as a `cycle`, other repeated occurrences as `repeated`, and
+/// functions whose calls weren't followed because of the depth limit as `truncated`.
+///
+/// Accepts the following optional query parameter:
+/// - `depth`: The maximum number of calls to follow (default 3, at most 32).
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<(String, Direction, String)>` containing the name of the project, the
+///   direction of the walk, and the name of the function, optionally qualified with its class
+///   (`VectorStore::search`).
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing a call tree per function with the name, with the
+///   metadata and location of each block. Calls to functions outside the project have no `id`.
+/// - `404 Not Found` if the project or the function doesn't exist.
+pub async fn get_call_tree(
+    info: web::Path<(String, Direction, String)>,
+    query: web::Query<CallTreeQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let (project_name, direction, function) = info.into_inner();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let trees = app_state
+        .vector_store
+        .lock()
+        .call_tree(&project_name, &function, direction, query.depth)
+        .await;
+
+    match trees {
+        Some(trees) => HttpResponse::Ok().content_type("application/json").body(
+            serde_json::to_string_pretty(&CallTreeResponse {
+                function,
+                direction,
+                depth: query.depth.min(MAX_DEPTH),
+                trees,
+            })
+            .unwrap(),
+        ),
+        None => HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!(
+                        "Function {} not found in project {}",
+                        function, project_name
+                    ),
+                })
+                .unwrap(),
+            ),
+    }
+}
+
+/// Exports a project's call graph, with its functions as nodes (with their class and source file
+/// as attributes) and the calls between them as edges. Calls to functions outside the project are
+/// left out.
+///
+/// Accepts the following optional query parameters:
+/// - `format`: `json` (default) for a list of nodes and edges, `dot` for Graphviz, or `graphml`.
+/// - `function`: Only export the functions within `depth` calls of this function, in either
+///   direction. The name can be qualified with its class, e.g. `VectorStore::search`.
+/// - `depth`: The number of calls to follow from `function` (default 2).
+/// - `path_prefix`: Only export the functions of source files under this path, e.g. `src/graph/`.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project. Must exist in the vector store.
+///
+/// # Returns
+///
+/// - `200 OK` with the call graph in the requested format.
+/// - `404 Not Found` if the project or `function` doesn't exist.
+pub async fn export_call_graph(
+    info: web::Path<String>,
+    query: web::Query<ExportOptions>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let graph = app_state
+        .vector_store
+        .lock()
+        .export_call_graph(&project_name, &query)
+        .await;
+
+    let Some(graph) = graph else {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!(
+                        "Function {} not found in project {}",
+                        query.function.as_deref().unwrap_or_default(),
+                        project_name
+                    ),
+                })
+                .unwrap(),
+            );
+    };
+
+    match query.format {
+        GraphFormat::Json => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string_pretty(&graph).unwrap()),
+        GraphFormat::Dot => HttpResponse::Ok()
+            .content_type("text/vnd.graphviz")
+            .body(graph.to_dot()),
+        GraphFormat::Graphml => HttpResponse::Ok()
+            .content_type("application/graphml+xml")
+            .body(graph.to_graphml()),
+    }
+}
+
+/// Ranks the blocks of a project by their centrality in its call graph: their number of distinct
+/// callers and their PageRank over the call edges, computed when the project is indexed.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project. Must exist in the vector store.
+/// * `query` - Optional query parameters:
+///   - `limit`: The number of blocks to return (default 20).
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON list of the most central blocks, most central first.
+/// - `404 Not Found` if the project doesn't exist.
+pub async fn get_central_blocks(
+    info: web::Path<String>,
+    query: web::Query<CentralityQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let blocks = app_state
+        .vector_store
+        .lock()
+        .central_blocks(&project_name, query.limit)
+        .await;
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&blocks).unwrap())
+}
+
+/// Reports groups of near-duplicate (copy-pasted) code blocks in a project.
+///
+/// Accepts the following optional query parameters:
+/// - `threshold`: The minimum cosine similarity of two blocks to report them (default 0.95).
+/// - `min_tokens`: Ignore blocks with fewer tokens than this (default 20).
+/// - `shingle_size`: The number of tokens per shingle of the clone check (default 5).
+/// - `clone_threshold`: The minimum Jaccard similarity of two blocks' shingles to consider
+///   them exact clones (default 0.9).
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project. Must exist in the vector store.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the groups of near-duplicates, largest first. Each group
+///   lists its blocks and the similarity of each near-duplicate pair.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn find_duplicates(
+    info: web::Path<String>,
+    query: web::Query<DuplicateOptions>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let project = loaded_project(&app_state, &project_name).await;
+
+    let blocks = app_state
+        .vector_store
+        .lock()
+        .get_block_vectors(&project_name, &project)
+        .await;
+
+    // the comparison is quadratic in the number of blocks, so it runs on the blocking thread
+    // pool, without holding the vector store lock
+    let options = query.into_inner();
+    let groups = match web::block(move || duplicates::find_duplicates(blocks, &options)).await {
+        Ok(groups) => groups,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string_pretty(&ErrorResponse {
+                        message: format!("Failed to find duplicates: {}", err),
+                    })
+                    .unwrap(),
+                );
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&groups).unwrap())
+}
+
+/// Reports the functions of a project that nothing in the project calls, grouped by file, so
+/// unused code can be pruned. Functions are matched to their callers through the call edges
+/// resolved from the blocks' outgoing calls when the project was indexed.
+///
+/// Accepts the following optional query parameters:
+/// - `entry_points`: Comma-separated names of functions called from outside the project (default
+///   `main`). Names can be qualified with their class (`Server::run`) or end with `*` to match a
+///   prefix (`handle_*`).
+/// - `include_tests`: Also report test functions (default `false`).
+/// - `include_public`: Also report public functions, which may be used by other projects
+///   (default `false`).
+/// - `path_prefix`: Only report the functions of source files under this path, e.g. `src/graph/`.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project. Must exist in the vector store.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the number of functions checked and excluded, and the
+///   functions without incoming calls grouped by file, with their line span and token count.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn find_dead_code(
+    info: web::Path<String>,
+    query: web::Query<DeadCodeOptions>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let report = app_state
+        .vector_store
+        .lock()
+        .find_dead_code(&project_name, &query)
+        .await;
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&report).unwrap())
+}
+
+/// Groups the blocks of a project into topics with k-means over their stored vectors, and stores
+/// the cluster of each block so searches can be filtered by cluster. Replaces any previous
+/// clustering of the project. Blocks inserted afterwards have no cluster until the next run.
+///
+/// Accepts the following optional query parameters:
+/// - `k`: The number of clusters (default `sqrt(blocks / 2)`).
+/// - `iterations`: The maximum number of k-means iterations (default 50).
+/// - `labels`: The number of function names to label each cluster with (default 3).
+/// - `seed`: The seed of the initialisation (default 0).
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project. Must exist in the vector store.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the clusters, largest first, with their size and labels.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+/// - `500 Internal Server Error` if the clustering fails.
+pub async fn cluster_project(
+    info: web::Path<String>,
+    query: web::Query<ClusterOptions>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let project = loaded_project(&app_state, &project_name).await;
+
+    let blocks = app_state
+        .vector_store
+        .lock()
+        .get_block_vectors(&project_name, &project)
+        .await;
+
+    // k-means runs over every vector of the project, so it runs on the blocking thread pool,
+    // without holding the vector store lock
+    let options = query.into_inner();
+    let clustering = match web::block(move || clusters::cluster_blocks(blocks, &options)).await {
+        Ok(clustering) => clustering,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string_pretty(&ErrorResponse {
+                        message: format!("Failed to cluster the project: {}", err),
+                    })
+                    .unwrap(),
+                );
+        }
+    };
+
+    app_state
+        .vector_store
+        .lock()
+        .set_clusters(&project_name, &clustering)
+        .await;
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&clustering.clusters).unwrap())
+}
+
+/// Retrieves the stored clusters of a project.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project. Must exist in the vector store.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the clusters, largest first, with their size and labels.
+///   Empty if the project wasn't clustered yet.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn get_clusters(
+    info: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let clusters = app_state
+        .vector_store
+        .lock()
+        .get_clusters(&project_name)
+        .await;
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&clusters).unwrap())
+}
+
+/// Retrieves the code blocks of a cluster.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<(String, usize)>` containing the name of the project and the id of the cluster.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the blocks of the cluster, with their row ids.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn get_cluster_blocks(
+    info: web::Path<(String, usize)>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let (project_name, cluster) = info.into_inner();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let blocks = app_state
+        .vector_store
+        .lock()
+        .get_cluster_blocks(&project_name, cluster)
+        .await;
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&blocks).unwrap())
+}
+
+/// Projects the vectors of a project's blocks to 2D or 3D, for plotting the codebase as a map.
+///
+/// Accepts the following optional query parameters:
+/// - `method`: `pca` (default), or `umap` to refine the PCA layout so that each block stays close
+///   to its nearest neighbours, which keeps local clusters apart at the cost of speed.
+/// - `dimensions`: `2` (default) or `3`.
+/// - `neighbors`, `epochs`, `seed`: The number of neighbours (default 15, at most 100),
+///   optimisation epochs (default 200, at most 1000) and random seed (default 0) of the `umap`
+///   layout.
+/// - `format`: `json` (default) or `csv`.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project. Must exist in the vector store.
+///
+/// # Returns
+///
+/// - `200 OK` with a point for every block: its row id, node key, function and class name,
+///   cluster (if the project was clustered) and coordinates.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn project_embeddings(
+    info: web::Path<String>,
+    query: web::Query<ProjectionOptions>,
+    format: web::Query<PointsFormatQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let project = loaded_project(&app_state, &project_name).await;
+
+    let (blocks, clusters) = {
+        let vector_store = app_state.vector_store.lock();
+        (
+            vector_store
+                .get_block_vectors(&project_name, &project)
+                .await,
+            vector_store.get_block_clusters(&project_name).await,
+        )
+    };
+
+    // like `find_duplicates`, the layout runs on the blocking thread pool, without holding the
+    // vector store lock
+    let options = query.into_inner();
+    let points = match web::block(move || projection::project(blocks, &clusters, &options)).await {
+        Ok(points) => points,
+        Err(err) => {
+            return HttpResponse::InternalServerError()
+                .content_type("application/json")
+                .body(
+                    serde_json::to_string_pretty(&ErrorResponse {
+                        message: format!("Failed to project embeddings: {}", err),
+                    })
+                    .unwrap(),
+                );
+        }
+    };
+
+    match format.format {
+        PointsFormat::Json => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string_pretty(&points).unwrap()),
+        PointsFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv")
+            .body(projection::to_csv(&points)),
+    }
+}
+
+/// Retrieves all code blocks from a project that are non-empty functions.
+///  
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project to retrieve blocks from. Must exist in the vector store.
+/// * `_req` - The HTTP request (unused).
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the retrieved code blocks.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn get_all_function_blocks(
+    info: web::Path<String>,
+    _req: HttpRequest,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let function_blocks = app_state
+        .vector_store
+        .lock()
+        .get_all_function_blocks(&project_name)
+        .await;
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&function_blocks).unwrap())
+}
+
+/// Searches for code blocks matching a query in a project, filtering for non-empty functions.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project to search in. Must exist in the vector store.
+/// * `_req` - The HTTP request (unused).  
+/// * `data` - The code to search for matches to, as a raw request body.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the code blocks matching the query.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn search_function_blocks(
+    info: web::Path<String>,
+    _req: HttpRequest,
+    data: web::Bytes,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let search_code = std::str::from_utf8(&data).unwrap().to_owned();
+
+    let function_blocks = app_state
+        .vector_store
+        .lock()
+        .search_from_function_blocks(&project_name, search_code)
+        .await;
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&function_blocks).unwrap())
+}
+
+/// Searches for code blocks with a specific function name in a project.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project to search in. Must exist in the vector store.
+/// * `_req` - The HTTP request (unused).
+/// * `data` - The function name to search for, as a raw request body.  
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the code blocks with the given function name.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn search_by_function_name(
+    info: web::Path<String>,
+    _req: HttpRequest,
+    data: web::Bytes,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let function_name = std::str::from_utf8(&data).unwrap().to_owned();
+
+    let function_blocks = app_state
+        .vector_store
+        .lock()
+        .search_by_function_name(&project_name, function_name)
+        .await;
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&function_blocks).unwrap())
+}
+
+/// Searches the functions and classes of a project by name, tolerating partial names and typos.
+///
+/// Accepts the following optional query parameters:
+///
+/// - `mode`: `auto` (default) ranks every kind of match, or restricts matching to `exact`,
+///   `prefix`, `fuzzy` (the query's characters in order, fzf-style) or `edit` (within a few
+///   typos).
+/// - `case_sensitive`: whether letter case must match. Defaults to `false`.
+/// - `kind`: only match `function`, `method` or `class` symbols.
+/// - `limit`: the maximum number of matches. Defaults to 20.
+/// - `max_distance`: the maximum number of typos of an `edit` match. Defaults to 1 to 3 by the
+///   query's length.
+/// - `suggestions`: the maximum number of "did you mean" suggestions. Defaults to 5.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project to search in. Must exist in the vector store.
+/// * `query` - The search options.
+/// * `data` - The name to search for, as a raw request body. It can be qualified with a class,
+///   e.g. `VectorStore::search`.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the matching symbols, best first, with their kind,
+///   location, score and matched character positions, and suggestions when nothing matches
+///   exactly.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn search_symbols(
+    info: web::Path<String>,
+    query: web::Query<SymbolSearchOptions>,
+    data: web::Bytes,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let search_query = std::str::from_utf8(&data).unwrap().to_owned();
+
+    let symbols = symbol_index(&app_state, &project_name).await;
+    let results = search::search_symbols(&symbols, &search_query, &query);
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&results).unwrap())
+}
+
+/// Completes a partial function or class name, for editor typeahead.
+///
+/// Completions come from the project's symbol index, kept in the index cache apart from its
+/// vectors and rebuilt when the project is reindexed, so they don't touch the database once
+/// the index is built.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project. Must exist in the vector store.
+/// * `query` - Query parameters:
+///   - `prefix`: The start of the name, ignoring case. It can be qualified with a class, e.g.
+///     `VectorStore::se`, to complete that class's methods.
+///   - `kind`: Only complete `function`, `method` or `class` symbols.
+///   - `limit`: The maximum number of completions (default 10).
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON list of the matching symbols, with their kind, block type, class and
+///   location, names with the prefix's case and shorter names first.
+/// - `404 Not Found` if the project doesn't exist.
+pub async fn autocomplete(
+    info: web::Path<String>,
+    query: web::Query<AutocompleteQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let symbols = symbol_index(&app_state, &project_name).await;
+    let completions = symbols.complete(&query.prefix, query.kind, query.limit);
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&completions).unwrap())
+}
+
+/// Retrieves statistics for the embedding cache shared by all projects.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the number of cached entries, hits, misses and the hit rate.
+pub async fn embedding_cache_stats(app_state: web::Data<AppState>) -> impl Responder {
+    let stats = app_state.vector_store.lock().embedding_cache_stats().await;
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&stats).unwrap())
+}
+
+/// Evicts entries from the embedding cache shared by all projects.
+///
+/// Accepts the following optional query parameters:
+/// - `model`: Only evict entries generated with this model.
+/// - `keep`: Keep this many of the most recently used entries instead of evicting everything.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the number of evicted entries.
+pub async fn evict_embedding_cache(
+    query: web::Query<EvictCacheQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let evicted = app_state
+        .vector_store
+        .lock()
+        .evict_embedding_cache(query.model.as_deref(), query.keep)
+        .await;
+
+    let response = EvictCacheResponse {
+        evicted,
+        message: format!("Evicted {} cached embeddings", evicted),
+    };
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&response).unwrap())
+}
+
+/// Retrieves the search settings of a project.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project. Must exist in the vector store.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the project's settings, e.g. `{"index": "kdtree"}`.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn get_project_settings(
+    info: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let settings = app_state
+        .vector_store
+        .lock()
+        .get_project_settings(&project_name)
+        .await;
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&settings).unwrap())
+}
+
+/// Updates the search settings of a project.
+///
+/// Expects a JSON body with the following fields:
+/// - `index`: The nearest-neighbour index to search the project with: `kdtree` (default) or `flat`,
+///   an exact SIMD scan that is faster for projects of up to a few hundred thousand blocks.
+/// - `quantization`: Scan compressed vectors instead: `none` (default), `int8` or `binary`.
+///   The shortlist is rescored at full precision.
+/// - `rescore`: How many times the needed number of quantized candidates to rescore (default 4).
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the updated settings.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn update_project_settings(
+    info: web::Path<String>,
+    data: web::Json<ProjectSettings>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    app_state
+        .vector_store
+        .lock()
+        .set_project_settings(&project_name, &data)
+        .await;
+
+    app_state.index_cache.lock().invalidate(&project_name);
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&*data).unwrap())
+}
+
+/// Measures the recall@k of a project's index or quantization against the exact flat search,
+/// using the project's own vectors as queries.
+///
+/// Accepts the following optional query parameters:
+/// - `index`: The index to measure (defaults to the project's configured index).
+/// - `quantization`: The quantization to measure (defaults to the project's configured quantization).
+/// - `rescore`: The rescoring factor of quantized searches (defaults to the project's configured factor).
+/// - `k`: The number of nearest neighbours to compare per query (default 10, at most 100).
+/// - `samples`: The maximum number of queries to run (default 100, at most 1000).
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the measured recall.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+/// - `500 Internal Server Error` if the measurement fails.
+pub async fn measure_recall(
+    info: web::Path<String>,
+    query: web::Query<RecallQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    let (settings, vectors) = {
+        let vector_store = app_state.vector_store.lock();
+        (
+            vector_store.get_project_settings(&project_name).await,
+            vector_store.get_vectors(&project_name).await,
+        )
+    };
+
+    // every sampled query is searched twice over the whole project, so the measurement runs on
+    // the blocking thread pool, without holding the vector store lock
+    let report = web::block(move || {
+        index::measure_recall(
+            vectors,
+            query.index.unwrap_or(settings.index),
+            query.quantization.unwrap_or(settings.quantization),
+            query.rescore.unwrap_or(settings.rescore),
+            query.k.unwrap_or(10),
+            query.samples.unwrap_or(100),
+        )
+    })
+    .await;
+
+    match report {
+        Ok(report) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(serde_json::to_string_pretty(&report).unwrap()),
+        Err(err) => HttpResponse::InternalServerError()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Failed to measure recall: {}", err),
+                })
+                .unwrap(),
+            ),
+    }
+}
+
+/// Retrieves statistics about the in-process cache of loaded project indexes.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the memory budget and usage, hit and miss counts,
+///   and the cached projects with their sizes, most recently used first.
+pub async fn index_cache_stats(app_state: web::Data<AppState>) -> impl Responder {
+    let stats = app_state.index_cache.lock().stats();
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&stats).unwrap())
+}
+
+/// Loads a project into the index cache ahead of its first search.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project. Must exist in the vector store.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing the index cache statistics after loading the project.
+///   Projects larger than the cache's memory budget are loaded but not kept.
+/// - `404 Not Found` if no project with the given name exists in the vector store.
+pub async fn warm_index_cache(
+    info: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    // check if project exists
+    let project_info = app_state
+        .vector_store
+        .lock()
+        .does_project_exist(&project_name)
+        .await;
+
+    if !project_info {
+        return HttpResponse::NotFound()
+            .content_type("application/json")
+            .body(
+                serde_json::to_string_pretty(&ErrorResponse {
+                    message: format!("Project {} not found", project_name),
+                })
+                .unwrap(),
+            );
+    }
+
+    loaded_project(&app_state, &project_name).await;
+
+    let stats = app_state.index_cache.lock().stats();
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&stats).unwrap())
+}
+
+/// Evicts a project from the index cache.
+///
+/// # Arguments
+///
+/// * `info` - A `web::Path<String>` containing the name of the project.
+///
+/// # Returns
+///
+/// - `200 OK` with a JSON body containing a message saying whether the project was cached.
+pub async fn evict_index_cache(
+    info: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let project_name = info.to_owned();
+
+    let message = if app_state.index_cache.lock().invalidate(&project_name) {
+        format!("Evicted {} from the index cache", project_name)
+    } else {
+        format!("Project {} was not in the index cache", project_name)
+    };
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string_pretty(&ErrorResponse { message }).unwrap())
 }
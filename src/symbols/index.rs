@@ -0,0 +1,262 @@
+use std::mem::size_of;
+
+use asterisk::block::BlockType;
+use serde::{Deserialize, Serialize};
+
+use crate::graph::calls::split_call;
+
+/// What a symbol names.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolKind {
+    /// A function outside of any class.
+    Function,
+
+    /// A function of a class.
+    Method,
+
+    /// A class, struct, trait, ...
+    Class,
+}
+
+/// A function or class defined by a block of a project.
+#[derive(Serialize, Debug, Clone)]
+pub struct Symbol {
+    /// The row id of the block defining the symbol.
+    pub id: i64,
+    pub node_key: String,
+
+    /// The name of the function or class.
+    pub name: String,
+    pub kind: SymbolKind,
+    pub block_type: BlockType,
+
+    /// The class of a method.
+    pub class_name: Option<String>,
+    pub file_path: Option<String>,
+    pub start_line: Option<usize>,
+}
+
+impl Symbol {
+    /// The name of the symbol, qualified with its class for methods, e.g. `VectorStore::search`.
+    pub fn qualified_name(&self) -> String {
+        match &self.class_name {
+            Some(class_name) if self.kind == SymbolKind::Method => {
+                format!("{}::{}", class_name, self.name)
+            }
+            _ => self.name.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Symbol {
+    /// A symbol for tests: a method of `class_name` if given, otherwise a function. Its node key
+    /// is `block_{id}`, and it has no location.
+    pub fn for_test(id: i64, class_name: Option<&str>, name: &str) -> Symbol {
+        Symbol {
+            id,
+            node_key: format!("block_{}", id),
+            name: name.to_owned(),
+            kind: if class_name.is_some() {
+                SymbolKind::Method
+            } else {
+                SymbolKind::Function
+            },
+            block_type: BlockType::Function,
+            class_name: class_name.map(str::to_owned),
+            file_path: None,
+            start_line: None,
+        }
+    }
+}
+
+/// The symbols of a project, sorted by name so they can be looked up by prefix.
+pub struct SymbolIndex {
+    /// The symbols, sorted by their lowercase name.
+    symbols: Vec<Symbol>,
+
+    /// The lowercase name of each symbol.
+    keys: Vec<String>,
+}
+
+impl SymbolIndex {
+    /// Builds the index of a project's symbols.
+    pub fn build(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_cached_key(|symbol| (symbol.name.to_lowercase(), symbol.id));
+        let keys = symbols
+            .iter()
+            .map(|symbol| symbol.name.to_lowercase())
+            .collect();
+
+        SymbolIndex { symbols, keys }
+    }
+
+    /// Every symbol, sorted by lowercase name.
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// The symbols whose name starts with a prefix, ignoring case, sorted by lowercase name.
+    pub fn with_prefix(&self, prefix: &str) -> &[Symbol] {
+        let prefix = prefix.to_lowercase();
+
+        let start = self
+            .keys
+            .partition_point(|key| key.as_str() < prefix.as_str());
+        let end =
+            start + self.keys[start..].partition_point(|key| key.starts_with(prefix.as_str()));
+
+        &self.symbols[start..end]
+    }
+
+    /// Completes a partial name to the symbols starting with it, for typeahead.
+    ///
+    /// Names with the prefix's exact case rank first, then shorter names, so `Vec` completes to
+    /// `Vector` before `vector_search`. A prefix qualified with a class (`VectorStore::se`) only
+    /// completes that class's methods.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The start of the name.
+    /// * `kind` - If given, only complete symbols of this kind.
+    /// * `limit` - The maximum number of symbols to return.
+    pub fn complete(&self, prefix: &str, kind: Option<SymbolKind>, limit: usize) -> Vec<&Symbol> {
+        let (qualifier, prefix) = split_call(prefix.trim());
+        let class_name = qualifier.map(|qualifier| split_call(qualifier).1.to_lowercase());
+
+        let mut symbols: Vec<&Symbol> = self
+            .with_prefix(prefix)
+            .iter()
+            .filter(|symbol| kind.is_none_or(|kind| symbol.kind == kind))
+            .filter(|symbol| {
+                class_name.as_ref().is_none_or(|class_name| {
+                    symbol
+                        .class_name
+                        .as_ref()
+                        .is_some_and(|symbol_class| symbol_class.to_lowercase() == *class_name)
+                })
+            })
+            .collect();
+
+        symbols.sort_by_key(|symbol| (!symbol.name.starts_with(prefix), symbol.name.len()));
+        symbols.truncate(limit);
+
+        symbols
+    }
+
+    /// The approximate number of bytes the index takes up in memory.
+    pub fn size_bytes(&self) -> usize {
+        let strings: usize = self
+            .symbols
+            .iter()
+            .map(|symbol| {
+                symbol.node_key.capacity()
+                    + symbol.name.capacity()
+                    + symbol.class_name.as_ref().map_or(0, String::capacity)
+                    + symbol.file_path.as_ref().map_or(0, String::capacity)
+            })
+            .sum();
+
+        self.symbols.capacity() * size_of::<Symbol>()
+            + self.keys.capacity() * size_of::<String>()
+            + self.keys.iter().map(String::capacity).sum::<usize>()
+            + strings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names<'a>(symbols: impl IntoIterator<Item = &'a Symbol>) -> Vec<&'a str> {
+        symbols
+            .into_iter()
+            .map(|symbol| symbol.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn sorts_symbols_by_lowercase_name() {
+        let index = SymbolIndex::build(vec![
+            Symbol::for_test(1, None, "search"),
+            Symbol::for_test(2, None, "Insert"),
+            Symbol::for_test(3, None, "delete"),
+        ]);
+
+        assert_eq!(names(index.symbols()), vec!["delete", "Insert", "search"]);
+    }
+
+    #[test]
+    fn looks_up_prefixes_ignoring_case() {
+        let index = SymbolIndex::build(vec![
+            Symbol::for_test(1, None, "insert"),
+            Symbol::for_test(2, None, "InsertMany"),
+            Symbol::for_test(3, None, "ins"),
+            Symbol::for_test(4, None, "index"),
+            Symbol::for_test(5, None, "search"),
+        ]);
+
+        assert_eq!(
+            names(index.with_prefix("INS")),
+            vec!["ins", "insert", "InsertMany"]
+        );
+        assert_eq!(names(index.with_prefix("")).len(), 5);
+        assert!(index.with_prefix("z").is_empty());
+    }
+
+    #[test]
+    fn qualifies_method_names() {
+        assert_eq!(
+            Symbol::for_test(1, Some("Store"), "insert").qualified_name(),
+            "Store::insert"
+        );
+        assert_eq!(
+            Symbol::for_test(2, None, "insert").qualified_name(),
+            "insert"
+        );
+    }
+
+    #[test]
+    fn completes_exact_case_and_shorter_names_first() {
+        let index = SymbolIndex::build(vec![
+            Symbol::for_test(1, None, "vector_search"),
+            Symbol::for_test(2, None, "Vector"),
+            Symbol::for_test(3, None, "VectorStore"),
+            Symbol::for_test(4, None, "vec"),
+            Symbol::for_test(5, None, "search"),
+        ]);
+
+        assert_eq!(
+            names(index.complete("Vec", None, 10)),
+            vec!["Vector", "VectorStore", "vec", "vector_search"]
+        );
+        assert_eq!(
+            names(index.complete("Vec", None, 2)),
+            vec!["Vector", "VectorStore"]
+        );
+    }
+
+    #[test]
+    fn completes_methods_of_a_qualifying_class() {
+        let index = SymbolIndex::build(vec![
+            Symbol::for_test(1, Some("VectorStore"), "search"),
+            Symbol::for_test(2, Some("VectorStore"), "insert"),
+            Symbol::for_test(3, Some("IndexCache"), "set"),
+            Symbol::for_test(4, None, "search_symbols"),
+        ]);
+
+        assert_eq!(
+            names(index.complete("VectorStore::se", None, 10)),
+            vec!["search"]
+        );
+        assert_eq!(
+            names(index.complete("vectorstore.ins", None, 10)),
+            vec!["insert"]
+        );
+        assert_eq!(
+            names(index.complete("se", Some(SymbolKind::Function), 10)),
+            vec!["search_symbols"]
+        );
+    }
+}
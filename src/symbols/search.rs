@@ -0,0 +1,586 @@
+use serde::{Deserialize, Serialize};
+
+use crate::graph::calls::split_call;
+use crate::symbols::index::{Symbol, SymbolIndex, SymbolKind};
+
+/// How a symbol search matches names.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// Every kind of match, ranked from exact to edit distance.
+    #[default]
+    Auto,
+
+    /// Names equal to the query.
+    Exact,
+
+    /// Names starting with the query.
+    Prefix,
+
+    /// Names containing the characters of the query in order, fzf-style.
+    Fuzzy,
+
+    /// Names within a few edits (insertions, deletions, substitutions and transpositions) of
+    /// the query, for typos.
+    Edit,
+}
+
+/// How a symbol matched a query.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchType {
+    Exact,
+    Prefix,
+    Fuzzy,
+    Edit,
+}
+
+/// Options of a symbol search.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SymbolSearchOptions {
+    pub mode: MatchMode,
+
+    /// Whether letter case must match. Ranking still prefers names with the query's case.
+    pub case_sensitive: bool,
+
+    /// Only match symbols of this kind.
+    pub kind: Option<SymbolKind>,
+
+    /// The maximum number of matches.
+    pub limit: usize,
+
+    /// The maximum number of edits of an edit-distance match. Defaults to 1 for queries of up
+    /// to 5 characters, 2 up to 9 and 3 beyond.
+    pub max_distance: Option<usize>,
+
+    /// The maximum number of "did you mean" suggestions.
+    pub suggestions: usize,
+}
+
+impl Default for SymbolSearchOptions {
+    fn default() -> Self {
+        SymbolSearchOptions {
+            mode: MatchMode::default(),
+            case_sensitive: false,
+            kind: None,
+            limit: 20,
+            max_distance: None,
+            suggestions: 5,
+        }
+    }
+}
+
+/// A symbol matching a query.
+#[derive(Serialize, Debug)]
+pub struct SymbolMatch {
+    #[serde(flatten)]
+    pub symbol: Symbol,
+    pub match_type: MatchType,
+
+    /// The score of the match, from 0 to 1, higher is better.
+    pub score: f32,
+
+    /// The positions of the characters of the name matching the query, for highlighting.
+    pub positions: Vec<usize>,
+}
+
+/// The results of a symbol search.
+#[derive(Serialize, Debug)]
+pub struct SymbolSearchResults {
+    /// The matching symbols, best first.
+    pub matches: Vec<SymbolMatch>,
+
+    /// The names closest to the query by edit distance, closest first, when no name matches
+    /// it exactly.
+    pub suggestions: Vec<String>,
+}
+
+/// Searches the symbols of a project by name.
+///
+/// In `auto` mode, every symbol is scored by its best kind of match: exact names first, then
+/// prefixes, then fuzzy and edit-distance matches by how close they are. Queries can be qualified
+/// with a class (`Store::insert`, `store.insert`) to only match that class's methods.
+///
+/// # Arguments
+///
+/// * `index` - The symbols of the project.
+/// * `query` - The name, or part of a name, to search for.
+/// * `options` - How to match names and how many matches to return.
+pub fn search_symbols(
+    index: &SymbolIndex,
+    query: &str,
+    options: &SymbolSearchOptions,
+) -> SymbolSearchResults {
+    let (qualifier, name) = split_call(query.trim());
+    let class_name = qualifier.map(|qualifier| split_call(qualifier).1);
+
+    let query: Vec<char> = name.chars().collect();
+    let max_distance = options
+        .max_distance
+        .unwrap_or_else(|| default_max_distance(query.len()));
+
+    let candidates = match options.mode {
+        MatchMode::Exact | MatchMode::Prefix if !options.case_sensitive => index.with_prefix(name),
+        _ => index.symbols(),
+    };
+
+    let mut matches: Vec<SymbolMatch> = candidates
+        .iter()
+        .filter(|symbol| options.kind.is_none_or(|kind| symbol.kind == kind))
+        .filter(|symbol| {
+            class_name.is_none_or(|class_name| {
+                symbol
+                    .class_name
+                    .as_deref()
+                    .is_some_and(|symbol_class| same_name(symbol_class, class_name, options))
+            })
+        })
+        .filter_map(|symbol| {
+            let (match_type, score, positions) =
+                match_name(&symbol.name, &query, max_distance, options)?;
+
+            Some(SymbolMatch {
+                symbol: symbol.to_owned(),
+                match_type,
+                score,
+                positions,
+            })
+        })
+        .collect();
+
+    matches.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(a.symbol.name.len().cmp(&b.symbol.name.len()))
+            .then(a.symbol.name.cmp(&b.symbol.name))
+            .then(a.symbol.id.cmp(&b.symbol.id))
+    });
+    matches.truncate(options.limit);
+
+    let suggestions = if matches
+        .iter()
+        .any(|symbol_match| symbol_match.match_type == MatchType::Exact)
+    {
+        Vec::new()
+    } else {
+        suggest(index, &query, max_distance.max(1), options.suggestions)
+    };
+
+    SymbolSearchResults {
+        matches,
+        suggestions,
+    }
+}
+
+/// The default maximum number of edits of an edit-distance match, by query length.
+fn default_max_distance(length: usize) -> usize {
+    match length {
+        0..=2 => 0,
+        3..=5 => 1,
+        6..=9 => 2,
+        _ => 3,
+    }
+}
+
+/// Whether two names are the same, ignoring case unless the search is case-sensitive.
+fn same_name(a: &str, b: &str, options: &SymbolSearchOptions) -> bool {
+    if options.case_sensitive {
+        a == b
+    } else {
+        a.to_lowercase() == b.to_lowercase()
+    }
+}
+
+/// Matches a name against a query in the modes the search allows, returning the best match.
+fn match_name(
+    name: &str,
+    query: &[char],
+    max_distance: usize,
+    options: &SymbolSearchOptions,
+) -> Option<(MatchType, f32, Vec<usize>)> {
+    if query.is_empty() {
+        return None;
+    }
+
+    let name: Vec<char> = name.chars().collect();
+    let allows = |mode: MatchMode| options.mode == MatchMode::Auto || options.mode == mode;
+
+    let fold = |c: &char| {
+        if options.case_sensitive {
+            *c
+        } else {
+            c.to_lowercase().next().unwrap_or(*c)
+        }
+    };
+    let folded_name: Vec<char> = name.iter().map(fold).collect();
+    let folded_query: Vec<char> = query.iter().map(fold).collect();
+
+    let prefix_positions = || (0..query.len()).collect::<Vec<usize>>();
+
+    if allows(MatchMode::Exact) && folded_name == folded_query {
+        let score = if name == query { 1.0 } else { 0.95 };
+        return Some((MatchType::Exact, score, prefix_positions()));
+    }
+
+    if allows(MatchMode::Prefix) && folded_name.starts_with(&folded_query) {
+        let score = 0.75 + 0.15 * query.len() as f32 / name.len() as f32;
+        return Some((MatchType::Prefix, score, prefix_positions()));
+    }
+
+    let fuzzy = if allows(MatchMode::Fuzzy) {
+        fuzzy_match(&name, &folded_name, &folded_query)
+            .map(|(quality, positions)| (MatchType::Fuzzy, 0.7 * quality, positions))
+    } else {
+        None
+    };
+
+    let edit = if allows(MatchMode::Edit) && max_distance > 0 {
+        edit_distance(&folded_name, &folded_query, max_distance).map(|distance| {
+            let length = name.len().max(query.len()) as f32;
+            let score = 0.7 * (1.0 - distance as f32 / length);
+            (MatchType::Edit, score, Vec::new())
+        })
+    } else {
+        None
+    };
+
+    match (fuzzy, edit) {
+        (Some(fuzzy), Some(edit)) => Some(if edit.1 > fuzzy.1 { edit } else { fuzzy }),
+        (fuzzy, edit) => fuzzy.or(edit),
+    }
+}
+
+/// Matches the characters of a query in order within a name, fzf-style.
+///
+/// Each matched character scores a point, two more if it starts a word (the start of the name,
+/// after `_`, `-`, `.` or `:`, or a capital after a lowercase letter) and one more if it follows
+/// the previous match. The quality of the match is its share of the best possible score, scaled
+/// by how tightly the matched characters are packed.
+///
+/// # Returns
+///
+/// The quality of the match from 0 to 1, and the positions of the matched characters, or
+/// `None` if the name doesn't contain the query's characters in order.
+fn fuzzy_match(name: &[char], folded_name: &[char], query: &[char]) -> Option<(f32, Vec<usize>)> {
+    let mut positions = Vec::with_capacity(query.len());
+    let mut start = 0;
+
+    for c in query {
+        let position = start + folded_name[start..].iter().position(|n| n == c)?;
+        positions.push(position);
+        start = position + 1;
+    }
+
+    let is_word_start = |position: usize| {
+        position == 0
+            || matches!(name[position - 1], '_' | '-' | '.' | ':')
+            || (name[position].is_uppercase() && name[position - 1].is_lowercase())
+    };
+
+    let mut points = 0;
+    for (i, position) in positions.iter().enumerate() {
+        points += 1;
+        if is_word_start(*position) {
+            points += 2;
+        }
+        if i > 0 && positions[i - 1] + 1 == *position {
+            points += 1;
+        }
+    }
+
+    let max_points = 4 * query.len() - 1;
+    let span = positions[positions.len() - 1] - positions[0] + 1;
+    let quality = points as f32 / max_points as f32 * query.len() as f32 / span as f32;
+
+    Some((quality, positions))
+}
+
+/// The optimal string alignment distance between two names: the number of insertions,
+/// deletions, substitutions and transpositions of adjacent characters turning one into the
+/// other.
+///
+/// # Returns
+///
+/// The distance, or `None` if it is over `max_distance`.
+fn edit_distance(a: &[char], b: &[char], max_distance: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max_distance {
+        return None;
+    }
+
+    let mut previous_previous: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current: Vec<usize> = vec![0; b.len() + 1];
+
+    for i in 1..=a.len() {
+        current[0] = i;
+        let mut row_min = current[0];
+
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(previous_previous[j - 2] + 1);
+            }
+
+            row_min = row_min.min(current[j]);
+        }
+
+        // Every later row is at least as far as the closest cell of this one.
+        if row_min > max_distance {
+            return None;
+        }
+
+        std::mem::swap(&mut previous_previous, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    let distance = previous[b.len()];
+    (distance <= max_distance).then_some(distance)
+}
+
+/// Suggests the names closest to a query by edit distance, ignoring case, once per name.
+///
+/// # Arguments
+///
+/// * `index` - The symbols of the project.
+/// * `query` - The query, without its class qualifier.
+/// * `max_distance` - The maximum number of edits of a suggestion.
+/// * `count` - The maximum number of suggestions.
+fn suggest(index: &SymbolIndex, query: &[char], max_distance: usize, count: usize) -> Vec<String> {
+    let folded_query: Vec<char> = query.iter().flat_map(|c| c.to_lowercase()).collect();
+
+    let mut suggestions: Vec<(usize, &str)> = Vec::new();
+    for symbol in index.symbols() {
+        if suggestions
+            .iter()
+            .any(|(_, name)| name.to_lowercase() == symbol.name.to_lowercase())
+        {
+            continue;
+        }
+
+        let folded_name: Vec<char> = symbol.name.chars().flat_map(char::to_lowercase).collect();
+        if let Some(distance) = edit_distance(&folded_name, &folded_query, max_distance) {
+            if distance > 0 {
+                suggestions.push((distance, &symbol.name));
+            }
+        }
+    }
+
+    suggestions.sort();
+    suggestions
+        .into_iter()
+        .take(count)
+        .map(|(_, name)| name.to_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(results: &SymbolSearchResults) -> Vec<&str> {
+        results
+            .matches
+            .iter()
+            .map(|symbol_match| symbol_match.symbol.name.as_str())
+            .collect()
+    }
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn ranks_exact_names_before_prefixes() {
+        let index = SymbolIndex::build(vec![
+            Symbol::for_test(1, None, "insert_block"),
+            Symbol::for_test(2, None, "Inserter"),
+            Symbol::for_test(3, None, "insert"),
+            Symbol::for_test(4, None, "search"),
+        ]);
+
+        let results = search_symbols(&index, "insert", &SymbolSearchOptions::default());
+
+        assert_eq!(names(&results), vec!["insert", "Inserter", "insert_block"]);
+        assert_eq!(results.matches[0].match_type, MatchType::Exact);
+        assert_eq!(results.matches[0].score, 1.0);
+        assert_eq!(results.matches[1].match_type, MatchType::Prefix);
+        assert_eq!(results.matches[1].positions, vec![0, 1, 2, 3, 4, 5]);
+        assert!(results.suggestions.is_empty());
+    }
+
+    #[test]
+    fn prefers_the_query_case_unless_case_sensitive() {
+        let index = SymbolIndex::build(vec![
+            Symbol::for_test(1, None, "Store"),
+            Symbol::for_test(2, None, "store"),
+        ]);
+
+        let options = SymbolSearchOptions {
+            mode: MatchMode::Exact,
+            ..SymbolSearchOptions::default()
+        };
+        let results = search_symbols(&index, "store", &options);
+        assert_eq!(names(&results), vec!["store", "Store"]);
+        assert_eq!(results.matches[1].score, 0.95);
+
+        let options = SymbolSearchOptions {
+            case_sensitive: true,
+            ..options
+        };
+        let results = search_symbols(&index, "store", &options);
+        assert_eq!(names(&results), vec!["store"]);
+    }
+
+    #[test]
+    fn filters_by_class_and_kind() {
+        let index = SymbolIndex::build(vec![
+            Symbol::for_test(1, Some("Store"), "insert"),
+            Symbol::for_test(2, Some("Cache"), "insert"),
+            Symbol::for_test(3, None, "insert"),
+        ]);
+
+        let ids = |results: SymbolSearchResults| -> Vec<i64> {
+            results
+                .matches
+                .iter()
+                .map(|symbol_match| symbol_match.symbol.id)
+                .collect()
+        };
+
+        let options = SymbolSearchOptions::default();
+        assert_eq!(
+            ids(search_symbols(&index, "Store::insert", &options)),
+            vec![1]
+        );
+        assert_eq!(
+            ids(search_symbols(&index, "self.cache.insert", &options)),
+            vec![2]
+        );
+
+        let options = SymbolSearchOptions {
+            kind: Some(SymbolKind::Function),
+            ..SymbolSearchOptions::default()
+        };
+        assert_eq!(ids(search_symbols(&index, "insert", &options)), vec![3]);
+    }
+
+    #[test]
+    fn matches_characters_in_order() {
+        let index = SymbolIndex::build(vec![
+            Symbol::for_test(1, None, "vector_search"),
+            Symbol::for_test(2, None, "insert"),
+        ]);
+        let options = SymbolSearchOptions {
+            mode: MatchMode::Fuzzy,
+            ..SymbolSearchOptions::default()
+        };
+
+        let results = search_symbols(&index, "vs", &options);
+
+        assert_eq!(names(&results), vec!["vector_search"]);
+        assert_eq!(results.matches[0].match_type, MatchType::Fuzzy);
+        assert_eq!(results.matches[0].positions, vec![0, 7]);
+    }
+
+    #[test]
+    fn scores_word_starts_and_adjacent_characters_higher() {
+        let name = chars("get_user_name");
+        let (word_starts, positions) = fuzzy_match(&name, &name, &chars("gun")).unwrap();
+        assert_eq!(positions, vec![0, 4, 9]);
+
+        let name = chars("begun");
+        let (adjacent, positions) = fuzzy_match(&name, &name, &chars("gun")).unwrap();
+        assert_eq!(positions, vec![2, 3, 4]);
+
+        let name = chars("gaudy_inn");
+        let (scattered, _) = fuzzy_match(&name, &name, &chars("gun")).unwrap();
+
+        assert!(word_starts > scattered);
+        assert!(adjacent > scattered);
+        assert!(fuzzy_match(&name, &name, &chars("nug")).is_none());
+    }
+
+    #[test]
+    fn matches_typos_by_edit_distance() {
+        let index = SymbolIndex::build(vec![
+            Symbol::for_test(1, None, "insert"),
+            Symbol::for_test(2, None, "search"),
+        ]);
+
+        let results = search_symbols(&index, "insret", &SymbolSearchOptions::default());
+
+        assert_eq!(names(&results), vec!["insert"]);
+        assert_eq!(results.matches[0].match_type, MatchType::Edit);
+        assert!(results.matches[0].positions.is_empty());
+        assert_eq!(results.suggestions, vec!["insert"]);
+    }
+
+    #[test]
+    fn suggests_close_names_for_short_queries() {
+        let index = SymbolIndex::build(vec![
+            Symbol::for_test(1, None, "ab"),
+            Symbol::for_test(2, Some("Store"), "AB"),
+            Symbol::for_test(3, None, "xyz"),
+        ]);
+
+        let results = search_symbols(&index, "ac", &SymbolSearchOptions::default());
+
+        assert!(results.matches.is_empty());
+        assert_eq!(results.suggestions, vec!["ab"]);
+    }
+
+    #[test]
+    fn truncates_to_the_limit() {
+        let index = SymbolIndex::build(
+            (0..10)
+                .map(|id| Symbol::for_test(id, None, &format!("handle_{}", id)))
+                .collect(),
+        );
+        let options = SymbolSearchOptions {
+            limit: 3,
+            ..SymbolSearchOptions::default()
+        };
+
+        let results = search_symbols(&index, "handle", &options);
+
+        assert_eq!(names(&results), vec!["handle_0", "handle_1", "handle_2"]);
+    }
+
+    #[test]
+    fn ignores_empty_queries() {
+        let index = SymbolIndex::build(vec![Symbol::for_test(1, None, "insert")]);
+
+        let results = search_symbols(&index, "  ", &SymbolSearchOptions::default());
+
+        assert!(results.matches.is_empty());
+    }
+
+    #[test]
+    fn computes_optimal_string_alignment_distances() {
+        assert_eq!(
+            edit_distance(&chars("kitten"), &chars("sitting"), 3),
+            Some(3)
+        );
+        assert_eq!(edit_distance(&chars("kitten"), &chars("sitting"), 2), None);
+        assert_eq!(
+            edit_distance(&chars("insert"), &chars("insret"), 1),
+            Some(1)
+        );
+        assert_eq!(edit_distance(&chars("same"), &chars("same"), 0), Some(0));
+        assert_eq!(edit_distance(&chars("a"), &chars("abcd"), 2), None);
+        assert_eq!(edit_distance(&chars(""), &chars("ab"), 2), Some(2));
+    }
+
+    #[test]
+    fn allows_more_edits_for_longer_queries() {
+        assert_eq!(default_max_distance(2), 0);
+        assert_eq!(default_max_distance(5), 1);
+        assert_eq!(default_max_distance(9), 2);
+        assert_eq!(default_max_distance(10), 3);
+    }
+}
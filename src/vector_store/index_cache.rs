@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::embeddings::encoder::MultiVector;
+use crate::embeddings::index::ProjectIndex;
+use crate::embeddings::preprocess::PreprocessConfig;
+use crate::embeddings::quantize::QuantizedIndex;
+use crate::symbols::index::SymbolIndex;
+use crate::vector_store::segments::Segments;
+use crate::vector_store::sqlite::ProjectSettings;
+
+/// A project loaded into memory, ready to be searched without reading its vectors from disk.
+pub struct LoadedProject {
+    /// The project's settings at the time it was loaded.
+    pub settings: ProjectSettings,
+
+    /// The preprocessing the project's blocks were embedded with.
+    pub preprocess: PreprocessConfig,
+
+    /// The project's memory-mapped vector segments.
+    pub segments: Segments,
+
+    /// The project's nearest-neighbour index, if it needs one built. Flat searches scan the
+    /// segments directly.
+    pub index: Option<ProjectIndex>,
+
+    /// The project's quantized vectors, if it is configured to be searched quantized.
+    pub quantized: Option<QuantizedIndex>,
+
+    /// The signature, docstring, body and summary vectors of every block, for weighted searches.
+    pub named_vectors: Vec<MultiVector>,
+
+    /// The centrality score of each block in the project's call graph, by row id.
+    pub centrality: HashMap<i64, f32>,
+}
+
+impl LoadedProject {
+    /// The approximate number of bytes the project takes up in memory, including its mapped
+    /// segments and named vectors.
+    pub fn size_bytes(&self) -> usize {
+        self.segments.size_bytes()
+            + self.index.as_ref().map_or(0, ProjectIndex::size_bytes)
+            + self
+                .quantized
+                .as_ref()
+                .map_or(0, QuantizedIndex::size_bytes)
+            + self
+                .named_vectors
+                .iter()
+                .map(MultiVector::size_bytes)
+                .sum::<usize>()
+            + self.centrality.capacity() * (size_of::<i64>() + size_of::<f32>())
+    }
+}
+
+struct CacheEntry {
+    project: Arc<LoadedProject>,
+    size: usize,
+    last_used: u64,
+}
+
+/// The size of a project held by the `IndexCache`.
+#[derive(Serialize, Debug)]
+pub struct CachedProject {
+    pub name: String,
+    pub size_bytes: usize,
+}
+
+/// Statistics about the `IndexCache`.
+#[derive(Serialize, Debug)]
+pub struct IndexCacheStats {
+    pub budget_bytes: usize,
+    pub used_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+
+    /// The combined size of the cached symbol indexes, which isn't counted against the budget.
+    pub symbols_bytes: usize,
+
+    /// The cached projects, most recently used first.
+    pub projects: Vec<CachedProject>,
+}
+
+/// An in-process cache of loaded projects, evicting the least recently used projects once their
+/// combined size exceeds a memory budget.
+///
+/// Every change to a project's blocks or settings must `invalidate` it. Each invalidation bumps
+/// the project's generation, so a project that was being loaded while it changed is not cached.
+///
+/// The symbol indexes of projects are cached alongside, outside of the budget: they are small,
+/// and symbol search and autocomplete shouldn't have to load a project's vectors.
+pub struct IndexCache {
+    budget: usize,
+    used: usize,
+    clock: u64,
+    entries: HashMap<String, CacheEntry>,
+    symbols: HashMap<String, Arc<SymbolIndex>>,
+    generations: HashMap<String, u64>,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl IndexCache {
+    /// Creates an empty cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget` - The maximum number of bytes the cached projects may take up.
+    pub fn new(budget: usize) -> Self {
+        IndexCache {
+            budget,
+            used: 0,
+            clock: 0,
+            entries: HashMap::new(),
+            symbols: HashMap::new(),
+            generations: HashMap::new(),
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Retrieves a cached project, marking it as most recently used.
+    ///
+    /// # Arguments
+    ///
+    /// * `project_name` - The name of the project to retrieve.
+    pub fn get(&mut self, project_name: &str) -> Option<Arc<LoadedProject>> {
+        self.clock += 1;
+
+        match self.entries.get_mut(project_name) {
+            Some(entry) => {
+                entry.last_used = self.clock;
+                self.hits += 1;
+                Some(entry.project.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// The current generation of a project. Pass it to `insert` after loading the project.
+    ///
+    /// # Arguments
+    ///
+    /// * `project_name` - The name of the project.
+    pub fn generation(&self, project_name: &str) -> u64 {
+        self.generations.get(project_name).copied().unwrap_or(0)
+    }
+
+    /// Caches a loaded project, evicting the least recently used projects until it fits the
+    /// budget. Projects larger than the whole budget, or that were invalidated since
+    /// `generation` was read, are returned without being cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `project_name` - The name of the project.
+    /// * `generation` - The generation of the project when loading started.
+    /// * `project` - The loaded project.
+    pub fn insert(
+        &mut self,
+        project_name: &str,
+        generation: u64,
+        project: LoadedProject,
+    ) -> Arc<LoadedProject> {
+        let size = project.size_bytes();
+        let project = Arc::new(project);
+
+        if size > self.budget || generation != self.generation(project_name) {
+            return project;
+        }
+
+        self.remove(project_name);
+
+        while self.used + size > self.budget {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(name, _)| name.to_owned())
+            else {
+                break;
+            };
+
+            self.remove(&oldest);
+            self.evictions += 1;
+        }
+
+        self.clock += 1;
+        self.used += size;
+        self.entries.insert(
+            project_name.to_owned(),
+            CacheEntry {
+                project: project.clone(),
+                size,
+                last_used: self.clock,
+            },
+        );
+
+        project
+    }
+
+    /// Retrieves the cached symbol index of a project.
+    ///
+    /// # Arguments
+    ///
+    /// * `project_name` - The name of the project.
+    pub fn get_symbols(&self, project_name: &str) -> Option<Arc<SymbolIndex>> {
+        self.symbols.get(project_name).cloned()
+    }
+
+    /// Caches the symbol index of a project, unless the project was invalidated since
+    /// `generation` was read.
+    ///
+    /// # Arguments
+    ///
+    /// * `project_name` - The name of the project.
+    /// * `generation` - The generation of the project when building the index started.
+    /// * `symbols` - The symbol index of the project.
+    pub fn insert_symbols(
+        &mut self,
+        project_name: &str,
+        generation: u64,
+        symbols: SymbolIndex,
+    ) -> Arc<SymbolIndex> {
+        let symbols = Arc::new(symbols);
+
+        if generation == self.generation(project_name) {
+            self.symbols
+                .insert(project_name.to_owned(), symbols.clone());
+        }
+
+        symbols
+    }
+
+    /// Drops a project from the cache after its blocks or settings changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `project_name` - The name of the project.
+    ///
+    /// # Returns
+    ///
+    /// Whether the project was cached.
+    pub fn invalidate(&mut self, project_name: &str) -> bool {
+        *self.generations.entry(project_name.to_owned()).or_insert(0) += 1;
+        self.symbols.remove(project_name);
+
+        self.remove(project_name)
+    }
+
+    fn remove(&mut self, project_name: &str) -> bool {
+        match self.entries.remove(project_name) {
+            Some(entry) => {
+                self.used -= entry.size;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Retrieves statistics about the cache.
+    pub fn stats(&self) -> IndexCacheStats {
+        let mut entries: Vec<(&String, &CacheEntry)> = self.entries.iter().collect();
+        entries.sort_by(|a, b| b.1.last_used.cmp(&a.1.last_used));
+
+        IndexCacheStats {
+            budget_bytes: self.budget,
+            used_bytes: self.used,
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            symbols_bytes: self
+                .symbols
+                .values()
+                .map(|symbols| symbols.size_bytes())
+                .sum(),
+            projects: entries
+                .into_iter()
+                .map(|(name, entry)| CachedProject {
+                    name: name.to_owned(),
+                    size_bytes: entry.size,
+                })
+                .collect(),
+        }
+    }
+}
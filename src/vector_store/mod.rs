@@ -1,2 +1,4 @@
-mod sqlite;
+pub mod index_cache;
+pub mod segments;
+pub mod sqlite;
 pub mod vector_store;
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use memmap2::Mmap;
+use rayon::prelude::*;
+
+use crate::embeddings::encoder::{Vector, VECTOR_SIZE};
+use crate::embeddings::flat::{self, normalize};
+
+/// The size of a record: a little-endian `i64` row id followed by `VECTOR_SIZE` little-endian `f32`s.
+const RECORD_SIZE: usize = 8 + VECTOR_SIZE * 4;
+
+/// The number of records after which a new segment file is started (about 25 MB).
+const SEGMENT_CAPACITY: usize = 16_384;
+
+/// The name of the header file of a project's segments.
+const HEADER_FILE: &str = "segments.header";
+
+/// The first bytes of a header file, followed by the project generation as a little-endian `i64`.
+const HEADER_MAGIC: &[u8; 8] = b"BLKSEG01";
+
+/// The vectors of a project, stored in append-only segment files and memory-mapped for search.
+///
+/// Searching only touches this vector data; the code of the final hits is fetched from SQLite
+/// by row id afterwards. The segments hold the only full-precision copy of the whole-block
+/// vectors.
+pub struct Segments {
+    maps: Vec<Mmap>,
+
+    /// The location (segment, record) of each row id.
+    locations: HashMap<i64, (usize, usize)>,
+
+    /// The project generation the segments were last written at, or `None` if they have no
+    /// header (e.g. segments written before headers existed).
+    generation: Option<i64>,
+}
+
+impl Segments {
+    /// The directory holding the segment files of a project.
+    fn directory(base: &str, project_name: &str) -> PathBuf {
+        Path::new(base).join(project_name)
+    }
+
+    /// The segment files of a project, in the order they were created.
+    fn segment_files(base: &str, project_name: &str) -> Result<Vec<PathBuf>> {
+        let directory = Self::directory(base, project_name);
+        if !directory.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut files: Vec<PathBuf> = fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "seg")
+            })
+            .collect();
+        files.sort();
+
+        Ok(files)
+    }
+
+    /// Reads the project generation from the header of a project's segments.
+    fn read_header(base: &str, project_name: &str) -> Option<i64> {
+        let bytes = fs::read(Self::directory(base, project_name).join(HEADER_FILE)).ok()?;
+        if bytes.len() != 16 || &bytes[..8] != HEADER_MAGIC {
+            return None;
+        }
+
+        Some(i64::from_le_bytes(bytes[8..].try_into().unwrap()))
+    }
+
+    /// Writes the header of a project's segments, replacing the previous one atomically.
+    fn write_header(base: &str, project_name: &str, generation: i64) -> Result<()> {
+        let directory = Self::directory(base, project_name);
+        let temporary = directory.join(format!("{}.tmp", HEADER_FILE));
+
+        let mut file = File::create(&temporary)?;
+        file.write_all(HEADER_MAGIC)?;
+        file.write_all(&generation.to_le_bytes())?;
+        file.sync_data()?;
+
+        fs::rename(temporary, directory.join(HEADER_FILE))?;
+
+        Ok(())
+    }
+
+    /// Appends vectors to a project's segment files, starting a new segment whenever the last
+    /// one is full, and records the project generation they were written at in the header.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - The directory holding the segment directories of all projects.
+    /// * `project_name` - The name of the project to append to.
+    /// * `generation` - The generation of the project once the vectors are added.
+    /// * `vectors` - Pairs of row id and vector to append.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the segment files can't be written.
+    pub fn append(
+        base: &str,
+        project_name: &str,
+        generation: i64,
+        vectors: &[(i64, &[f32])],
+    ) -> Result<()> {
+        let directory = Self::directory(base, project_name);
+        fs::create_dir_all(&directory)?;
+
+        let mut files = Self::segment_files(base, project_name)?;
+        let mut remaining = vectors;
+
+        while !remaining.is_empty() {
+            let (path, records) = match files.last() {
+                Some(path) => {
+                    let records = fs::metadata(path)?.len() as usize / RECORD_SIZE;
+                    if records < SEGMENT_CAPACITY {
+                        (path.to_owned(), records)
+                    } else {
+                        (directory.join(format!("{:06}.seg", files.len())), 0)
+                    }
+                }
+                None => (directory.join(format!("{:06}.seg", 0)), 0),
+            };
+
+            let (batch, rest) =
+                remaining.split_at((SEGMENT_CAPACITY - records).min(remaining.len()));
+
+            let mut bytes = Vec::with_capacity(batch.len() * RECORD_SIZE);
+            for (id, vector) in batch {
+                bytes.extend_from_slice(&id.to_le_bytes());
+                for value in vector.iter() {
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            file.write_all(&bytes)?;
+            file.sync_data()?;
+
+            if !files.contains(&path) {
+                files.push(path);
+            }
+            remaining = rest;
+        }
+
+        Self::write_header(base, project_name, generation)
+    }
+
+    /// Deletes all segment files of a project.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - The directory holding the segment directories of all projects.
+    /// * `project_name` - The name of the project to delete the segments of.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the segment directory exists but can't be removed.
+    pub fn delete(base: &str, project_name: &str) -> Result<()> {
+        let directory = Self::directory(base, project_name);
+        if directory.exists() {
+            fs::remove_dir_all(directory)?;
+        }
+
+        Ok(())
+    }
+
+    /// Memory-maps the segment files of a project.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - The directory holding the segment directories of all projects.
+    /// * `project_name` - The name of the project to open the segments of.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the segment files can't be opened or mapped.
+    pub fn open(base: &str, project_name: &str) -> Result<Self> {
+        let mut maps = Vec::new();
+        let mut locations = HashMap::new();
+
+        for path in Self::segment_files(base, project_name)? {
+            let file = File::open(&path)?;
+            if file.metadata()?.len() < RECORD_SIZE as u64 {
+                continue;
+            }
+
+            // SAFETY: segment files are only ever appended to, by this process, while holding
+            // the vector store lock, so the mapped bytes don't change underneath us.
+            let map = unsafe { Mmap::map(&file)? };
+
+            let segment = maps.len();
+            for (record, bytes) in map.chunks_exact(RECORD_SIZE).enumerate() {
+                locations.insert(Self::record_id(bytes), (segment, record));
+            }
+
+            maps.push(map);
+        }
+
+        Ok(Segments {
+            maps,
+            locations,
+            generation: Self::read_header(base, project_name),
+        })
+    }
+
+    /// The project generation the segments were last written at, if they have a header.
+    pub fn generation(&self) -> Option<i64> {
+        self.generation
+    }
+
+    /// The approximate number of bytes the segments take up in memory once fully paged in.
+    pub fn size_bytes(&self) -> usize {
+        let mapped: usize = self.maps.iter().map(|map| map.len()).sum();
+
+        mapped + self.locations.capacity() * std::mem::size_of::<(i64, (usize, usize))>()
+    }
+
+    /// The row ids of every vector in the segments, in no particular order.
+    pub fn ids(&self) -> impl Iterator<Item = i64> + '_ {
+        self.locations.keys().copied()
+    }
+
+    /// Whether the segments hold no vectors.
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    /// The row id of a record.
+    fn record_id(record: &[u8]) -> i64 {
+        i64::from_le_bytes(record[..8].try_into().unwrap())
+    }
+
+    /// Decodes the vector of a record.
+    fn record_vector(record: &[u8]) -> [f32; VECTOR_SIZE] {
+        let mut vector = [0.0; VECTOR_SIZE];
+        for (value, bytes) in vector.iter_mut().zip(record[8..].chunks_exact(4)) {
+            *value = f32::from_le_bytes(bytes.try_into().unwrap());
+        }
+
+        vector
+    }
+
+    /// Every record of every segment.
+    fn records(&self) -> impl ParallelIterator<Item = &[u8]> + '_ {
+        self.maps
+            .par_iter()
+            .flat_map(|map| map.par_chunks_exact(RECORD_SIZE))
+    }
+
+    /// Retrieves the vector of a row id.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The row id of the code block.
+    pub fn vector(&self, id: i64) -> Option<[f32; VECTOR_SIZE]> {
+        let (segment, record) = *self.locations.get(&id)?;
+        let offset = record * RECORD_SIZE;
+
+        Some(Self::record_vector(
+            &self.maps[segment][offset..offset + RECORD_SIZE],
+        ))
+    }
+
+    /// Decodes every vector in the segments. Their code is left empty.
+    pub fn vectors(&self) -> Vec<Vector> {
+        self.records()
+            .map(|record| Vector {
+                id: Self::record_id(record),
+                point: Self::record_vector(record),
+                code: String::new(),
+            })
+            .collect()
+    }
+
+    /// Finds the `count` vectors with the highest cosine similarity to a query by scanning the
+    /// mapped segments directly, without copying them.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query vector.
+    /// * `count` - The number of nearest neighbours to return.
+    ///
+    /// # Returns
+    ///
+    /// Pairs of row id and cosine similarity, most similar first.
+    pub fn search(&self, query: &[f32], count: usize) -> Vec<(i64, f32)> {
+        let query = normalize(query);
+
+        let scores: Vec<(i64, f32)> = self
+            .records()
+            .map(|record| {
+                let vector = Self::record_vector(record);
+                let norm = flat::dot(&vector, &vector).sqrt();
+                let dot = flat::dot(&vector, &query);
+
+                let score = if norm == 0.0 { 0.0 } else { dot / norm };
+
+                (Self::record_id(record), score)
+            })
+            .collect();
+
+        flat::top_k(scores, count)
+    }
+}
@@ -1,11 +1,24 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use asterisk::block::{Block, BlockType};
-use rusqlite::{params, Connection};
-use serde::Serialize;
+use rusqlite::{params, types::Value, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use indicatif::{ProgressBar, ProgressStyle};
 
-use crate::{blocks::EmbeddedBlock, embeddings::encoder::Vector};
+use crate::{
+    analysis::clusters::{Cluster, Clustering},
+    blocks::{BlockLocation, EmbeddedBlock},
+    embeddings::encoder::{MultiVector, Vector, VectorKind, MODEL_NAME, VECTOR_SIZE},
+    embeddings::index::IndexType,
+    embeddings::preprocess::PreprocessConfig,
+    embeddings::quantize::{Quantization, QuantizedIndex},
+    embeddings::tokens::TokenCount,
+    graph::calls::CallEdge,
+    graph::centrality::Centrality,
+    symbols::index::{Symbol, SymbolKind},
+};
 
 #[derive(Clone)]
 pub struct SQLite {
@@ -24,9 +37,179 @@ pub struct SQLite {
 pub struct ProjectInfo {
     pub name: String,
     pub total_code_blocks: i32,
+
+    /// The model and embedding path the project's blocks were embedded with, if known.
+    pub embedding_model: Option<String>,
+
+    /// Whether the project's blocks were embedded differently from how they are now (by an
+    /// older version of blockoli), so its searches are less accurate until it is reindexed.
+    pub needs_reindex: bool,
+}
+
+/// Per-project search configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProjectSettings {
+    /// The nearest-neighbour index the project is searched with.
+    pub index: IndexType,
+
+    /// The quantization of the vectors scanned in the first stage of a search.
+    /// When set, it replaces `index`.
+    pub quantization: Quantization,
+
+    /// How many times the needed number of quantized candidates to rescore at full precision.
+    pub rescore: usize,
+}
+
+/// Restricts a search to a subset of a project's blocks.
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilter {
+    /// Only search the blocks of this cluster.
+    pub cluster: Option<usize>,
+
+    /// Only search the blocks of this source file.
+    pub file: Option<String>,
+
+    /// Only search the blocks of source files whose path starts with this prefix.
+    pub path_prefix: Option<String>,
+}
+
+impl SearchFilter {
+    /// Whether the filter lets every block through.
+    pub fn is_empty(&self) -> bool {
+        self.cluster.is_none() && self.file.is_none() && self.path_prefix.is_none()
+    }
+}
+
+/// A code block as stored in a project, with its row id.
+#[derive(Serialize, Debug, Clone)]
+pub struct StoredBlock {
+    pub id: i64,
+
+    #[serde(flatten)]
+    pub block: Block,
+
+    /// Where the block is in its project, if it was located when it was indexed.
+    #[serde(flatten)]
+    pub location: Option<BlockLocation>,
+
+    /// The token counts of the block's content, if they were counted when it was indexed.
+    pub tokens: TokenCount,
+}
+
+impl StoredBlock {
+    /// The number of tokens of the block's content, as charged to a token budget.
+    pub fn token_count(&self) -> usize {
+        self.tokens.budget(&self.block.content)
+    }
+}
+
+#[cfg(test)]
+impl StoredBlock {
+    /// A block for tests: a function (or method) if `function_name` is given, otherwise a class.
+    /// Its node key is `block_{id}`, and it has no outgoing calls, location or token counts.
+    pub fn for_test(
+        id: i64,
+        class_name: Option<&str>,
+        function_name: Option<&str>,
+        content: &str,
+    ) -> StoredBlock {
+        StoredBlock {
+            id,
+            block: Block {
+                node_key: format!("block_{}", id),
+                block_type: if function_name.is_some() {
+                    BlockType::Function
+                } else {
+                    BlockType::Class
+                },
+                content: content.to_owned(),
+                class_name: class_name.map(str::to_owned),
+                function_name: function_name.map(str::to_owned),
+                outgoing_calls: Vec::new(),
+            },
+            location: None,
+            tokens: TokenCount::default(),
+        }
+    }
+}
+
+impl Default for ProjectSettings {
+    fn default() -> Self {
+        ProjectSettings {
+            index: IndexType::default(),
+            quantization: Quantization::default(),
+            rescore: 4,
+        }
+    }
 }
 
+/// The suffixes of the auxiliary tables and indexes of a project, which share SQLite's namespace
+/// with the project tables. A project named with one of them could clash with another project's
+/// tables, e.g. `foo_vectors` with the named vectors of `foo`.
+const RESERVED_SUFFIXES: &[&str] = &[
+    "_vectors",
+    "_quantized",
+    "_clusters",
+    "_clusters_cluster",
+    "_cluster_labels",
+    "_calls",
+    "_calls_caller",
+    "_calls_callee",
+    "_centrality",
+    "_file_path",
+];
+
+/// The names of the tables shared by all projects, and `all`, which stands for every project
+/// in multi-project searches.
+const RESERVED_NAMES: &[&str] = &[
+    "project_registry",
+    "project_settings",
+    "embedding_cache",
+    "all",
+];
+
+/// The largest number of row ids bound to a single `id IN (...)` query, well below SQLite's
+/// limit on the number of parameters of a statement.
+const MAX_QUERY_IDS: usize = 500;
+
 impl SQLite {
+    /// Checks that a new project can be created with a name: it must be alphanumeric or
+    /// underscore characters only, and must not clash with the tables of other projects.
+    ///
+    /// # Arguments
+    ///
+    /// * `project_name` - The project name to check.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing why the name can't be used.
+    pub fn check_project_name(project_name: &str) -> Result<()> {
+        if project_name.is_empty()
+            || !project_name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_')
+        {
+            return Err(anyhow!(
+                "Project name must be alphanumeric or underscore characters only"
+            ));
+        }
+
+        // SQLite table names are case-insensitive.
+        let name = project_name.to_lowercase();
+        if RESERVED_NAMES.contains(&name.as_str()) || name.starts_with("sqlite_") {
+            return Err(anyhow!("Project name {} is reserved", project_name));
+        }
+        if let Some(suffix) = RESERVED_SUFFIXES
+            .iter()
+            .find(|suffix| name.ends_with(*suffix))
+        {
+            return Err(anyhow!("Project names can't end with {}", suffix));
+        }
+
+        Ok(())
+    }
+
     /// Validates that a project name only contains alphanumeric characters or underscores.
     ///
     /// # Arguments
@@ -61,7 +244,7 @@ impl SQLite {
     /// Returns an error if the SQL query fails to execute.
     pub fn does_project_exist(conn: &Connection, project_name: &str) -> Result<bool> {
         Self::validate_project_name(project_name);
-        let query = "SELECT name FROM sqlite_master WHERE type='table' AND name=?";
+        let query = "SELECT name FROM project_registry WHERE name = ?";
         let mut stmt = conn.prepare(query)?;
         let mut rows = stmt.query(params![project_name])?;
 
@@ -72,6 +255,92 @@ impl SQLite {
         }
     }
 
+    /// Creates the registry of projects, in which every project is recorded when it is created.
+    ///
+    /// Databases created before the registry existed have their projects registered from the
+    /// tables with a `node_key` column, which no auxiliary table has.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the SQL queries fail to execute.
+    pub fn create_project_registry(conn: &Connection) -> Result<()> {
+        let exists: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='project_registry'",
+            params![],
+            |row| row.get(0),
+        )?;
+
+        if exists == 0 {
+            conn.execute(
+                "CREATE TABLE project_registry (
+                name TEXT PRIMARY KEY
+            )",
+                params![],
+            )?;
+            conn.execute(
+                "INSERT INTO project_registry (name)
+                SELECT name FROM sqlite_master WHERE type='table' AND sql LIKE '%node_key TEXT NOT NULL%'",
+                params![],
+            )?;
+        }
+
+        Self::add_column_if_missing(conn, "project_registry", "embedding_model", "TEXT")?;
+        Self::add_column_if_missing(
+            conn,
+            "project_registry",
+            "generation",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+
+        Ok(())
+    }
+
+    /// Retrieves the generation of a project, which is bumped every time blocks are inserted
+    /// into it, so data derived from its blocks can tell whether it is out of date.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_generation(conn: &Connection, project_name: &str) -> Result<i64> {
+        Self::validate_project_name(project_name);
+        let mut stmt = conn.prepare("SELECT generation FROM project_registry WHERE name = ?")?;
+        let mut rows = stmt.query(params![project_name])?;
+
+        match rows.next()? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Lists the names of all projects in the SQLite database.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn list_projects(conn: &Connection) -> Result<Vec<String>> {
+        let query = "SELECT name FROM project_registry ORDER BY name";
+        let mut stmt = conn.prepare(query)?;
+
+        let projects = stmt
+            .query_map(params![], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+
+        Ok(projects)
+    }
+
     /// Creates a new project table in the SQLite database.
     ///
     /// # Arguments
@@ -98,6 +367,70 @@ impl SQLite {
             project_name
         );
 
+        conn.execute(&query, params![])?;
+        Self::migrate_project_table(conn, project_name)?;
+        Self::create_named_vectors_table(conn, project_name)?;
+        conn.execute(
+            "INSERT OR IGNORE INTO project_registry (name) VALUES (?)",
+            params![project_name],
+        )?;
+
+        Ok(())
+    }
+
+    /// Adds the columns holding the location of each block to a project table created before
+    /// they existed, and indexes the file path column.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project table to migrate.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the SQL queries fail to execute.
+    pub fn migrate_project_table(conn: &Connection, project_name: &str) -> Result<()> {
+        Self::validate_project_name(project_name);
+
+        Self::add_column_if_missing(conn, project_name, "file_path", "TEXT NOT NULL DEFAULT ''")?;
+        Self::add_column_if_missing(conn, project_name, "start_line", "INTEGER")?;
+        Self::add_column_if_missing(conn, project_name, "end_line", "INTEGER")?;
+        Self::add_column_if_missing(conn, project_name, "start_byte", "INTEGER")?;
+        Self::add_column_if_missing(conn, project_name, "end_byte", "INTEGER")?;
+        Self::add_column_if_missing(conn, project_name, "language", "TEXT NOT NULL DEFAULT ''")?;
+        Self::add_column_if_missing(conn, project_name, "tokens", "INTEGER")?;
+        Self::add_column_if_missing(conn, project_name, "llm_tokens", "INTEGER")?;
+
+        let query = format!(
+            "CREATE INDEX IF NOT EXISTS {0}_file_path ON {0} (file_path)",
+            project_name
+        );
+        conn.execute(&query, params![])?;
+
+        Ok(())
+    }
+
+    /// Creates the table holding the named vectors (signature, docstring, ...) of a project's blocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to create the table for.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    fn create_named_vectors_table(conn: &Connection, project_name: &str) -> Result<()> {
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {}_vectors (
+            block_id INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            vectors TEXT NOT NULL,
+            PRIMARY KEY (block_id, kind)
+        )",
+            project_name
+        );
+
         conn.execute(&query, params![])?;
 
         Ok(())
@@ -117,6 +450,26 @@ impl SQLite {
         Self::validate_project_name(project_name);
         let query = format!("DROP TABLE IF EXISTS {}", project_name);
         conn.execute(&query, params![])?;
+        let query = format!("DROP TABLE IF EXISTS {}_vectors", project_name);
+        conn.execute(&query, params![])?;
+        let query = format!("DROP TABLE IF EXISTS {}_quantized", project_name);
+        conn.execute(&query, params![])?;
+        let query = format!("DROP TABLE IF EXISTS {}_clusters", project_name);
+        conn.execute(&query, params![])?;
+        let query = format!("DROP TABLE IF EXISTS {}_cluster_labels", project_name);
+        conn.execute(&query, params![])?;
+        let query = format!("DROP TABLE IF EXISTS {}_calls", project_name);
+        conn.execute(&query, params![])?;
+        let query = format!("DROP TABLE IF EXISTS {}_centrality", project_name);
+        conn.execute(&query, params![])?;
+        conn.execute(
+            "DELETE FROM project_settings WHERE project_name = ?",
+            params![project_name],
+        )?;
+        conn.execute(
+            "DELETE FROM project_registry WHERE name = ?",
+            params![project_name],
+        )?;
         conn.execute("VACUUM", params![])?;
         Ok(())
     }
@@ -140,31 +493,75 @@ impl SQLite {
         let query = format!("SELECT COUNT(*) FROM {}", project_name);
 
         if let Ok(total_code_blocks) = conn.query_row(&query, params![], |row| row.get(0)) {
+            let embedding_model = Self::get_embedding_model(conn, project_name)?;
+
             return Ok(Some(ProjectInfo {
                 name: project_name.to_owned(),
                 total_code_blocks,
+                needs_reindex: total_code_blocks > 0
+                    && embedding_model.as_deref() != Some(MODEL_NAME),
+                embedding_model,
             }));
         } else {
             return Ok(None);
         }
     }
 
-    /// Inserts a list of code blocks and their embeddings into a SQLite database table.
+    /// Retrieves the model and embedding path all of a project's blocks were embedded with.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    ///
+    /// # Returns
+    ///
+    /// The `MODEL_NAME` the project was indexed with, or `None` if it is unknown, e.g. for
+    /// projects indexed before it was recorded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_embedding_model(conn: &Connection, project_name: &str) -> Result<Option<String>> {
+        Self::validate_project_name(project_name);
+        let mut stmt =
+            conn.prepare("SELECT embedding_model FROM project_registry WHERE name = ?")?;
+        let mut rows = stmt.query(params![project_name])?;
+
+        match rows.next()? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Inserts a list of code blocks and their embeddings into a SQLite database table, and
+    /// bumps the project generation.
+    ///
+    /// The whole-block vectors aren't stored in the table but handed to `store_vectors` before
+    /// the blocks are committed, so no block is ever committed without its vector.
     ///
     /// # Arguments
     ///
     /// * `conn` - The SQLite database connection.
     /// * `project_name` - The name of the project table to insert into.
     /// * `blocks` - The list of `EmbeddedBlock` structs to insert, containing code blocks and their vector embeddings.
+    /// * `store_vectors` - Stores the whole-block vectors, given the new project generation and
+    ///   pairs of row id and vector.
+    ///
+    /// # Returns
+    ///
+    /// The row ids of the inserted blocks, in the same order as `blocks`.
     ///
     /// # Errors
     ///
-    /// Returns an error if any of the SQL queries fail to execute.
+    /// Returns an error if any of the SQL queries fail to execute or `store_vectors` fails, in
+    /// which case no block is inserted.
     pub fn insert_blocks(
         conn: &mut Connection,
         project_name: &str,
         blocks: Vec<EmbeddedBlock>,
-    ) -> Result<()> {
+        store_vectors: impl FnOnce(i64, &[(i64, &[f32])]) -> Result<()>,
+    ) -> Result<Vec<i64>> {
         Self::validate_project_name(project_name);
 
         eprintln!("\n[-] Inserting blocks into {}", project_name);
@@ -173,13 +570,37 @@ impl SQLite {
         progress_bar.set_style(ProgressStyle::default_bar()
             .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} {percent}% {per_sec} ETA: {eta}"));
 
+        Self::create_named_vectors_table(conn, project_name)?;
+
+        let mut block_ids = Vec::with_capacity(blocks.len());
+        let mut vectors = Vec::with_capacity(blocks.len());
         let transaction = conn.transaction()?;
+
+        // The project is only recorded as embedded with the current model if all of its blocks
+        // are, so blocks added to a project indexed by an older version don't hide it.
+        transaction.execute(
+            &format!(
+                "UPDATE project_registry SET embedding_model = ?1 WHERE name = ?2 AND NOT EXISTS (SELECT 1 FROM {})",
+                project_name
+            ),
+            params![MODEL_NAME, project_name],
+        )?;
+        transaction.execute(
+            "UPDATE project_registry SET generation = generation + 1 WHERE name = ?",
+            params![project_name],
+        )?;
         let query = format!(
-            "INSERT INTO {} (node_key, block_type, content, class_name, function_name, outgoing_calls, vectors) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            "INSERT INTO {} (node_key, block_type, content, class_name, function_name, outgoing_calls, vectors, file_path, start_line, end_line, start_byte, end_byte, language, tokens, llm_tokens) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            project_name
+        );
+        let named_vectors_query = format!(
+            "INSERT OR REPLACE INTO {}_vectors (block_id, kind, vectors) VALUES (?1, ?2, ?3)",
             project_name
         );
 
         for block in blocks {
+            let location = block.location.as_ref();
+
             transaction.execute(
                 &query,
                 params![
@@ -189,17 +610,51 @@ impl SQLite {
                     serde_json::to_string(&block.block.class_name.clone()).unwrap(),
                     serde_json::to_string(&block.block.function_name.clone()).unwrap(),
                     serde_json::to_string(&block.block.outgoing_calls).unwrap(),
-                    serde_json::to_string(&block.vectors).unwrap(),
+                    // Only blocks indexed before vector segments existed have their vector here.
+                    "",
+                    location.map_or("", |location| location.file_path.as_str()),
+                    location.map(|location| location.start_line as i64),
+                    location.map(|location| location.end_line as i64),
+                    location.map(|location| location.start_byte as i64),
+                    location.map(|location| location.end_byte as i64),
+                    location.map_or("", |location| location.language.as_str()),
+                    block.tokens.embedding.map(|tokens| tokens as i64),
+                    block.tokens.llm.map(|tokens| tokens as i64),
                 ],
             )?;
 
+            let block_id = transaction.last_insert_rowid();
+            block_ids.push(block_id);
+            vectors.push((block_id, block.vectors));
+            for (kind, vectors) in &block.named_vectors {
+                transaction.execute(
+                    &named_vectors_query,
+                    params![
+                        block_id,
+                        kind.as_str(),
+                        serde_json::to_string(vectors).unwrap()
+                    ],
+                )?;
+            }
+
             progress_bar.inc(1);
         }
 
+        let generation: i64 = transaction.query_row(
+            "SELECT generation FROM project_registry WHERE name = ?",
+            params![project_name],
+            |row| row.get(0),
+        )?;
+        let records: Vec<(i64, &[f32])> = vectors
+            .iter()
+            .map(|(id, vector)| (*id, vector.as_slice()))
+            .collect();
+        store_vectors(generation, &records)?;
+
         transaction.commit()?;
         progress_bar.finish();
 
-        Ok(())
+        Ok(block_ids)
     }
 
     /// Retrieves all code blocks from a SQLite database table that are non-empty functions.
@@ -211,7 +666,7 @@ impl SQLite {
     ///
     /// # Returns
     ///
-    /// A list of `Block` structs representing the retrieved code blocks.
+    /// A list of `StoredBlock` structs representing the retrieved code blocks.
     ///
     /// # Errors
     ///
@@ -219,38 +674,16 @@ impl SQLite {
     pub fn get_all_function_blocks(
         conn: &Connection,
         project_name: &str,
-    ) -> Result<Vec<asterisk::block::Block>> {
+    ) -> Result<Vec<StoredBlock>> {
         Self::validate_project_name(project_name);
-        let query = format!("SELECT * FROM {} WHERE function_name != ''", project_name);
+        let query = format!(
+            "SELECT * FROM {} WHERE function_name != 'null'",
+            project_name
+        );
         let mut stmt = conn.prepare(&query)?;
-        let blocks_iter = stmt.query_map(params![], |row| {
-            let block_type_string = row.get::<_, String>(2)?;
-            let block_type = serde_json::from_str(&block_type_string).unwrap();
-
-            let class_name_string = row.get::<_, String>(4)?;
-            let class_name = serde_json::from_str(&class_name_string).unwrap_or_default();
-
-            let function_name_string = row.get::<_, String>(5)?;
-            let function_name = serde_json::from_str(&function_name_string).unwrap_or_default();
-
-            let outgoing_calls_string = row.get::<_, String>(6)?;
-            let outgoing_calls: Vec<String> = serde_json::from_str(&outgoing_calls_string).unwrap();
-
-            Ok(asterisk::block::Block {
-                node_key: row.get(1)?,
-                block_type,
-                content: row.get(3)?,
-                class_name,
-                function_name,
-                outgoing_calls,
-            })
-        })?;
-
-        let mut blocks = Vec::new();
-
-        for project in blocks_iter {
-            blocks.push(project?);
-        }
+        let blocks = stmt
+            .query_map(params![], Self::stored_block_from_row)?
+            .collect::<rusqlite::Result<Vec<StoredBlock>>>()?;
 
         Ok(blocks)
     }
@@ -265,7 +698,7 @@ impl SQLite {
     ///
     /// # Returns
     ///
-    /// A list of `Block` structs representing the retrieved code blocks that match the query.
+    /// A list of `StoredBlock` structs representing the retrieved code blocks that match the query.
     ///
     /// # Errors
     ///
@@ -274,41 +707,19 @@ impl SQLite {
         conn: &Connection,
         project_name: &str,
         search_code: &str,
-    ) -> Result<Vec<asterisk::block::Block>> {
+    ) -> Result<Vec<StoredBlock>> {
         Self::validate_project_name(project_name);
         let query = format!(
-            "SELECT * FROM {} WHERE function_name != '' AND code LIKE ?",
+            "SELECT * FROM {} WHERE function_name != 'null' AND content LIKE ?",
             project_name
         );
         let mut stmt = conn.prepare(&query)?;
-        let blocks_iter = stmt.query_map(params!["%".to_owned() + search_code + "%"], |row| {
-            let block_type_string = row.get::<_, String>(2)?;
-            let block_type = serde_json::from_str(&block_type_string).unwrap();
-
-            let class_name_string = row.get::<_, String>(4)?;
-            let class_name = serde_json::from_str(&class_name_string).unwrap_or_default();
-
-            let function_name_string = row.get::<_, String>(5)?;
-            let function_name = serde_json::from_str(&function_name_string).unwrap_or_default();
-
-            let outgoing_calls_string = row.get::<_, String>(6)?;
-            let outgoing_calls: Vec<String> = serde_json::from_str(&outgoing_calls_string).unwrap();
-
-            Ok(asterisk::block::Block {
-                node_key: row.get(1)?,
-                block_type,
-                content: row.get(3)?,
-                class_name,
-                function_name,
-                outgoing_calls,
-            })
-        })?;
-
-        let mut blocks = Vec::new();
-
-        for project in blocks_iter {
-            blocks.push(project?);
-        }
+        let blocks = stmt
+            .query_map(
+                params!["%".to_owned() + search_code + "%"],
+                Self::stored_block_from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<StoredBlock>>>()?;
 
         Ok(blocks)
     }
@@ -323,7 +734,7 @@ impl SQLite {
     ///
     /// # Returns
     ///
-    /// A list of `Block` structs representing the retrieved code blocks that match the function name.
+    /// A list of `StoredBlock` structs representing the retrieved code blocks that match the function name.
     ///
     /// # Errors
     ///
@@ -332,84 +743,1728 @@ impl SQLite {
         conn: &Connection,
         project_name: &str,
         function_name: &str,
-    ) -> Result<Vec<asterisk::block::Block>> {
+    ) -> Result<Vec<StoredBlock>> {
         Self::validate_project_name(project_name);
-        let query = format!(
-            "SELECT * FROM {} WHERE function_name != '' AND function_name = ?",
-            project_name,
-        );
+        let query = format!("SELECT * FROM {} WHERE function_name = ?", project_name);
         let mut stmt = conn.prepare(&query)?;
-        let blocks_iter = stmt.query_map(params![function_name], |row| {
-            let block_type_string = row.get::<_, String>(2)?;
-            let block_type = serde_json::from_str(&block_type_string).unwrap();
+        let blocks = stmt
+            .query_map(
+                // Names are stored JSON-encoded, so match against the encoded name.
+                params![serde_json::to_string(&Some(function_name))?],
+                Self::stored_block_from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<StoredBlock>>>()?;
 
-            let class_name_string = row.get::<_, String>(4)?;
-            let class_name = serde_json::from_str(&class_name_string).unwrap_or_default();
+        Ok(blocks)
+    }
 
-            let function_name_string = row.get::<_, String>(5)?;
-            let function_name = serde_json::from_str(&function_name_string).unwrap_or_default();
+    /// Retrieves the row id of every code block of a project, with the whole-block vector stored
+    /// in the project table by versions of blockoli before vector segments, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the table to retrieve vectors from.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute or parsing any of the data fails.
+    pub fn get_legacy_vectors(
+        conn: &Connection,
+        project_name: &str,
+    ) -> Result<Vec<(i64, Option<Vec<f32>>)>> {
+        Self::validate_project_name(project_name);
+        let query = format!("SELECT id, vectors FROM {} ORDER BY id", project_name);
+        let mut stmt = conn.prepare(&query)?;
 
-            let outgoing_calls_string = row.get::<_, String>(6)?;
-            let outgoing_calls: Vec<String> = serde_json::from_str(&outgoing_calls_string).unwrap();
+        let vectors = stmt
+            .query_map(params![], |row| {
+                let id: i64 = row.get(0)?;
+                let vectors_string = row.get::<_, String>(1)?;
 
-            Ok(asterisk::block::Block {
-                node_key: row.get(1)?,
-                block_type,
-                content: row.get(3)?,
-                class_name,
-                function_name,
-                outgoing_calls,
-            })
-        })?;
+                let vectors: Option<Vec<f32>> = serde_json::from_str(&vectors_string).ok();
 
-        let mut blocks = Vec::new();
+                Ok((id, vectors.filter(|vectors| vectors.len() == VECTOR_SIZE)))
+            })?
+            .collect::<rusqlite::Result<Vec<(i64, Option<Vec<f32>>)>>>()?;
 
-        for project in blocks_iter {
-            blocks.push(project?);
-        }
+        Ok(vectors)
+    }
 
-        Ok(blocks)
+    /// Clears the whole-block vectors stored in the project table by versions of blockoli
+    /// before vector segments, once they have been moved to the segments.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn clear_legacy_vectors(conn: &Connection, project_name: &str) -> Result<()> {
+        Self::validate_project_name(project_name);
+        let query = format!(
+            "UPDATE {} SET vectors = '' WHERE vectors != ''",
+            project_name
+        );
+        conn.execute(&query, params![])?;
+
+        Ok(())
     }
 
-    /// Retrieves vector embeddings for code blocks from a SQLite database table.
+    /// Retrieves every named vector of every code block in a project, without the blocks' code.
+    /// The whole-block vectors are kept in the vector segments, not in SQLite, so they are left
+    /// out.
     ///
     /// # Arguments
     ///
     /// * `conn` - The SQLite database connection.
-    /// * `project_name` - The name of the table to retrieve vectors from.
+    /// * `project_name` - The name of the project to retrieve vectors from.
     ///
     /// # Returns
     ///
-    /// A list of `Vector` structs representing the retrieved vectors and their corresponding code blocks.
+    /// A list of `MultiVector` structs, one per code block.
     ///
     /// # Errors
     ///
-    /// Returns an error if the SQL query fails to execute or parsing any of the data fails.
-    pub fn get_code_vectors(conn: &Connection, project_name: &str) -> Result<Vec<Vector>> {
+    /// Returns an error if any of the SQL queries fail to execute or parsing any of the data fails.
+    pub fn get_named_vectors(conn: &Connection, project_name: &str) -> Result<Vec<MultiVector>> {
         Self::validate_project_name(project_name);
-        let query = format!("SELECT * FROM {}", project_name);
-        let mut stmt = conn.prepare(&query)?;
-        let project_iter = stmt.query_map(params![], |row| {
-            let content: String = row.get(2)?;
-
-            let vectors_string = row.get::<_, String>(7)?;
-            let vectors: Vec<f32> = serde_json::from_str(&vectors_string).unwrap();
-
-            Ok((vectors, content))
-        })?;
+        Self::create_named_vectors_table(conn, project_name)?;
 
-        let mut code_vectors = Vec::new();
+        let query = format!("SELECT id FROM {}", project_name);
+        let mut stmt = conn.prepare(&query)?;
+        let block_iter = stmt.query_map(params![], |row| row.get::<_, i64>(0))?;
 
-        for project in project_iter {
-            let project = project?;
-            let code_vector = Vector {
-                point: project.0.as_slice().try_into().unwrap(),
-                code: project.1,
-            };
+        let mut blocks: HashMap<i64, MultiVector> = HashMap::new();
+        let mut order = Vec::new();
 
-            code_vectors.push(code_vector);
+        for id in block_iter {
+            let id = id?;
+            order.push(id);
+            blocks.insert(
+                id,
+                MultiVector {
+                    id,
+                    vectors: HashMap::new(),
+                },
+            );
         }
 
-        Ok(code_vectors)
+        let query = format!(
+            "SELECT block_id, kind, vectors FROM {}_vectors",
+            project_name
+        );
+        let mut stmt = conn.prepare(&query)?;
+        let vector_iter = stmt.query_map(params![], |row| {
+            let block_id: i64 = row.get(0)?;
+            let kind: String = row.get(1)?;
+
+            let vectors_string = row.get::<_, String>(2)?;
+            let vectors: Vec<f32> = serde_json::from_str(&vectors_string).unwrap();
+
+            Ok((block_id, kind, vectors))
+        })?;
+
+        for vector in vector_iter {
+            let (block_id, kind, vectors) = vector?;
+            if let Some(block) = blocks.get_mut(&block_id) {
+                block.vectors.insert(VectorKind::parse(&kind)?, vectors);
+            }
+        }
+
+        Ok(order
+            .into_iter()
+            .filter_map(|id| blocks.remove(&id))
+            .collect())
+    }
+
+    /// Creates the table holding the settings of every project, if it doesn't exist yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn create_settings_table(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS project_settings (
+            project_name TEXT PRIMARY KEY,
+            index_type TEXT NOT NULL
+        )",
+            params![],
+        )?;
+
+        Self::add_column_if_missing(
+            conn,
+            "project_settings",
+            "quantization",
+            "TEXT NOT NULL DEFAULT 'none'",
+        )?;
+        Self::add_column_if_missing(
+            conn,
+            "project_settings",
+            "rescore",
+            "INTEGER NOT NULL DEFAULT 4",
+        )?;
+        Self::add_column_if_missing(conn, "project_settings", "preprocess", "TEXT")?;
+
+        Ok(())
+    }
+
+    /// Adds a column to a table created by an older version of blockoli, if it doesn't have it yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `table` - The name of the table to migrate.
+    /// * `column` - The name of the column to add.
+    /// * `definition` - The type and constraints of the column, e.g. `TEXT NOT NULL DEFAULT ''`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the SQL queries fail to execute.
+    fn add_column_if_missing(
+        conn: &Connection,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<()> {
+        let query = format!(
+            "SELECT COUNT(*) FROM pragma_table_info('{}') WHERE name = ?",
+            table
+        );
+        let exists: i64 = conn.query_row(&query, params![column], |row| row.get(0))?;
+
+        if exists == 0 {
+            let query = format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition);
+            conn.execute(&query, params![])?;
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves the settings of a project, falling back to the defaults if none were stored.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to retrieve the settings of.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_project_settings(conn: &Connection, project_name: &str) -> Result<ProjectSettings> {
+        Self::validate_project_name(project_name);
+        let mut stmt = conn.prepare(
+            "SELECT index_type, quantization, rescore FROM project_settings WHERE project_name = ?",
+        )?;
+        let mut rows = stmt.query(params![project_name])?;
+
+        match rows.next()? {
+            Some(row) => Ok(ProjectSettings {
+                index: IndexType::parse(&row.get::<_, String>(0)?),
+                quantization: Quantization::parse(&row.get::<_, String>(1)?),
+                rescore: row.get::<_, i64>(2)? as usize,
+            }),
+            None => Ok(ProjectSettings::default()),
+        }
+    }
+
+    /// Stores the settings of a project, replacing any previous settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to store the settings of.
+    /// * `settings` - The settings to store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn set_project_settings(
+        conn: &Connection,
+        project_name: &str,
+        settings: &ProjectSettings,
+    ) -> Result<()> {
+        Self::validate_project_name(project_name);
+        conn.execute(
+            "INSERT INTO project_settings (project_name, index_type, quantization, rescore) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (project_name) DO UPDATE SET index_type = excluded.index_type, quantization = excluded.quantization, rescore = excluded.rescore",
+            params![
+                project_name,
+                settings.index.as_str(),
+                settings.quantization.as_str(),
+                settings.rescore as i64
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Retrieves the preprocessing a project's blocks were embedded with, so that code queries
+    /// can be preprocessed the same way.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    ///
+    /// # Returns
+    ///
+    /// The stored `PreprocessConfig`, or the default one for projects indexed before it was stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute or the stored config can't be parsed.
+    pub fn get_preprocess_config(
+        conn: &Connection,
+        project_name: &str,
+    ) -> Result<PreprocessConfig> {
+        Self::validate_project_name(project_name);
+        let mut stmt =
+            conn.prepare("SELECT preprocess FROM project_settings WHERE project_name = ?")?;
+        let mut rows = stmt.query(params![project_name])?;
+        let config: Option<String> = match rows.next()? {
+            Some(row) => row.get(0)?,
+            None => None,
+        };
+
+        match config {
+            Some(config) => Ok(serde_json::from_str(&config)?),
+            None => Ok(PreprocessConfig::default()),
+        }
+    }
+
+    /// Stores the preprocessing a project's blocks were embedded with, keeping its other settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    /// * `config` - The preprocessing the blocks were embedded with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn set_preprocess_config(
+        conn: &Connection,
+        project_name: &str,
+        config: &PreprocessConfig,
+    ) -> Result<()> {
+        Self::validate_project_name(project_name);
+        let defaults = ProjectSettings::default();
+        conn.execute(
+            "INSERT INTO project_settings (project_name, index_type, quantization, rescore, preprocess) VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (project_name) DO UPDATE SET preprocess = excluded.preprocess",
+            params![
+                project_name,
+                defaults.index.as_str(),
+                defaults.quantization.as_str(),
+                defaults.rescore as i64,
+                serde_json::to_string(config)?
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Retrieves the quantized index of a project, (re)building it from the full-precision
+    /// vectors first if it is missing, uses a different quantization, or is out of date.
+    ///
+    /// Every quantized vector records the project generation it was built at, so the index is
+    /// rebuilt after any insert, even one that leaves the number of blocks unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to retrieve the quantized index of.
+    /// * `quantization` - The quantization the index should use.
+    /// * `vectors` - Returns the full-precision vectors of the project, if the index has to be
+    ///   rebuilt.
+    ///
+    /// # Returns
+    ///
+    /// A `QuantizedIndex` over every code block of the project.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the SQL queries fail to execute or parsing any of the data fails.
+    pub fn get_quantized_index(
+        conn: &Connection,
+        project_name: &str,
+        quantization: Quantization,
+        vectors: impl FnOnce() -> Vec<Vector>,
+    ) -> Result<QuantizedIndex> {
+        Self::validate_project_name(project_name);
+
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {}_quantized (
+            block_id INTEGER PRIMARY KEY,
+            quantization TEXT NOT NULL,
+            codes BLOB NOT NULL
+        )",
+            project_name
+        );
+        conn.execute(&query, params![])?;
+        Self::add_column_if_missing(
+            conn,
+            &format!("{}_quantized", project_name),
+            "generation",
+            "INTEGER NOT NULL DEFAULT -1",
+        )?;
+
+        let generation = Self::get_generation(conn, project_name)?;
+
+        let query = format!(
+            "SELECT (SELECT COUNT(*) FROM {0}_quantized WHERE quantization = ?1 AND generation = ?2),
+                    (SELECT COUNT(*) FROM {0}_quantized)",
+            project_name
+        );
+        let (current, quantized): (i64, i64) =
+            conn.query_row(&query, params![quantization.as_str(), generation], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+
+        if current != quantized || quantized == 0 {
+            Self::rebuild_quantized_index(
+                conn,
+                project_name,
+                quantization,
+                generation,
+                &vectors(),
+            )?;
+        }
+
+        let query = format!(
+            "SELECT block_id, codes FROM {}_quantized ORDER BY block_id",
+            project_name
+        );
+        let mut stmt = conn.prepare(&query)?;
+        let mut rows = stmt.query(params![])?;
+
+        let mut index = QuantizedIndex {
+            quantization,
+            ids: Vec::new(),
+            codes: Vec::new(),
+        };
+
+        while let Some(row) = rows.next()? {
+            index.ids.push(row.get(0)?);
+            index.codes.extend(row.get::<_, Vec<u8>>(1)?);
+        }
+
+        Ok(index)
+    }
+
+    /// Replaces the quantized vectors of a project with freshly quantized full-precision vectors,
+    /// built at the given project generation.
+    fn rebuild_quantized_index(
+        conn: &Connection,
+        project_name: &str,
+        quantization: Quantization,
+        generation: i64,
+        code_vectors: &[Vector],
+    ) -> Result<()> {
+        eprintln!(
+            "\n[-] Quantizing {} vectors ({})",
+            project_name,
+            quantization.as_str()
+        );
+
+        let transaction = conn.unchecked_transaction()?;
+        transaction.execute(
+            &format!("DELETE FROM {}_quantized", project_name),
+            params![],
+        )?;
+
+        {
+            let query = format!(
+                "INSERT INTO {}_quantized (block_id, quantization, codes, generation) VALUES (?1, ?2, ?3, ?4)",
+                project_name
+            );
+            let mut stmt = transaction.prepare(&query)?;
+
+            for vector in code_vectors {
+                stmt.execute(params![
+                    vector.id,
+                    quantization.as_str(),
+                    quantization.encode(&vector.point),
+                    generation
+                ])?;
+            }
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Retrieves the code of specific code blocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to retrieve code from.
+    /// * `ids` - The row ids of the code blocks to retrieve.
+    ///
+    /// # Returns
+    ///
+    /// A map from row id to code for the code blocks that exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_contents(
+        conn: &Connection,
+        project_name: &str,
+        ids: &[i64],
+    ) -> Result<HashMap<i64, String>> {
+        Self::validate_project_name(project_name);
+        let query = format!("SELECT id, content FROM {}", project_name);
+
+        let mut contents: HashMap<i64, String> = HashMap::new();
+
+        Self::for_each_row_by_id(conn, &query, ids, |row| {
+            contents.insert(row.get("id")?, row.get("content")?);
+            Ok(())
+        })?;
+
+        Ok(contents)
+    }
+
+    /// Retrieves every code block of a project, with its row id.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to retrieve blocks from.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute or parsing any of the data fails.
+    pub fn get_blocks(conn: &Connection, project_name: &str) -> Result<Vec<StoredBlock>> {
+        Self::validate_project_name(project_name);
+        let query = format!("SELECT * FROM {} ORDER BY id", project_name);
+        let mut stmt = conn.prepare(&query)?;
+
+        let blocks = stmt
+            .query_map(params![], Self::stored_block_from_row)?
+            .collect::<rusqlite::Result<Vec<StoredBlock>>>()?;
+
+        Ok(blocks)
+    }
+
+    /// Parses a `StoredBlock` from a row of `SELECT *` on a project table.
+    fn stored_block_from_row(row: &rusqlite::Row) -> rusqlite::Result<StoredBlock> {
+        let block_type_string = row.get::<_, String>(2)?;
+        let block_type: BlockType = serde_json::from_str(&block_type_string).unwrap();
+
+        let class_name_string = row.get::<_, String>(4)?;
+        let class_name = serde_json::from_str(&class_name_string).unwrap_or_default();
+
+        let function_name_string = row.get::<_, String>(5)?;
+        let function_name = serde_json::from_str(&function_name_string).unwrap_or_default();
+
+        let outgoing_calls_string = row.get::<_, String>(6)?;
+        let outgoing_calls: Vec<String> = serde_json::from_str(&outgoing_calls_string).unwrap();
+
+        Ok(StoredBlock {
+            id: row.get(0)?,
+            block: Block {
+                node_key: row.get(1)?,
+                block_type,
+                content: row.get(3)?,
+                class_name,
+                function_name,
+                outgoing_calls,
+            },
+            location: Self::location_from_row(row)?,
+            tokens: Self::token_count_from_row(row)?,
+        })
+    }
+
+    /// Parses the `TokenCount` of a row with the token columns of a project table. Blocks
+    /// indexed before tokens were counted have no counts.
+    fn token_count_from_row(row: &rusqlite::Row) -> rusqlite::Result<TokenCount> {
+        Ok(TokenCount {
+            embedding: row
+                .get::<_, Option<i64>>("tokens")?
+                .map(|tokens| tokens as usize),
+            llm: row
+                .get::<_, Option<i64>>("llm_tokens")?
+                .map(|tokens| tokens as usize),
+        })
+    }
+
+    /// Parses the `BlockLocation` of a row with the location columns of a project table.
+    /// Blocks indexed before locations were stored, or that couldn't be located, have none.
+    fn location_from_row(row: &rusqlite::Row) -> rusqlite::Result<Option<BlockLocation>> {
+        let file_path: String = row.get("file_path")?;
+        if file_path.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(BlockLocation {
+            file_path,
+            start_line: row.get::<_, Option<i64>>("start_line")?.unwrap_or_default() as usize,
+            end_line: row.get::<_, Option<i64>>("end_line")?.unwrap_or_default() as usize,
+            start_byte: row.get::<_, Option<i64>>("start_byte")?.unwrap_or_default() as usize,
+            end_byte: row.get::<_, Option<i64>>("end_byte")?.unwrap_or_default() as usize,
+            language: row.get("language")?,
+        }))
+    }
+
+    /// Runs a `SELECT` query over the rows with the given ids, in batches of `id IN (...)`
+    /// queries, and calls `f` with every row found.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `query` - The query, without a `WHERE` clause. It must select the `id` column to tell
+    ///   the rows apart.
+    /// * `ids` - The row ids to select.
+    /// * `f` - Called with every row found, in no particular order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute or `f` fails.
+    fn for_each_row_by_id(
+        conn: &Connection,
+        query: &str,
+        ids: &[i64],
+        mut f: impl FnMut(&rusqlite::Row) -> Result<()>,
+    ) -> Result<()> {
+        for chunk in ids.chunks(MAX_QUERY_IDS) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let mut stmt = conn.prepare(&format!("{} WHERE id IN ({})", query, placeholders))?;
+            let mut rows = stmt.query(rusqlite::params_from_iter(chunk))?;
+
+            while let Some(row) = rows.next()? {
+                f(row)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves the locations of code blocks by their row ids.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the blocks belong to.
+    /// * `ids` - The row ids of the blocks.
+    ///
+    /// # Returns
+    ///
+    /// The location of each located block, by row id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_locations(
+        conn: &Connection,
+        project_name: &str,
+        ids: &[i64],
+    ) -> Result<HashMap<i64, BlockLocation>> {
+        Self::validate_project_name(project_name);
+        let query = format!(
+            "SELECT id, file_path, start_line, end_line, start_byte, end_byte, language FROM {}",
+            project_name
+        );
+
+        let mut locations: HashMap<i64, BlockLocation> = HashMap::new();
+
+        Self::for_each_row_by_id(conn, &query, ids, |row| {
+            if let Some(location) = Self::location_from_row(row)? {
+                locations.insert(row.get("id")?, location);
+            }
+            Ok(())
+        })?;
+
+        Ok(locations)
+    }
+
+    /// Retrieves the number of tokens of code blocks by their row ids, as charged to a token
+    /// budget. See `TokenCount::budget`.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the blocks belong to.
+    /// * `ids` - The row ids of the blocks.
+    ///
+    /// # Returns
+    ///
+    /// The number of tokens of each block that exists, by row id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_token_counts(
+        conn: &Connection,
+        project_name: &str,
+        ids: &[i64],
+    ) -> Result<HashMap<i64, usize>> {
+        Self::validate_project_name(project_name);
+        let query = format!(
+            "SELECT id, content, tokens, llm_tokens FROM {}",
+            project_name
+        );
+
+        let mut token_counts: HashMap<i64, usize> = HashMap::new();
+
+        Self::for_each_row_by_id(conn, &query, ids, |row| {
+            let content: String = row.get("content")?;
+            let tokens = Self::token_count_from_row(row)?;
+            token_counts.insert(row.get("id")?, tokens.budget(&content));
+            Ok(())
+        })?;
+
+        Ok(token_counts)
+    }
+
+    /// Creates the tables holding the cluster of each block of a project and the labels of
+    /// each cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to create the tables for.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    fn create_cluster_tables(conn: &Connection, project_name: &str) -> Result<()> {
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {}_clusters (
+            block_id INTEGER PRIMARY KEY,
+            cluster INTEGER NOT NULL
+        )",
+            project_name
+        );
+        conn.execute(&query, params![])?;
+
+        let query = format!(
+            "CREATE INDEX IF NOT EXISTS {0}_clusters_cluster ON {0}_clusters (cluster)",
+            project_name
+        );
+        conn.execute(&query, params![])?;
+
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {}_cluster_labels (
+            cluster INTEGER PRIMARY KEY,
+            size INTEGER NOT NULL,
+            labels TEXT NOT NULL
+        )",
+            project_name
+        );
+        conn.execute(&query, params![])?;
+
+        Ok(())
+    }
+
+    /// Replaces the clusters of a project.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the clusters belong to.
+    /// * `clustering` - The clusters and the cluster of each block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the SQL queries fail to execute.
+    pub fn set_clusters(
+        conn: &Connection,
+        project_name: &str,
+        clustering: &Clustering,
+    ) -> Result<()> {
+        Self::validate_project_name(project_name);
+        Self::create_cluster_tables(conn, project_name)?;
+
+        let transaction = conn.unchecked_transaction()?;
+        transaction.execute(&format!("DELETE FROM {}_clusters", project_name), params![])?;
+        transaction.execute(
+            &format!("DELETE FROM {}_cluster_labels", project_name),
+            params![],
+        )?;
+
+        let query = format!(
+            "INSERT INTO {}_clusters (block_id, cluster) VALUES (?1, ?2)",
+            project_name
+        );
+        for (block_id, cluster) in &clustering.assignments {
+            transaction.execute(&query, params![block_id, *cluster as i64])?;
+        }
+
+        let query = format!(
+            "INSERT INTO {}_cluster_labels (cluster, size, labels) VALUES (?1, ?2, ?3)",
+            project_name
+        );
+        for cluster in &clustering.clusters {
+            transaction.execute(
+                &query,
+                params![
+                    cluster.id as i64,
+                    cluster.size as i64,
+                    serde_json::to_string(&cluster.labels).unwrap()
+                ],
+            )?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Retrieves the clusters of a project.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to retrieve the clusters of.
+    ///
+    /// # Returns
+    ///
+    /// The clusters of the project, largest first, or an empty list if it wasn't clustered yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_clusters(conn: &Connection, project_name: &str) -> Result<Vec<Cluster>> {
+        Self::validate_project_name(project_name);
+        Self::create_cluster_tables(conn, project_name)?;
+
+        let query = format!(
+            "SELECT cluster, size, labels FROM {}_cluster_labels ORDER BY cluster",
+            project_name
+        );
+        let mut stmt = conn.prepare(&query)?;
+
+        let clusters = stmt
+            .query_map(params![], |row| {
+                let labels_string = row.get::<_, String>(2)?;
+
+                Ok(Cluster {
+                    id: row.get::<_, i64>(0)? as usize,
+                    size: row.get::<_, i64>(1)? as usize,
+                    labels: serde_json::from_str(&labels_string).unwrap_or_default(),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<Cluster>>>()?;
+
+        Ok(clusters)
+    }
+
+    /// Retrieves the cluster of every clustered block of a project.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to retrieve the clusters of.
+    ///
+    /// # Returns
+    ///
+    /// The cluster of each block, by row id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_block_clusters(
+        conn: &Connection,
+        project_name: &str,
+    ) -> Result<HashMap<i64, usize>> {
+        Self::validate_project_name(project_name);
+        Self::create_cluster_tables(conn, project_name)?;
+
+        let query = format!("SELECT block_id, cluster FROM {}_clusters", project_name);
+        let mut stmt = conn.prepare(&query)?;
+
+        let clusters = stmt
+            .query_map(params![], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)? as usize))
+            })?
+            .collect::<rusqlite::Result<HashMap<i64, usize>>>()?;
+
+        Ok(clusters)
+    }
+
+    /// Retrieves the code blocks of a cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the cluster belongs to.
+    /// * `cluster` - The id of the cluster.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute or parsing any of the data fails.
+    pub fn get_cluster_blocks(
+        conn: &Connection,
+        project_name: &str,
+        cluster: usize,
+    ) -> Result<Vec<StoredBlock>> {
+        Self::validate_project_name(project_name);
+        Self::create_cluster_tables(conn, project_name)?;
+
+        let query = format!(
+            "SELECT p.* FROM {0} p JOIN {0}_clusters c ON c.block_id = p.id WHERE c.cluster = ? ORDER BY p.id",
+            project_name
+        );
+        let mut stmt = conn.prepare(&query)?;
+
+        let blocks = stmt
+            .query_map(params![cluster as i64], Self::stored_block_from_row)?
+            .collect::<rusqlite::Result<Vec<StoredBlock>>>()?;
+
+        Ok(blocks)
+    }
+
+    /// Retrieves the row ids of the blocks of a project that pass a search filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to filter the blocks of.
+    /// * `filter` - The filter to apply.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn filter_block_ids(
+        conn: &Connection,
+        project_name: &str,
+        filter: &SearchFilter,
+    ) -> Result<HashSet<i64>> {
+        Self::validate_project_name(project_name);
+        Self::create_cluster_tables(conn, project_name)?;
+
+        let mut query = format!("SELECT p.id FROM {0} p", project_name);
+        let mut conditions: Vec<String> = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+
+        if let Some(cluster) = filter.cluster {
+            query.push_str(&format!(
+                " JOIN {}_clusters c ON c.block_id = p.id",
+                project_name
+            ));
+            conditions.push("c.cluster = ?".to_owned());
+            values.push(Value::Integer(cluster as i64));
+        }
+
+        if let Some(file) = &filter.file {
+            conditions.push("p.file_path = ?".to_owned());
+            values.push(Value::Text(file.to_owned()));
+        }
+
+        if let Some(path_prefix) = &filter.path_prefix {
+            // GLOB is case-sensitive, like file paths, and can use the file path index.
+            let pattern: String = path_prefix
+                .chars()
+                .map(|c| match c {
+                    '*' | '?' | '[' => format!("[{}]", c),
+                    c => c.to_string(),
+                })
+                .collect();
+
+            conditions.push("p.file_path GLOB ?".to_owned());
+            values.push(Value::Text(pattern + "*"));
+        }
+
+        if !conditions.is_empty() {
+            query.push_str(" WHERE ");
+            query.push_str(&conditions.join(" AND "));
+        }
+
+        let mut stmt = conn.prepare(&query)?;
+        let ids = stmt
+            .query_map(rusqlite::params_from_iter(values), |row| row.get(0))?
+            .collect::<rusqlite::Result<HashSet<i64>>>()?;
+
+        Ok(ids)
+    }
+
+    /// Looks up the row id of a code block by its node key.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the block belongs to.
+    /// * `node_key` - The node key of the block.
+    ///
+    /// # Returns
+    ///
+    /// The row id of the first block with the node key, or `None` if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn find_block_id(
+        conn: &Connection,
+        project_name: &str,
+        node_key: &str,
+    ) -> Result<Option<i64>> {
+        Self::validate_project_name(project_name);
+        let query = format!(
+            "SELECT id FROM {} WHERE node_key = ? ORDER BY id LIMIT 1",
+            project_name
+        );
+        let mut stmt = conn.prepare(&query)?;
+        let mut rows = stmt.query(params![node_key])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Looks up the row ids of the blocks defining a function.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the function belongs to.
+    /// * `function_name` - The name of the function.
+    /// * `class_name` - If given, only the function of this class is looked up.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn find_function_ids(
+        conn: &Connection,
+        project_name: &str,
+        function_name: &str,
+        class_name: Option<&str>,
+    ) -> Result<Vec<i64>> {
+        Self::validate_project_name(project_name);
+        let query = format!(
+            "SELECT id FROM {} WHERE function_name = ?1 AND (?2 IS NULL OR class_name = ?2) ORDER BY id",
+            project_name
+        );
+        let mut stmt = conn.prepare(&query)?;
+
+        // Names are stored JSON-encoded, like the rest of the block's fields.
+        let ids = stmt
+            .query_map(
+                params![
+                    serde_json::to_string(&Some(function_name)).unwrap(),
+                    class_name.map(|class_name| serde_json::to_string(&Some(class_name)).unwrap()),
+                ],
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+
+        Ok(ids)
+    }
+
+    /// Looks up the row ids of the blocks of a class itself, as opposed to its methods.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the class belongs to.
+    /// * `class_name` - The name of the class.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn find_class_ids(
+        conn: &Connection,
+        project_name: &str,
+        class_name: &str,
+    ) -> Result<Vec<i64>> {
+        Self::validate_project_name(project_name);
+        let query = format!(
+            "SELECT id FROM {} WHERE class_name = ? AND function_name = 'null' ORDER BY id",
+            project_name
+        );
+        let mut stmt = conn.prepare(&query)?;
+
+        let ids = stmt
+            .query_map(
+                params![serde_json::to_string(&Some(class_name)).unwrap()],
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+
+        Ok(ids)
+    }
+
+    /// Retrieves the functions and classes defined by the blocks of a project.
+    ///
+    /// A block defining a function is a function symbol, or a method if it belongs to a class,
+    /// and a block of a class without a function is a class symbol.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to retrieve symbols from.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute or parsing any of the data fails.
+    pub fn get_symbols(conn: &Connection, project_name: &str) -> Result<Vec<Symbol>> {
+        Self::validate_project_name(project_name);
+        let query = format!(
+            "SELECT id, node_key, block_type, class_name, function_name, file_path, start_line FROM {} WHERE function_name != 'null' OR class_name != 'null' ORDER BY id",
+            project_name
+        );
+        let mut stmt = conn.prepare(&query)?;
+
+        let symbols = stmt
+            .query_map(params![], |row| {
+                let block_type_string = row.get::<_, String>(2)?;
+                let block_type: BlockType = serde_json::from_str(&block_type_string).unwrap();
+
+                let class_name: Option<String> =
+                    serde_json::from_str(&row.get::<_, String>(3)?).unwrap_or_default();
+                let function_name: Option<String> =
+                    serde_json::from_str(&row.get::<_, String>(4)?).unwrap_or_default();
+
+                let (name, kind) = match (function_name, &class_name) {
+                    (Some(function_name), Some(_)) => (function_name, SymbolKind::Method),
+                    (Some(function_name), None) => (function_name, SymbolKind::Function),
+                    (None, Some(class_name)) => (class_name.to_owned(), SymbolKind::Class),
+                    (None, None) => return Ok(None),
+                };
+
+                let file_path: String = row.get(5)?;
+
+                Ok(Some(Symbol {
+                    id: row.get(0)?,
+                    node_key: row.get(1)?,
+                    name,
+                    kind,
+                    block_type,
+                    class_name: class_name.filter(|_| kind == SymbolKind::Method),
+                    file_path: (!file_path.is_empty()).then_some(file_path),
+                    start_line: row
+                        .get::<_, Option<i64>>(6)?
+                        .map(|start_line| start_line as usize),
+                }))
+            })?
+            .filter_map(|symbol| symbol.transpose())
+            .collect::<rusqlite::Result<Vec<Symbol>>>()?;
+
+        Ok(symbols)
+    }
+
+    /// Retrieves code blocks by their row ids.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the blocks belong to.
+    /// * `ids` - The row ids of the blocks.
+    ///
+    /// # Returns
+    ///
+    /// The blocks that exist, by row id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute or parsing any of the data fails.
+    pub fn get_blocks_by_id(
+        conn: &Connection,
+        project_name: &str,
+        ids: &[i64],
+    ) -> Result<HashMap<i64, StoredBlock>> {
+        Self::validate_project_name(project_name);
+        let query = format!("SELECT * FROM {}", project_name);
+
+        let mut blocks = HashMap::new();
+
+        Self::for_each_row_by_id(conn, &query, ids, |row| {
+            let block = Self::stored_block_from_row(row)?;
+            blocks.insert(block.id, block);
+            Ok(())
+        })?;
+
+        Ok(blocks)
+    }
+
+    /// Creates the table holding the call edges of a project, if it doesn't exist yet.
+    fn create_calls_table(conn: &Connection, project_name: &str) -> Result<()> {
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {}_calls (
+            caller_id INTEGER NOT NULL,
+            callee_id INTEGER,
+            callee_name TEXT NOT NULL
+        )",
+            project_name
+        );
+        conn.execute(&query, params![])?;
+
+        let query = format!(
+            "CREATE INDEX IF NOT EXISTS {0}_calls_caller ON {0}_calls (caller_id)",
+            project_name
+        );
+        conn.execute(&query, params![])?;
+
+        let query = format!(
+            "CREATE INDEX IF NOT EXISTS {0}_calls_callee ON {0}_calls (callee_id)",
+            project_name
+        );
+        conn.execute(&query, params![])?;
+
+        Ok(())
+    }
+
+    /// Checks if the call edges of a project were stored yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn has_call_edges(conn: &Connection, project_name: &str) -> Result<bool> {
+        Self::validate_project_name(project_name);
+        let exists: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name=?",
+            params![format!("{}_calls", project_name)],
+            |row| row.get(0),
+        )?;
+
+        Ok(exists > 0)
+    }
+
+    /// Replaces the call edges of a project.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    /// * `edges` - Every call edge of the project.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the SQL queries fail to execute.
+    pub fn set_call_edges(conn: &Connection, project_name: &str, edges: &[CallEdge]) -> Result<()> {
+        Self::validate_project_name(project_name);
+        Self::create_calls_table(conn, project_name)?;
+
+        let transaction = conn.unchecked_transaction()?;
+        transaction.execute(&format!("DELETE FROM {}_calls", project_name), params![])?;
+
+        let query = format!(
+            "INSERT INTO {}_calls (caller_id, callee_id, callee_name) VALUES (?1, ?2, ?3)",
+            project_name
+        );
+        for edge in edges {
+            transaction.execute(&query, params![edge.caller, edge.callee, edge.callee_name])?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Retrieves the calls made by a code block.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the block belongs to.
+    /// * `block_id` - The row id of the calling block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_callees(
+        conn: &Connection,
+        project_name: &str,
+        block_id: i64,
+    ) -> Result<Vec<CallEdge>> {
+        Self::query_call_edges(conn, project_name, "c.caller_id = ?", params![block_id])
+    }
+
+    /// Retrieves the calls made to a code block.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the block belongs to.
+    /// * `block_id` - The row id of the called block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_callers(
+        conn: &Connection,
+        project_name: &str,
+        block_id: i64,
+    ) -> Result<Vec<CallEdge>> {
+        Self::query_call_edges(conn, project_name, "c.callee_id = ?", params![block_id])
+    }
+
+    /// Retrieves every call edge between two blocks of a project, leaving out calls to functions
+    /// outside the project.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_resolved_call_edges(conn: &Connection, project_name: &str) -> Result<Vec<CallEdge>> {
+        Self::query_call_edges(conn, project_name, "c.callee_id IS NOT NULL", params![])
+    }
+
+    /// Retrieves the call edges matching a condition on the calls table, aliased `c`, with the
+    /// node keys of their blocks.
+    fn query_call_edges(
+        conn: &Connection,
+        project_name: &str,
+        condition: &str,
+        values: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<CallEdge>> {
+        Self::validate_project_name(project_name);
+        Self::create_calls_table(conn, project_name)?;
+
+        let query = format!(
+            "SELECT c.caller_id, caller.node_key, c.callee_id, callee.node_key, c.callee_name
+            FROM {0}_calls c
+            JOIN {0} caller ON caller.id = c.caller_id
+            LEFT JOIN {0} callee ON callee.id = c.callee_id
+            WHERE {1}
+            ORDER BY c.caller_id, c.callee_id, c.callee_name",
+            project_name, condition
+        );
+        let mut stmt = conn.prepare(&query)?;
+
+        let edges = stmt
+            .query_map(values, |row| {
+                Ok(CallEdge {
+                    caller: row.get(0)?,
+                    caller_key: row.get(1)?,
+                    callee: row.get(2)?,
+                    callee_key: row.get(3)?,
+                    callee_name: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<CallEdge>>>()?;
+
+        Ok(edges)
+    }
+
+    /// Creates the table holding the centrality of each block of a project, if it doesn't exist
+    /// yet.
+    fn create_centrality_table(conn: &Connection, project_name: &str) -> Result<()> {
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {}_centrality (
+            block_id INTEGER PRIMARY KEY,
+            in_degree INTEGER NOT NULL,
+            pagerank REAL NOT NULL,
+            score REAL NOT NULL
+        )",
+            project_name
+        );
+        conn.execute(&query, params![])?;
+
+        Ok(())
+    }
+
+    /// Checks if the centrality of a project's blocks was stored yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn has_centrality(conn: &Connection, project_name: &str) -> Result<bool> {
+        Self::validate_project_name(project_name);
+        let exists: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name=?",
+            params![format!("{}_centrality", project_name)],
+            |row| row.get(0),
+        )?;
+
+        Ok(exists > 0)
+    }
+
+    /// Replaces the centrality of a project's blocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    /// * `centrality` - The centrality of every block of the project, by row id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the SQL queries fail to execute.
+    pub fn set_centrality(
+        conn: &Connection,
+        project_name: &str,
+        centrality: &HashMap<i64, Centrality>,
+    ) -> Result<()> {
+        Self::validate_project_name(project_name);
+        Self::create_centrality_table(conn, project_name)?;
+
+        let transaction = conn.unchecked_transaction()?;
+        transaction.execute(
+            &format!("DELETE FROM {}_centrality", project_name),
+            params![],
+        )?;
+
+        let query = format!(
+            "INSERT INTO {}_centrality (block_id, in_degree, pagerank, score) VALUES (?1, ?2, ?3, ?4)",
+            project_name
+        );
+        for (block_id, centrality) in centrality {
+            transaction.execute(
+                &query,
+                params![
+                    block_id,
+                    centrality.in_degree as i64,
+                    centrality.pagerank,
+                    centrality.score as f64
+                ],
+            )?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Retrieves the centrality of every block of a project.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    ///
+    /// # Returns
+    ///
+    /// The centrality of each block, by row id.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn get_centrality(
+        conn: &Connection,
+        project_name: &str,
+    ) -> Result<HashMap<i64, Centrality>> {
+        Self::validate_project_name(project_name);
+        Self::create_centrality_table(conn, project_name)?;
+
+        let query = format!(
+            "SELECT block_id, in_degree, pagerank, score FROM {}_centrality",
+            project_name
+        );
+        let mut stmt = conn.prepare(&query)?;
+
+        let centrality = stmt
+            .query_map(params![], |row| {
+                let centrality = Centrality {
+                    in_degree: row.get::<_, i64>(1)? as usize,
+                    pagerank: row.get(2)?,
+                    score: row.get::<_, f64>(3)? as f32,
+                };
+
+                Ok((row.get(0)?, centrality))
+            })?
+            .collect::<rusqlite::Result<HashMap<i64, Centrality>>>()?;
+
+        Ok(centrality)
+    }
+
+    /// Creates the embedding cache table, shared by all projects, if it doesn't exist yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn create_embedding_cache(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS embedding_cache (
+            model TEXT NOT NULL,
+            content_hash TEXT NOT NULL,
+            vectors TEXT NOT NULL,
+            last_used INTEGER NOT NULL,
+            PRIMARY KEY (model, content_hash)
+        )",
+            params![],
+        )?;
+
+        Ok(())
+    }
+
+    /// Looks up cached embeddings by content hash, marking every hit as recently used.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `model` - The name of the model the embeddings were generated with.
+    /// * `hashes` - The content hashes to look up.
+    ///
+    /// # Returns
+    ///
+    /// A map from content hash to embedding for every hash present in the cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the SQL queries fail to execute or parsing any of the data fails.
+    pub fn get_cached_embeddings(
+        conn: &Connection,
+        model: &str,
+        hashes: &[String],
+    ) -> Result<HashMap<String, Vec<f32>>> {
+        let transaction = conn.unchecked_transaction()?;
+        let now = Self::unix_timestamp();
+        let mut cached = HashMap::new();
+
+        {
+            let mut select = transaction.prepare(
+                "SELECT vectors FROM embedding_cache WHERE model = ?1 AND content_hash = ?2",
+            )?;
+            let mut touch = transaction.prepare(
+                "UPDATE embedding_cache SET last_used = ?3 WHERE model = ?1 AND content_hash = ?2",
+            )?;
+
+            for hash in hashes {
+                if cached.contains_key(hash) {
+                    continue;
+                }
+
+                let mut rows = select.query(params![model, hash])?;
+                if let Some(row) = rows.next()? {
+                    let vectors_string = row.get::<_, String>(0)?;
+                    let vectors: Vec<f32> = serde_json::from_str(&vectors_string)?;
+
+                    touch.execute(params![model, hash, now])?;
+                    cached.insert(hash.to_owned(), vectors);
+                }
+            }
+        }
+
+        transaction.commit()?;
+
+        Ok(cached)
+    }
+
+    /// Inserts embeddings into the cache, replacing any existing entry for the same content.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `model` - The name of the model the embeddings were generated with.
+    /// * `entries` - Pairs of content hash and embedding to store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the SQL queries fail to execute.
+    pub fn insert_cached_embeddings(
+        conn: &Connection,
+        model: &str,
+        entries: &[(String, Vec<f32>)],
+    ) -> Result<()> {
+        let transaction = conn.unchecked_transaction()?;
+        let now = Self::unix_timestamp();
+
+        {
+            let mut stmt = transaction.prepare(
+                "INSERT OR REPLACE INTO embedding_cache (model, content_hash, vectors, last_used) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+
+            for (hash, vectors) in entries {
+                stmt.execute(params![model, hash, serde_json::to_string(vectors)?, now])?;
+            }
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Counts the entries in the embedding cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn count_cached_embeddings(conn: &Connection) -> Result<i64> {
+        let entries = conn.query_row("SELECT COUNT(*) FROM embedding_cache", params![], |row| {
+            row.get(0)
+        })?;
+
+        Ok(entries)
+    }
+
+    /// Evicts entries from the embedding cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `model` - Only evict entries generated with this model, if given.
+    /// * `keep` - Keep this many of the most recently used entries, if given. Otherwise every matching entry is evicted.
+    ///
+    /// # Returns
+    ///
+    /// The number of evicted entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    pub fn evict_embedding_cache(
+        conn: &Connection,
+        model: Option<&str>,
+        keep: Option<usize>,
+    ) -> Result<usize> {
+        let evicted = match keep {
+            Some(keep) => conn.execute(
+                "DELETE FROM embedding_cache WHERE (?1 IS NULL OR model = ?1) AND rowid NOT IN (
+                    SELECT rowid FROM embedding_cache WHERE (?1 IS NULL OR model = ?1)
+                    ORDER BY last_used DESC LIMIT ?2
+                )",
+                params![model, keep as i64],
+            )?,
+            None => conn.execute(
+                "DELETE FROM embedding_cache WHERE (?1 IS NULL OR model = ?1)",
+                params![model],
+            )?,
+        };
+
+        conn.execute("VACUUM", params![])?;
+
+        Ok(evicted)
+    }
+
+    /// Returns the current time as seconds since the Unix epoch.
+    fn unix_timestamp() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embeddings::preprocess::TextMode;
+
+    /// An in-memory database with a project named `project` holding the given blocks. Row ids
+    /// are assigned from 1 in order, so blocks made with `StoredBlock::for_test(id, ...)` for
+    /// ids 1, 2, ... keep their id.
+    fn project(blocks: Vec<StoredBlock>) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        SQLite::create_project_registry(&conn).unwrap();
+        SQLite::create_table(&conn, "project").unwrap();
+
+        let blocks = blocks
+            .into_iter()
+            .map(|block| EmbeddedBlock {
+                block: block.block,
+                vectors: Vec::new(),
+                named_vectors: HashMap::new(),
+                location: block.location,
+                tokens: block.tokens,
+            })
+            .collect();
+        SQLite::insert_blocks(&mut conn, "project", blocks, |_, _| Ok(())).unwrap();
+
+        conn
+    }
+
+    #[test]
+    fn keeps_the_preprocess_config_when_settings_change() {
+        let conn = Connection::open_in_memory().unwrap();
+        SQLite::create_settings_table(&conn).unwrap();
+
+        assert_eq!(
+            SQLite::get_preprocess_config(&conn, "project").unwrap(),
+            PreprocessConfig::default()
+        );
+
+        let config = PreprocessConfig {
+            split_identifiers: false,
+            comments: TextMode::Strip,
+            ..PreprocessConfig::default()
+        };
+        SQLite::set_preprocess_config(&conn, "project", &config).unwrap();
+
+        let settings = ProjectSettings {
+            rescore: 8,
+            ..ProjectSettings::default()
+        };
+        SQLite::set_project_settings(&conn, "project", &settings).unwrap();
+
+        assert_eq!(
+            SQLite::get_preprocess_config(&conn, "project").unwrap(),
+            config
+        );
+        assert_eq!(
+            SQLite::get_project_settings(&conn, "project")
+                .unwrap()
+                .rescore,
+            8
+        );
+    }
+
+    #[test]
+    fn gets_blocks_by_id_in_batches() {
+        let count = MAX_QUERY_IDS as i64 + 100;
+        let conn = project(
+            (1..=count)
+                .map(|id| StoredBlock::for_test(id, None, Some("f"), "fn f() {}"))
+                .collect(),
+        );
+
+        let mut ids: Vec<i64> = (1..=count).collect();
+        ids.push(count + 1);
+        let blocks = SQLite::get_blocks_by_id(&conn, "project", &ids).unwrap();
+
+        assert_eq!(blocks.len(), count as usize);
+        assert_eq!(blocks[&count].block.node_key, format!("block_{}", count));
+        assert!(!blocks.contains_key(&(count + 1)));
+    }
+
+    #[test]
+    fn finds_function_blocks_by_name_and_content() {
+        let conn = project(vec![
+            StoredBlock::for_test(1, Some("C"), None, "class C {}"),
+            StoredBlock::for_test(2, Some("C"), Some("parse"), "fn parse(input: &str) {}"),
+            StoredBlock::for_test(3, None, Some("render"), "fn render(output: &str) {}"),
+        ]);
+        let ids = |blocks: Vec<StoredBlock>| blocks.iter().map(|b| b.id).collect::<Vec<_>>();
+
+        assert_eq!(
+            ids(SQLite::get_all_function_blocks(&conn, "project").unwrap()),
+            vec![2, 3]
+        );
+        assert_eq!(
+            ids(SQLite::search_from_function_blocks(&conn, "project", "output").unwrap()),
+            vec![3]
+        );
+        assert_eq!(
+            ids(SQLite::search_from_function_blocks(&conn, "project", "class").unwrap()),
+            Vec::<i64>::new()
+        );
+        assert_eq!(
+            ids(SQLite::search_by_function_name(&conn, "project", "parse").unwrap()),
+            vec![2]
+        );
     }
 }
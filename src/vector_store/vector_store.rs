@@ -1,15 +1,39 @@
-use crate::blocks::EmbeddedBlock;
-use crate::embeddings::encoder::NearestVectors;
+use crate::analysis::clusters::{Cluster, Clustering};
+use crate::analysis::dead_code::{self, DeadCodeOptions, DeadCodeReport};
+use crate::blocks::{BlockLocation, EmbeddedBlock};
+use crate::embeddings::cache::{CacheStats, EmbeddingCache};
+use crate::embeddings::encoder::{
+    Candidate, MultiVector, NearestVectors, ProjectMatch, SearchOptions, Vector, VectorKind,
+    VECTOR_SIZE,
+};
+use crate::embeddings::index::{self, IndexType, ProjectIndex, RecallReport};
+use crate::embeddings::preprocess::PreprocessConfig;
+use crate::embeddings::quantize::Quantization;
+use crate::graph::calls::{self, BlockCalls};
+use crate::graph::centrality::{self, CentralBlock, Centrality};
+use crate::graph::context::{self, Context, ContextCandidate, ContextOptions, ContextRole};
+use crate::graph::export::{CallGraph, ExportOptions};
+use crate::graph::tree::{self, CallTreeNode, Direction};
+use crate::symbols::index::SymbolIndex;
+use anyhow::Result;
+use rayon::prelude::*;
 use rusqlite::Connection;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 pub enum VectorStore {
     SQLiteStore(Connection),
 }
 
 use crate::embeddings::encoder::Embeddings;
-use crate::vector_store::sqlite::{ProjectInfo, SQLite};
+use crate::vector_store::index_cache::LoadedProject;
+use crate::vector_store::segments::Segments;
+use crate::vector_store::sqlite::{
+    ProjectInfo, ProjectSettings, SQLite, SearchFilter, StoredBlock,
+};
 
 static DB_PATH: &str = "db/blockoli.sqlite";
+static SEGMENTS_PATH: &str = "db/segments";
 
 impl VectorStore {
     /// Initializes a new SQLite-backed vector store.
@@ -19,6 +43,28 @@ impl VectorStore {
     /// A `VectorStore` enum with the `:SQLiteStore` variant containing the SQLite connection.
     pub fn init_sqlite() -> VectorStore {
         let connection = Connection::open(DB_PATH).unwrap();
+        SQLite::create_embedding_cache(&connection).unwrap();
+        SQLite::create_settings_table(&connection).unwrap();
+        SQLite::create_project_registry(&connection).unwrap();
+
+        for project_name in SQLite::list_projects(&connection).unwrap() {
+            SQLite::migrate_project_table(&connection, &project_name).unwrap();
+
+            let info = SQLite::get_project_info(&connection, &project_name).unwrap();
+            if info.is_some_and(|info| info.needs_reindex) {
+                eprintln!(
+                    "[!] {} was embedded by an older version of blockoli, regenerate its embeddings for accurate searches",
+                    project_name
+                );
+            }
+
+            if !SQLite::has_call_edges(&connection, &project_name).unwrap()
+                || !SQLite::has_centrality(&connection, &project_name).unwrap()
+            {
+                Self::index_calls(&connection, &project_name).unwrap();
+            }
+        }
+
         VectorStore::SQLiteStore(connection)
     }
 
@@ -36,6 +82,22 @@ impl VectorStore {
         }
     }
 
+    /// Checks that a new project can be created with a name. See `SQLite::check_project_name`.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` the project would be created in.
+    /// * `project_name` - The name of the new project.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing why the name can't be used.
+    pub async fn check_project_name(&self, project_name: &str) -> Result<()> {
+        match self {
+            VectorStore::SQLiteStore(_) => SQLite::check_project_name(project_name),
+        }
+    }
+
     /// Deletes a project from the vector store.
     ///
     /// # Arguments
@@ -46,6 +108,7 @@ impl VectorStore {
         match self {
             VectorStore::SQLiteStore(conn) => {
                 SQLite::delete_project(conn, project_name).unwrap();
+                Segments::delete(SEGMENTS_PATH, project_name).unwrap();
             }
         }
     }
@@ -68,6 +131,17 @@ impl VectorStore {
         }
     }
 
+    /// Lists the names of all projects in the vector store.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to list the projects of.
+    pub async fn list_projects(&self) -> Vec<String> {
+        match self {
+            VectorStore::SQLiteStore(conn) => SQLite::list_projects(conn).unwrap(),
+        }
+    }
+
     /// Retrieves information about a project from the vector store.
     ///
     /// # Arguments
@@ -94,7 +168,370 @@ impl VectorStore {
     pub async fn insert_blocks(&mut self, project_name: &str, blocks: Vec<EmbeddedBlock>) {
         match self {
             VectorStore::SQLiteStore(conn) => {
-                SQLite::insert_blocks(conn, project_name, blocks).unwrap();
+                SQLite::insert_blocks(conn, project_name, blocks, |generation, records| {
+                    Segments::append(SEGMENTS_PATH, project_name, generation, records)
+                })
+                .unwrap();
+
+                Self::index_calls(conn, project_name).unwrap();
+            }
+        }
+    }
+
+    /// Resolves the outgoing calls of every block of a project and stores them as call edges,
+    /// along with the centrality of every block they imply, replacing the previous ones. See
+    /// `calls::resolve_calls` and `centrality::compute_centrality`.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the blocks can't be read or the edges can't be stored.
+    fn index_calls(conn: &Connection, project_name: &str) -> Result<()> {
+        let blocks = SQLite::get_blocks(conn, project_name)?;
+        let edges = calls::resolve_calls(&blocks);
+        SQLite::set_call_edges(conn, project_name, &edges)?;
+
+        let ids: Vec<i64> = blocks.iter().map(|block| block.id).collect();
+        let centrality = centrality::compute_centrality(&ids, &edges);
+
+        SQLite::set_centrality(conn, project_name, &centrality)
+    }
+
+    /// Walks the call graph of a project from a function. See `tree::call_tree`.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to read the call edges from.
+    /// * `project_name` - The name of the project the function belongs to.
+    /// * `function` - The name of the function, optionally qualified with its class, e.g.
+    ///   `VectorStore::search`. Every function with the name is a root of the walk.
+    /// * `direction` - Whether to walk to the callers or the callees of the function.
+    /// * `depth` - The maximum number of calls to follow.
+    ///
+    /// # Returns
+    ///
+    /// A call tree per function with the name, or `None` if the project has no such function.
+    pub async fn call_tree(
+        &self,
+        project_name: &str,
+        function: &str,
+        direction: Direction,
+        depth: usize,
+    ) -> Option<Vec<CallTreeNode>> {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                let (class_name, function_name) = calls::split_call(function);
+                let roots =
+                    SQLite::find_function_ids(conn, project_name, function_name, class_name)
+                        .unwrap();
+                if roots.is_empty() {
+                    return None;
+                }
+
+                let mut trees = tree::call_tree(&roots, direction, depth, |id| match direction {
+                    Direction::Callers => SQLite::get_callers(conn, project_name, id),
+                    Direction::Callees => SQLite::get_callees(conn, project_name, id),
+                })
+                .unwrap();
+
+                let mut ids = HashSet::new();
+                tree::block_ids(&trees, &mut ids);
+                let ids: Vec<i64> = ids.into_iter().collect();
+                let blocks = SQLite::get_blocks_by_id(conn, project_name, &ids).unwrap();
+                tree::fill_blocks(&mut trees, &blocks);
+
+                Some(trees)
+            }
+        }
+    }
+
+    /// Builds the call graph of a project for export, optionally restricted to the neighbourhood
+    /// of a function and to a path prefix.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to read the blocks and call edges from.
+    /// * `project_name` - The name of the project.
+    /// * `options` - The function and path prefix to restrict the graph to.
+    ///
+    /// # Returns
+    ///
+    /// The call graph, or `None` if `options.function` names a function the project doesn't have.
+    pub async fn export_call_graph(
+        &self,
+        project_name: &str,
+        options: &ExportOptions,
+    ) -> Option<CallGraph> {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                let blocks = SQLite::get_blocks(conn, project_name).unwrap();
+                let edges = SQLite::get_resolved_call_edges(conn, project_name).unwrap();
+                let mut graph = CallGraph::build(&blocks, &edges);
+
+                if let Some(function) = &options.function {
+                    let (class_name, function_name) = calls::split_call(function);
+                    let roots =
+                        SQLite::find_function_ids(conn, project_name, function_name, class_name)
+                            .unwrap();
+                    if roots.is_empty() {
+                        return None;
+                    }
+
+                    graph.retain_neighbourhood(&roots, options.depth);
+                }
+
+                if let Some(path_prefix) = &options.path_prefix {
+                    graph.retain(|node| {
+                        node.file_path
+                            .as_ref()
+                            .is_some_and(|file_path| file_path.starts_with(path_prefix.as_str()))
+                    });
+                }
+
+                Some(graph)
+            }
+        }
+    }
+
+    /// Ranks the blocks of a project by their centrality in its call graph, most central first.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to read the centrality from.
+    /// * `project_name` - The name of the project.
+    /// * `limit` - The maximum number of blocks to return.
+    pub async fn central_blocks(&self, project_name: &str, limit: usize) -> Vec<CentralBlock> {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                let mut ranked: Vec<(i64, Centrality)> = SQLite::get_centrality(conn, project_name)
+                    .unwrap()
+                    .into_iter()
+                    .collect();
+                ranked.sort_by(|a, b| b.1.pagerank.total_cmp(&a.1.pagerank).then(a.0.cmp(&b.0)));
+                ranked.truncate(limit);
+
+                let ids: Vec<i64> = ranked.iter().map(|(id, _)| *id).collect();
+                let blocks = SQLite::get_blocks_by_id(conn, project_name, &ids).unwrap();
+
+                ranked
+                    .into_iter()
+                    .filter_map(|(id, centrality)| {
+                        let block = blocks.get(&id)?;
+
+                        Some(CentralBlock {
+                            id,
+                            node_key: block.block.node_key.to_owned(),
+                            function_name: block.block.function_name.to_owned(),
+                            class_name: block.block.class_name.to_owned(),
+                            file_path: block
+                                .location
+                                .as_ref()
+                                .map(|location| location.file_path.to_owned()),
+                            centrality,
+                        })
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Assembles a prompt-ready context for a query: searches the project, adds the callees,
+    /// callers and class of each hit from the call graph, and packs them into a token budget.
+    /// See `context::assemble`.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to search.
+    /// * `project_name` - The name of the project to search in.
+    /// * `project` - The project loaded by `load_project`.
+    /// * `search_code` - The code or natural-language query to assemble a context for.
+    /// * `filter` - Restricts the search hits to a subset of the project's blocks. Blocks added
+    ///   from the call graph are not filtered.
+    /// * `search_options` - The query type and ranking options of the search. The number of
+    ///   matches is taken from `options.hits`.
+    /// * `options` - The token budget and which blocks to add to each hit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the search fails. See `search`.
+    pub async fn assemble_context(
+        &self,
+        project_name: &str,
+        project: &LoadedProject,
+        search_code: String,
+        filter: &SearchFilter,
+        search_options: &SearchOptions,
+        options: &ContextOptions,
+    ) -> Result<Context> {
+        // The context has its own budget, counting the blocks added around the hits.
+        let search_options = SearchOptions {
+            matches: options.hits,
+            max_tokens: None,
+            ..search_options.clone()
+        };
+        let nearest = self
+            .search(
+                project_name,
+                project,
+                search_code,
+                None,
+                filter,
+                &search_options,
+            )
+            .await?;
+
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                let mut candidates = Vec::new();
+                let mut ids: Vec<i64> = Vec::new();
+
+                let hits = nearest.ids.iter().zip(&nearest.scores).enumerate();
+                for (hit, (id, score)) in hits {
+                    let mut add = |id: i64, role: ContextRole| {
+                        ids.push(id);
+                        candidates.push(ContextCandidate {
+                            id,
+                            role,
+                            hit,
+                            score: *score,
+                        });
+                    };
+
+                    add(*id, ContextRole::Hit);
+
+                    if options.callees {
+                        let callees = SQLite::get_callees(conn, project_name, *id).unwrap();
+                        for callee in callees
+                            .iter()
+                            .filter_map(|edge| edge.callee)
+                            .filter(|callee| callee != id)
+                            .take(options.neighbours)
+                        {
+                            add(callee, ContextRole::Callee);
+                        }
+                    }
+
+                    if options.callers {
+                        let callers = SQLite::get_callers(conn, project_name, *id).unwrap();
+                        for caller in callers
+                            .iter()
+                            .map(|edge| edge.caller)
+                            .filter(|caller| caller != id)
+                            .take(options.neighbours)
+                        {
+                            add(caller, ContextRole::Caller);
+                        }
+                    }
+
+                    if options.class_context {
+                        let block = SQLite::get_blocks_by_id(conn, project_name, &[*id]).unwrap();
+                        if let Some(class_name) = block
+                            .get(id)
+                            .and_then(|block| block.block.class_name.as_deref())
+                        {
+                            for class in
+                                SQLite::find_class_ids(conn, project_name, class_name).unwrap()
+                            {
+                                add(class, ContextRole::Class);
+                            }
+                        }
+                    }
+                }
+
+                ids.sort_unstable();
+                ids.dedup();
+                let blocks = SQLite::get_blocks_by_id(conn, project_name, &ids).unwrap();
+
+                Ok(context::assemble(candidates, &blocks, options.max_tokens))
+            }
+        }
+    }
+
+    /// Retrieves the direct callers and callees of a code block.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to read the call edges from.
+    /// * `project_name` - The name of the project the block belongs to.
+    /// * `block_id` - The row id of the block.
+    ///
+    /// # Returns
+    ///
+    /// The calls of the block, or `None` if the project has no block with this row id.
+    pub async fn get_calls(&self, project_name: &str, block_id: i64) -> Option<BlockCalls> {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                let exists = SQLite::get_contents(conn, project_name, &[block_id])
+                    .unwrap()
+                    .contains_key(&block_id);
+                if !exists {
+                    return None;
+                }
+
+                Some(BlockCalls {
+                    callers: SQLite::get_callers(conn, project_name, block_id).unwrap(),
+                    callees: SQLite::get_callees(conn, project_name, block_id).unwrap(),
+                })
+            }
+        }
+    }
+
+    /// Loads a project's vectors and indexes into memory for searching, as configured by its
+    /// settings. The result is meant to be kept in the `IndexCache`.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to load the project from.
+    /// * `project_name` - The name of the project to load.
+    ///
+    /// # Returns
+    ///
+    /// A `LoadedProject` that can be passed to `search`.
+    pub async fn load_project(&self, project_name: &str) -> LoadedProject {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                let settings = SQLite::get_project_settings(conn, project_name).unwrap();
+                let preprocess = SQLite::get_preprocess_config(conn, project_name).unwrap();
+                let segments = Self::open_segments(conn, project_name).unwrap();
+
+                let (index, quantized) = match (settings.quantization, settings.index) {
+                    (Quantization::None, IndexType::Flat) => (None, None),
+                    (Quantization::None, index_type) => (
+                        Some(ProjectIndex::build(segments.vectors(), index_type)),
+                        None,
+                    ),
+                    (quantization, _) => (
+                        None,
+                        Some(
+                            SQLite::get_quantized_index(conn, project_name, quantization, || {
+                                segments.vectors()
+                            })
+                            .unwrap(),
+                        ),
+                    ),
+                };
+
+                let named_vectors = SQLite::get_named_vectors(conn, project_name).unwrap();
+
+                let centrality = SQLite::get_centrality(conn, project_name)
+                    .unwrap()
+                    .into_iter()
+                    .map(|(id, centrality)| (id, centrality.score))
+                    .collect();
+
+                LoadedProject {
+                    settings,
+                    preprocess,
+                    segments,
+                    index,
+                    quantized,
+                    named_vectors,
+                    centrality,
+                }
             }
         }
     }
@@ -105,17 +542,776 @@ impl VectorStore {
     ///
     /// * `self` - The `VectorStore` to search.
     /// * `project_name` - The name of the project to search in.
-    /// * `search_code` - The code to search for matching blocks to.
+    /// * `project` - The project loaded by `load_project`.
+    /// * `search_code` - The code or natural-language query to search for matching blocks to.
+    /// * `weights` - If given, score blocks by a weighted combination of their named vectors,
+    ///   kept in the loaded project.
+    /// * `filter` - Restricts the search to a subset of the project's blocks. Filtered searches
+    ///   scan the matching blocks exactly instead of using the project's index.
+    /// * `options` - The query type, number of matches and ranking options of the search.
+    ///   The index and quantization are taken from the project's settings, and code queries are
+    ///   preprocessed the way the project's blocks were.
     ///
     /// # Returns
     ///
     /// A `NearestVectors` struct containing the most similar code block and a list of the nearest matching blocks.
-    pub async fn search(&self, project_name: &str, search_code: String) -> NearestVectors {
+    /// Both are empty if no block matches `filter`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the query can't be embedded or reranked, or if the database can't be read.
+    pub async fn search(
+        &self,
+        project_name: &str,
+        project: &LoadedProject,
+        search_code: String,
+        weights: Option<HashMap<VectorKind, f32>>,
+        filter: &SearchFilter,
+        options: &SearchOptions,
+    ) -> Result<NearestVectors> {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                let options = &SearchOptions {
+                    index: project.settings.index,
+                    preprocess: project.preprocess.clone(),
+                    ..options.clone()
+                };
+
+                let allowed = if filter.is_empty() {
+                    None
+                } else {
+                    Some(SQLite::filter_block_ids(conn, project_name, filter)?)
+                };
+
+                let mut nearest =
+                    Embeddings::search(search_code, options, |query, count| match weights {
+                        Some(weights) => {
+                            let blocks: Vec<&MultiVector> = project
+                                .named_vectors
+                                .iter()
+                                .filter(|block| {
+                                    allowed
+                                        .as_ref()
+                                        .is_none_or(|allowed| allowed.contains(&block.id))
+                                })
+                                .collect();
+
+                            let mut candidates = Embeddings::weighted_candidates(
+                                blocks,
+                                |id| project.segments.vector(id),
+                                query,
+                                &weights,
+                                count,
+                            );
+                            Self::fill_centrality(project, &mut candidates);
+                            Self::fill_code(conn, project_name, &mut candidates)?;
+
+                            Ok(candidates)
+                        }
+                        None => match &allowed {
+                            Some(allowed) => {
+                                let mut candidates =
+                                    Self::scan_candidates(project, allowed, query, count);
+                                Self::fill_code(conn, project_name, &mut candidates)?;
+
+                                Ok(candidates)
+                            }
+                            None => {
+                                Self::find_candidates(conn, project_name, project, query, count)
+                            }
+                        },
+                    })?;
+
+                Self::fill_locations(conn, project_name, &mut nearest)?;
+                Self::fill_tokens(conn, project_name, &mut nearest, options)?;
+
+                Ok(nearest)
+            }
+        }
+    }
+
+    /// Looks up the row id of a code block in a project by its node key.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to search.
+    /// * `project_name` - The name of the project the block belongs to.
+    /// * `node_key` - The node key of the block.
+    pub async fn find_block_id(&self, project_name: &str, node_key: &str) -> Option<i64> {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                SQLite::find_block_id(conn, project_name, node_key).unwrap()
+            }
+        }
+    }
+
+    /// Searches for code blocks similar to a block already stored in a project, using its
+    /// stored vector instead of embedding it again.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to search.
+    /// * `project_name` - The name of the project to search in.
+    /// * `project` - The project loaded by `load_project`.
+    /// * `block_id` - The row id of the source block.
+    /// * `exclude_source` - Whether to leave the source block itself out of the matches.
+    /// * `exclude_file` - Whether to leave every block of the source block's file out of the
+    ///   matches. Blocks that weren't located are never excluded by file.
+    /// * `options` - The number of matches and ranking options of the search. The source
+    ///   block's code is the query of any reranking.
+    ///
+    /// # Returns
+    ///
+    /// A `NearestVectors` struct with the closest matching blocks, or `None` if the project
+    /// has no block with the given row id. The matches are empty if every other block is
+    /// excluded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reranking fails or if the database can't be read.
+    pub async fn search_similar(
+        &self,
+        project_name: &str,
+        project: &LoadedProject,
+        block_id: i64,
+        exclude_source: bool,
+        exclude_file: bool,
+        options: &SearchOptions,
+    ) -> Result<Option<NearestVectors>> {
         match self {
             VectorStore::SQLiteStore(conn) => {
-                let code_vectors = SQLite::get_code_vectors(conn, project_name).unwrap();
+                let Some(point) = project.segments.vector(block_id) else {
+                    return Ok(None);
+                };
+                let Some(code) =
+                    SQLite::get_contents(conn, project_name, &[block_id])?.remove(&block_id)
+                else {
+                    return Ok(None);
+                };
+
+                let query = Vector {
+                    id: block_id,
+                    point,
+                    code,
+                };
+
+                let source_file = SQLite::get_locations(conn, project_name, &[block_id])?
+                    .remove(&block_id)
+                    .map(|location| location.file_path)
+                    .filter(|_| exclude_file);
+
+                let mut candidates = match &source_file {
+                    // Blocks of the same file can crowd out every other match, so the rest of
+                    // the project is scanned exactly.
+                    Some(file) => {
+                        let mut excluded = SQLite::filter_block_ids(
+                            conn,
+                            project_name,
+                            &SearchFilter {
+                                file: Some(file.to_owned()),
+                                ..SearchFilter::default()
+                            },
+                        )?;
+                        if !exclude_source {
+                            excluded.remove(&block_id);
+                        }
+
+                        let allowed: HashSet<i64> = project
+                            .segments
+                            .ids()
+                            .filter(|id| !excluded.contains(id))
+                            .collect();
+
+                        Self::scan_candidates(project, &allowed, &query, options.candidate_count())
+                    }
+                    None => {
+                        let count = options.candidate_count() + usize::from(exclude_source);
+                        Self::nearest_candidates(project, &query, count)
+                    }
+                };
+
+                if exclude_source {
+                    candidates.retain(|candidate| candidate.id != block_id);
+                }
+                Self::fill_code(conn, project_name, &mut candidates)?;
+
+                let mut nearest = Embeddings::rank(&query.code, candidates, options)?;
+                Self::fill_locations(conn, project_name, &mut nearest)?;
+                Self::fill_tokens(conn, project_name, &mut nearest, options)?;
 
-                Embeddings::search(code_vectors, search_code, 5).unwrap()
+                Ok(Some(nearest))
+            }
+        }
+    }
+
+    /// Retrieves the blocks of a project together with their whole-block vectors, as the input of
+    /// `duplicates::find_duplicates`, `projection::project` and `clusters::cluster_blocks`.
+    ///
+    /// The blocks are copied out so that these analyses can run without holding the vector
+    /// store lock.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to read the blocks from.
+    /// * `project_name` - The name of the project to read.
+    /// * `project` - The project loaded by `load_project`.
+    ///
+    /// # Returns
+    ///
+    /// Every block of the project that has a vector, with that vector.
+    pub async fn get_block_vectors(
+        &self,
+        project_name: &str,
+        project: &LoadedProject,
+    ) -> Vec<(StoredBlock, [f32; VECTOR_SIZE])> {
+        match self {
+            VectorStore::SQLiteStore(conn) => SQLite::get_blocks(conn, project_name)
+                .unwrap()
+                .into_iter()
+                .filter_map(|block| {
+                    let vector = project.segments.vector(block.id)?;
+                    Some((block, vector))
+                })
+                .collect(),
+        }
+    }
+
+    /// Reports the functions of a project that nothing calls. See `dead_code::find_dead_code`.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to analyse.
+    /// * `project_name` - The name of the project to analyse.
+    /// * `options` - The entry points and the kinds of functions to exclude.
+    pub async fn find_dead_code(
+        &self,
+        project_name: &str,
+        options: &DeadCodeOptions,
+    ) -> DeadCodeReport {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                let blocks = SQLite::get_blocks(conn, project_name).unwrap();
+                let edges = SQLite::get_resolved_call_edges(conn, project_name).unwrap();
+
+                dead_code::find_dead_code(&blocks, &edges, options)
+            }
+        }
+    }
+
+    /// Builds the index of the functions and classes of a project, for symbol search and
+    /// autocomplete. See `search::search_symbols` and `SymbolIndex::complete`.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to read the symbols from.
+    /// * `project_name` - The name of the project.
+    pub async fn load_symbols(&self, project_name: &str) -> SymbolIndex {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                SymbolIndex::build(SQLite::get_symbols(conn, project_name).unwrap())
+            }
+        }
+    }
+
+    /// Stores the clusters of a project computed by `clusters::cluster_blocks`, replacing any
+    /// previous clustering.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to store the clusters in.
+    /// * `project_name` - The name of the clustered project.
+    /// * `clustering` - The clusters and the cluster of each block.
+    pub async fn set_clusters(&self, project_name: &str, clustering: &Clustering) {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                SQLite::set_clusters(conn, project_name, clustering).unwrap();
+            }
+        }
+    }
+
+    /// Retrieves the stored clusters of a project.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to retrieve the clusters from.
+    /// * `project_name` - The name of the project to retrieve the clusters of.
+    ///
+    /// # Returns
+    ///
+    /// The clusters of the project, largest first, or an empty list if it wasn't clustered yet.
+    pub async fn get_clusters(&self, project_name: &str) -> Vec<Cluster> {
+        match self {
+            VectorStore::SQLiteStore(conn) => SQLite::get_clusters(conn, project_name).unwrap(),
+        }
+    }
+
+    /// Retrieves the code blocks of a cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to retrieve the blocks from.
+    /// * `project_name` - The name of the project the cluster belongs to.
+    /// * `cluster` - The id of the cluster.
+    pub async fn get_cluster_blocks(&self, project_name: &str, cluster: usize) -> Vec<StoredBlock> {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                SQLite::get_cluster_blocks(conn, project_name, cluster).unwrap()
+            }
+        }
+    }
+
+    /// Retrieves the cluster of each block of a project, by row id, to tag projected points with.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to read the clusters from.
+    /// * `project_name` - The name of the project.
+    ///
+    /// # Returns
+    ///
+    /// The cluster of every clustered block, empty if the project was never clustered.
+    pub async fn get_block_clusters(&self, project_name: &str) -> HashMap<i64, usize> {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                SQLite::get_block_clusters(conn, project_name).unwrap()
+            }
+        }
+    }
+
+    /// Searches several projects at once, merging their matches by score.
+    ///
+    /// The loaded projects are searched in parallel, each with the query embedded the way its
+    /// blocks were preprocessed. The merged candidates are then ranked together, so reranking
+    /// and diversification apply across projects.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to search.
+    /// * `projects` - The names of the projects to search, with the projects loaded by `load_project`.
+    /// * `queries` - The embedded query of each project, in the same order as `projects`. See
+    ///   `Embeddings::embed_queries`.
+    /// * `search_code` - The code or natural-language query to search for matching blocks to.
+    /// * `options` - The number of matches and ranking options of the search.
+    ///
+    /// # Returns
+    ///
+    /// The best matches over all projects, each tagged with its project, best first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reranking fails or if the database can't be read.
+    pub async fn search_projects(
+        &self,
+        projects: &[(String, Arc<LoadedProject>)],
+        queries: &[Vector],
+        search_code: String,
+        options: &SearchOptions,
+    ) -> Result<Vec<ProjectMatch>> {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                let count = options.candidate_count();
+
+                let nearest: Vec<Vec<Candidate>> = projects
+                    .par_iter()
+                    .zip(queries)
+                    .map(|((_, project), query)| Self::nearest_candidates(project, query, count))
+                    .collect();
+
+                // Row ids are only unique within a project, so the merged candidates are renumbered
+                // by their position in `sources` while they are ranked.
+                let mut sources: Vec<(&str, i64)> = Vec::new();
+                let mut candidates: Vec<Candidate> = Vec::new();
+
+                for ((project_name, _), mut project_candidates) in projects.iter().zip(nearest) {
+                    Self::fill_code(conn, project_name, &mut project_candidates)?;
+
+                    for mut candidate in project_candidates {
+                        sources.push((project_name, candidate.id));
+                        candidate.id = sources.len() as i64 - 1;
+                        candidates.push(candidate);
+                    }
+                }
+
+                candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+                candidates.truncate(count);
+
+                let ranked = Embeddings::rank_candidates(&search_code, candidates, options)?;
+
+                // The token counts and locations of the ranked matches are fetched with one query
+                // per project rather than per match.
+                let mut ids: HashMap<&str, Vec<i64>> = HashMap::new();
+                for candidate in &ranked {
+                    let (project_name, id) = sources[candidate.id as usize];
+                    ids.entry(project_name).or_default().push(id);
+                }
+
+                let mut token_counts: HashMap<&str, HashMap<i64, usize>> = HashMap::new();
+                let mut locations: HashMap<&str, HashMap<i64, BlockLocation>> = HashMap::new();
+                for (&project_name, ids) in &ids {
+                    token_counts.insert(
+                        project_name,
+                        SQLite::get_token_counts(conn, project_name, ids)?,
+                    );
+                    locations.insert(
+                        project_name,
+                        SQLite::get_locations(conn, project_name, ids)?,
+                    );
+                }
+
+                let mut remaining = options.max_tokens.unwrap_or(usize::MAX);
+
+                Ok(ranked
+                    .into_iter()
+                    .filter_map(|candidate| {
+                        let (project_name, id) = sources[candidate.id as usize];
+                        let tokens = token_counts[project_name]
+                            .get(&id)
+                            .copied()
+                            .unwrap_or_default();
+
+                        // Matches that don't fit the remaining budget are skipped, as in `search`.
+                        if tokens > remaining {
+                            return None;
+                        }
+                        remaining -= tokens;
+
+                        let location = locations
+                            .get_mut(project_name)
+                            .and_then(|locations| locations.remove(&id));
+
+                        Some(ProjectMatch {
+                            project: project_name.to_owned(),
+                            code: candidate.code,
+                            location,
+                            score: candidate.score,
+                            tokens,
+                        })
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    /// Finds the nearest candidates to a query in a loaded project. Only the code of the
+    /// returned candidates is fetched from SQLite.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to search in.
+    /// * `project` - The loaded project.
+    /// * `query` - The embedded search query.
+    /// * `count` - The number of candidates to return.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the code of the candidates can't be loaded.
+    fn find_candidates(
+        conn: &Connection,
+        project_name: &str,
+        project: &LoadedProject,
+        query: &Vector,
+        count: usize,
+    ) -> Result<Vec<Candidate>> {
+        let mut candidates = Self::nearest_candidates(project, query, count);
+        Self::fill_code(conn, project_name, &mut candidates)?;
+
+        Ok(candidates)
+    }
+
+    /// Finds the nearest candidates to a query in a loaded project, without their code.
+    ///
+    /// # Arguments
+    ///
+    /// * `project` - The loaded project.
+    /// * `query` - The embedded search query.
+    /// * `count` - The number of candidates to return.
+    fn nearest_candidates(project: &LoadedProject, query: &Vector, count: usize) -> Vec<Candidate> {
+        let segments = &project.segments;
+        if segments.is_empty() {
+            return Vec::new();
+        }
+
+        let mut candidates = match (&project.quantized, &project.index) {
+            (Some(quantized), _) => {
+                let shortlist: Vec<Vector> = quantized
+                    .search(&query.point, count * project.settings.rescore.max(1))
+                    .into_iter()
+                    .filter_map(|(id, _)| {
+                        Some(Vector {
+                            id,
+                            point: segments.vector(id)?,
+                            code: String::new(),
+                        })
+                    })
+                    .collect();
+
+                index::rescore(query, shortlist, count)
+            }
+            (None, Some(index)) => index.search(query, count),
+            (None, None) => segments
+                .search(&query.point, count)
+                .into_iter()
+                .filter_map(|(id, score)| {
+                    Some(Candidate {
+                        id,
+                        code: String::new(),
+                        point: segments.vector(id)?.to_vec(),
+                        score,
+                        centrality: 0.0,
+                    })
+                })
+                .collect(),
+        };
+
+        Self::fill_centrality(project, &mut candidates);
+        candidates
+    }
+
+    /// Finds the nearest candidates to a query among a subset of a loaded project's blocks by
+    /// scanning their vectors, without their code.
+    ///
+    /// # Arguments
+    ///
+    /// * `project` - The loaded project.
+    /// * `ids` - The row ids of the blocks to scan.
+    /// * `query` - The embedded search query.
+    /// * `count` - The number of candidates to return.
+    fn scan_candidates(
+        project: &LoadedProject,
+        ids: &HashSet<i64>,
+        query: &Vector,
+        count: usize,
+    ) -> Vec<Candidate> {
+        let vectors: Vec<Vector> = ids
+            .iter()
+            .filter_map(|id| {
+                Some(Vector {
+                    id: *id,
+                    point: project.segments.vector(*id)?,
+                    code: String::new(),
+                })
+            })
+            .collect();
+
+        let mut candidates = index::rescore(query, vectors, count);
+        Self::fill_centrality(project, &mut candidates);
+
+        candidates
+    }
+
+    /// Fills in the centrality of candidates from a loaded project.
+    fn fill_centrality(project: &LoadedProject, candidates: &mut [Candidate]) {
+        for candidate in candidates.iter_mut() {
+            candidate.centrality = project
+                .centrality
+                .get(&candidate.id)
+                .copied()
+                .unwrap_or_default();
+        }
+    }
+
+    /// Fetches the code of candidates from SQLite by their row ids.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the candidates belong to.
+    /// * `candidates` - The candidates to fill in the code of.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    fn fill_code(
+        conn: &Connection,
+        project_name: &str,
+        candidates: &mut [Candidate],
+    ) -> Result<()> {
+        let ids: Vec<i64> = candidates.iter().map(|candidate| candidate.id).collect();
+        let mut contents = SQLite::get_contents(conn, project_name, &ids)?;
+
+        for candidate in candidates.iter_mut() {
+            candidate.code = contents.remove(&candidate.id).unwrap_or_default();
+        }
+
+        Ok(())
+    }
+
+    /// Loads the locations of the matches of a search from SQLite.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the matches belong to.
+    /// * `nearest` - The matches whose locations to load.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    fn fill_locations(
+        conn: &Connection,
+        project_name: &str,
+        nearest: &mut NearestVectors,
+    ) -> Result<()> {
+        let mut locations = SQLite::get_locations(conn, project_name, &nearest.ids)?;
+
+        nearest.locations = nearest.ids.iter().map(|id| locations.remove(id)).collect();
+
+        Ok(())
+    }
+
+    /// Loads the token counts of the matches of a search from SQLite, and trims the matches to
+    /// the token budget of the search, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project the matches belong to.
+    /// * `nearest` - The matches whose token counts to load.
+    /// * `options` - The options of the search, with its token budget.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SQL query fails to execute.
+    fn fill_tokens(
+        conn: &Connection,
+        project_name: &str,
+        nearest: &mut NearestVectors,
+        options: &SearchOptions,
+    ) -> Result<()> {
+        let token_counts = SQLite::get_token_counts(conn, project_name, &nearest.ids)?;
+
+        nearest.tokens = nearest
+            .ids
+            .iter()
+            .map(|id| token_counts.get(id).copied().unwrap_or_default())
+            .collect();
+
+        if let Some(max_tokens) = options.max_tokens {
+            nearest.fit_budget(max_tokens);
+        }
+
+        Ok(())
+    }
+
+    /// Memory-maps the vector segments of a project, rebuilding them first if they weren't
+    /// written at the project's current generation.
+    ///
+    /// The rebuilt segments take the vectors of blocks indexed before segments existed from
+    /// the project table, and keep the vectors of the other blocks. Records of blocks that are
+    /// no longer in the project are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `conn` - The SQLite database connection.
+    /// * `project_name` - The name of the project to open the segments of.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the segments can't be read or rebuilt.
+    fn open_segments(conn: &Connection, project_name: &str) -> Result<Segments> {
+        let segments = Segments::open(SEGMENTS_PATH, project_name)?;
+        let generation = SQLite::get_generation(conn, project_name)?;
+
+        if segments.generation() == Some(generation) {
+            return Ok(segments);
+        }
+
+        eprintln!("\n[-] Rebuilding vector segments of {}", project_name);
+
+        let mut missing = 0;
+        let vectors: Vec<(i64, Vec<f32>)> = SQLite::get_legacy_vectors(conn, project_name)?
+            .into_iter()
+            .filter_map(|(id, legacy)| {
+                let vector = legacy.or_else(|| segments.vector(id).map(|vector| vector.to_vec()));
+                if vector.is_none() {
+                    missing += 1;
+                }
+                Some((id, vector?))
+            })
+            .collect();
+
+        if missing > 0 {
+            eprintln!(
+                "[!] {} blocks of {} have no vector, regenerate its embeddings to search them",
+                missing, project_name
+            );
+        }
+
+        drop(segments);
+        Segments::delete(SEGMENTS_PATH, project_name)?;
+
+        let records: Vec<(i64, &[f32])> = vectors
+            .iter()
+            .map(|(id, vector)| (*id, vector.as_slice()))
+            .collect();
+        Segments::append(SEGMENTS_PATH, project_name, generation, &records)?;
+        SQLite::clear_legacy_vectors(conn, project_name)?;
+
+        Segments::open(SEGMENTS_PATH, project_name)
+    }
+
+    /// Retrieves the settings of a project.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to retrieve the settings from.
+    /// * `project_name` - The name of the project to retrieve the settings of.
+    ///
+    /// # Returns
+    ///
+    /// The project's `ProjectSettings`, or the defaults if none were stored.
+    pub async fn get_project_settings(&self, project_name: &str) -> ProjectSettings {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                SQLite::get_project_settings(conn, project_name).unwrap()
+            }
+        }
+    }
+
+    /// Stores the settings of a project.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to store the settings in.
+    /// * `project_name` - The name of the project to store the settings of.
+    /// * `settings` - The settings to store.
+    pub async fn set_project_settings(&self, project_name: &str, settings: &ProjectSettings) {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                SQLite::set_project_settings(conn, project_name, settings).unwrap();
+            }
+        }
+    }
+
+    /// Stores the preprocessing a project's blocks were embedded with, for its code queries.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` to store the config in.
+    /// * `project_name` - The name of the project.
+    /// * `config` - The preprocessing the blocks were embedded with.
+    pub async fn set_preprocess_config(&self, project_name: &str, config: &PreprocessConfig) {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                SQLite::set_preprocess_config(conn, project_name, config).unwrap();
+            }
+        }
+    }
+
+    /// Retrieves the whole-block vectors of a project, as the input of `index::measure_recall`.
+    ///
+    /// The vectors are copied out so that the recall can be measured without holding the
+    /// vector store lock.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` holding the project.
+    /// * `project_name` - The name of the project.
+    pub async fn get_vectors(&self, project_name: &str) -> Vec<Vector> {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                Self::open_segments(conn, project_name).unwrap().vectors()
             }
         }
     }
@@ -129,8 +1325,8 @@ impl VectorStore {
     ///
     /// # Returns  
     ///
-    /// A list of `Block` structs representing the code blocks that are non-empty functions.
-    pub async fn get_all_function_blocks(&self, project_name: &str) -> Vec<asterisk::block::Block> {
+    /// A list of `StoredBlock` structs representing the code blocks that are non-empty functions.
+    pub async fn get_all_function_blocks(&self, project_name: &str) -> Vec<StoredBlock> {
         match self {
             VectorStore::SQLiteStore(conn) => {
                 SQLite::get_all_function_blocks(conn, project_name).unwrap()
@@ -148,12 +1344,12 @@ impl VectorStore {
     ///
     /// # Returns
     ///
-    /// A list of `Block` structs representing the code blocks that match the query and are non-empty functions.
+    /// A list of `StoredBlock` structs representing the code blocks that match the query and are non-empty functions.
     pub async fn search_from_function_blocks(
         &self,
         project_name: &str,
         search_code: String,
-    ) -> Vec<asterisk::block::Block> {
+    ) -> Vec<StoredBlock> {
         match self {
             VectorStore::SQLiteStore(conn) => {
                 SQLite::search_from_function_blocks(conn, project_name, &search_code).unwrap()
@@ -171,16 +1367,72 @@ impl VectorStore {
     ///
     /// # Returns
     ///
-    /// A list of `Block` structs representing the code blocks with the given function name.
+    /// A list of `StoredBlock` structs representing the code blocks with the given function name.
     pub async fn search_by_function_name(
         &self,
         project_name: &str,
         function_name: String,
-    ) -> Vec<asterisk::block::Block> {
+    ) -> Vec<StoredBlock> {
         match self {
             VectorStore::SQLiteStore(conn) => {
                 SQLite::search_by_function_name(conn, project_name, &function_name).unwrap()
             }
         }
     }
+
+    /// Retrieves hit-rate statistics for the embedding cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` holding the cache.
+    ///
+    /// # Returns
+    ///
+    /// A `CacheStats` struct with the number of cached entries and the hit rate since startup.
+    pub async fn embedding_cache_stats(&self) -> CacheStats {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                CacheStats::snapshot(SQLite::count_cached_embeddings(conn).unwrap())
+            }
+        }
+    }
+
+    /// Evicts entries from the embedding cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The `VectorStore` holding the cache.
+    /// * `model` - Only evict entries generated with this model, if given.
+    /// * `keep` - Keep this many of the most recently used entries, if given.
+    ///
+    /// # Returns
+    ///
+    /// The number of evicted entries.
+    pub async fn evict_embedding_cache(&self, model: Option<&str>, keep: Option<usize>) -> usize {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                SQLite::evict_embedding_cache(conn, model, keep).unwrap()
+            }
+        }
+    }
+}
+
+impl EmbeddingCache for VectorStore {
+    fn get_cached_embeddings(
+        &self,
+        model: &str,
+        hashes: &[String],
+    ) -> Result<HashMap<String, Vec<f32>>> {
+        match self {
+            VectorStore::SQLiteStore(conn) => SQLite::get_cached_embeddings(conn, model, hashes),
+        }
+    }
+
+    fn insert_cached_embeddings(&self, model: &str, entries: &[(String, Vec<f32>)]) -> Result<()> {
+        match self {
+            VectorStore::SQLiteStore(conn) => {
+                SQLite::insert_cached_embeddings(conn, model, entries)
+            }
+        }
+    }
 }